@@ -32,7 +32,7 @@ fn test_stella_population_example() {
     let res = model.simulate(100.0);
 
     res.print_summary();
-    res.print_detailed(&["population"]);
+    res.print_detailed(&["population", "births", "deaths"]);
 }
 
 fn main() {
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use arrow_array::{Array, ArrayRef, Float64Array, RecordBatch, UInt64Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+
+use crate::SimulationResult;
+
+impl SimulationResult {
+    /// The results as an Arrow record batch: a `time` column and one
+    /// `Float64` column per stock, auxiliary and flow, in the same order as
+    /// CSV files.
+    pub fn to_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        Self::runs_to_record_batch(std::slice::from_ref(self), false)
+    }
+
+    /// Several runs, such as a Monte Carlo batch, as one record batch with
+    /// the runs' rows one after another.
+    ///
+    /// With `run_ids`, a `run` column first holds each row's index into
+    /// `runs`. Variables missing from a run are null in its rows.
+    pub fn runs_to_record_batch(
+        runs: &[SimulationResult],
+        run_ids: bool,
+    ) -> Result<RecordBatch, ArrowError> {
+        let mut variables: Vec<&String> = Vec::new();
+        for run in runs {
+            for id in run.variable_ids() {
+                if !variables.contains(&id) {
+                    variables.push(id);
+                }
+            }
+        }
+
+        let mut fields = Vec::new();
+        let mut columns: Vec<ArrayRef> = Vec::new();
+        if run_ids {
+            let ids = runs
+                .iter()
+                .enumerate()
+                .flat_map(|(i, run)| std::iter::repeat_n(i as u64, run.time_series.len()));
+            fields.push(Field::new("run", DataType::UInt64, false));
+            columns.push(Arc::new(UInt64Array::from_iter_values(ids)));
+        }
+
+        let times = runs.iter().flat_map(|run| run.time_series.iter().copied());
+        fields.push(Field::new("time", DataType::Float64, false));
+        columns.push(Arc::new(Float64Array::from_iter_values(times)));
+
+        for id in variables {
+            let values = runs.iter().flat_map(|run| {
+                let values = run.get(id);
+                (0..run.time_series.len())
+                    .map(move |i| values.and_then(|values| values.get(i).copied()))
+            });
+            let array = Float64Array::from_iter(values);
+            fields.push(Field::new(id, DataType::Float64, array.null_count() > 0));
+            columns.push(Arc::new(array));
+        }
+
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_files {
+    use std::io::Write;
+
+    use parquet::arrow::ArrowWriter;
+    use parquet::errors::ParquetError;
+
+    use crate::SimulationResult;
+
+    impl SimulationResult {
+        /// Writes the results as a Parquet file, with the columns of
+        /// `to_record_batch`.
+        pub fn write_parquet<W: Write + Send>(&self, writer: W) -> Result<(), ParquetError> {
+            write(writer, &self.to_record_batch()?)
+        }
+
+        /// Writes several runs as one Parquet file, with the columns of
+        /// `runs_to_record_batch` and a `run` column.
+        pub fn write_runs_parquet<W: Write + Send>(
+            runs: &[SimulationResult],
+            writer: W,
+        ) -> Result<(), ParquetError> {
+            write(writer, &Self::runs_to_record_batch(runs, true)?)
+        }
+    }
+
+    fn write<W: Write + Send>(
+        writer: W,
+        batch: &arrow_array::RecordBatch,
+    ) -> Result<(), ParquetError> {
+        let mut writer = ArrowWriter::try_new(writer, batch.schema(), None)?;
+        writer.write(batch)?;
+        writer.close()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,574 @@
+//! Compilation of a model into an index-based form for simulation.
+//!
+//! Before a run, every stock, auxiliary and flow ID is resolved to a slot in
+//! one flat vector of values, and equations to `Code` that reads those
+//! slots. Evaluating the model at each step then works on plain vectors
+//! without looking up, hashing or cloning IDs.
+
+use std::collections::HashMap;
+use std::mem;
+
+use crate::delay::{self, History};
+use crate::expr::{BinaryOp, Expr, Function, UnaryOp};
+use crate::lookup::CompiledTable;
+use crate::{Element, EvalError, FlowFunction, Model, SimulationResult, SystemState};
+
+/// An equation with its IDs resolved to slots and lookup tables to indices.
+#[derive(Debug, Clone)]
+enum Code {
+    Number(f64),
+    Slot(usize),
+    Time,
+    Dt,
+    Unary(UnaryOp, Box<Code>),
+    Binary(BinaryOp, Box<Code>, Box<Code>),
+    If(Box<Code>, Box<Code>, Box<Code>),
+    Call(Function, Vec<Code>),
+    Lookup(usize, Box<Code>),
+    /// `slope * stock + intercept`, with 0 for a missing stock
+    Linear {
+        slope: f64,
+        intercept: f64,
+        stock: Option<usize>,
+    },
+    /// A lookup of a table that does not exist, which fails after its input
+    /// is evaluated
+    UnknownLookup(String, Box<Code>),
+    /// Fails when evaluated
+    Fail(EvalError),
+}
+
+/// What equations read while being evaluated.
+struct Frame<'a> {
+    slots: &'a [f64],
+    tables: &'a [CompiledTable],
+    time: f64,
+    dt: f64,
+}
+
+impl Code {
+    /// Evaluates the code with the same results and errors as evaluating
+    /// the original equation against a `SystemState`.
+    fn eval(&self, frame: &Frame) -> Result<f64, EvalError> {
+        match self {
+            Code::Number(value) => Ok(*value),
+            Code::Slot(slot) => Ok(frame.slots[*slot]),
+            Code::Time => Ok(frame.time),
+            Code::Dt => Ok(frame.dt),
+            Code::Unary(op, operand) => Ok(op.apply(operand.eval(frame)?)),
+            Code::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(frame)?;
+                if let Some(value) = op.short_circuit(lhs) {
+                    return Ok(value);
+                }
+                Ok(op.apply(lhs, rhs.eval(frame)?))
+            }
+            Code::If(condition, then, otherwise) => {
+                if condition.eval(frame)? != 0.0 {
+                    then.eval(frame)
+                } else {
+                    otherwise.eval(frame)
+                }
+            }
+            Code::Call(function, args) => {
+                // Most functions take few arguments, which fit on the stack
+                let mut buffer = [0.0; 4];
+                if args.len() <= buffer.len() {
+                    for (value, arg) in buffer.iter_mut().zip(args) {
+                        *value = arg.eval(frame)?;
+                    }
+                    Ok(function.apply(&buffer[..args.len()], frame.time, frame.dt))
+                } else {
+                    let values = args
+                        .iter()
+                        .map(|arg| arg.eval(frame))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Ok(function.apply(&values, frame.time, frame.dt))
+                }
+            }
+            Code::Lookup(table, input) => frame.tables[*table].evaluate(input.eval(frame)?),
+            Code::Linear {
+                slope,
+                intercept,
+                stock,
+            } => {
+                let input = stock.map_or(0.0, |stock| frame.slots[stock]);
+                Ok(slope * input + intercept)
+            }
+            Code::UnknownLookup(table, input) => {
+                input.eval(frame)?;
+                Err(EvalError::UnknownLookup(table.clone()))
+            }
+            Code::Fail(error) => Err(error.clone()),
+        }
+    }
+}
+
+/// Resolves IDs while compiling equations.
+struct Compiler<'a> {
+    stocks: &'a HashMap<String, usize>,
+    variables: &'a HashMap<String, usize>,
+    lookups: &'a HashMap<String, usize>,
+    tables: &'a mut Vec<CompiledTable>,
+}
+
+impl Compiler<'_> {
+    fn function(&mut self, function: &FlowFunction) -> Code {
+        match function {
+            FlowFunction::Constant(value) => Code::Number(*value),
+            FlowFunction::Linear {
+                slope,
+                intercept,
+                input_stock,
+            } => Code::Linear {
+                slope: *slope,
+                intercept: *intercept,
+                stock: self.stocks.get(input_stock).copied(),
+            },
+            FlowFunction::Expression(expr) => self.expr(expr),
+            FlowFunction::Lookup { table, input } => {
+                let input = self.expr(input);
+                self.tables.push(CompiledTable::new(table));
+                Code::Lookup(self.tables.len() - 1, Box::new(input))
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Code {
+        match expr {
+            Expr::Number(value) => Code::Number(*value),
+            Expr::Variable(id) => match self.stocks.get(id).or(self.variables.get(id)) {
+                Some(&slot) => Code::Slot(slot),
+                None => Code::Fail(EvalError::UnknownVariable(id.clone())),
+            },
+            Expr::Time => Code::Time,
+            Expr::Dt => Code::Dt,
+            Expr::Unary(op, operand) => Code::Unary(*op, Box::new(self.expr(operand))),
+            Expr::Binary(op, lhs, rhs) => {
+                Code::Binary(*op, Box::new(self.expr(lhs)), Box::new(self.expr(rhs)))
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => Code::If(
+                Box::new(self.expr(condition)),
+                Box::new(self.expr(then)),
+                Box::new(self.expr(otherwise)),
+            ),
+            Expr::Call(function, _) if function.is_stateful() => {
+                Code::Fail(EvalError::StatefulFunction(*function))
+            }
+            Expr::Call(function, args) => {
+                Code::Call(*function, args.iter().map(|arg| self.expr(arg)).collect())
+            }
+            Expr::Lookup(table, input) => {
+                let input = Box::new(self.expr(input));
+                match self.lookups.get(table) {
+                    Some(&index) => Code::Lookup(index, input),
+                    None => Code::UnknownLookup(table.clone(), input),
+                }
+            }
+        }
+    }
+}
+
+/// An auxiliary or flow, compiled.
+struct CompiledElement {
+    slot: usize,
+    code: Code,
+    /// Indices of the stocks a flow drains and fills
+    from: Option<usize>,
+    to: Option<usize>,
+    /// Factor converting a flow rate to stock units per model time unit
+    conversion: f64,
+}
+
+/// A fixed delay, whose output is read from the history of its input.
+struct FixedDelay {
+    id: String,
+    slot: usize,
+    history: History,
+    input: Code,
+}
+
+/// Evaluates the auxiliaries and flows of a model during a simulation run.
+///
+/// Integrators work on plain vectors of stock values, in the order of
+/// `stock_ids`. The evaluator copies them into the first slots of its
+/// values, calculates the other slots in evaluation order and returns the
+/// net rate of change of each stock.
+pub(crate) struct Evaluator {
+    pub(crate) stock_ids: Vec<String>,
+    /// Minimum and maximum of each stock, in `stock_ids` order
+    bounds: Vec<(Option<f64>, Option<f64>)>,
+    /// IDs of the slots: the stocks, then computed variables
+    ids: Vec<String>,
+    /// Stock values, then the values of computed variables
+    slots: Vec<f64>,
+    /// Whether each slot has a value, which computed variables only get
+    /// once calculated
+    defined: Vec<bool>,
+    tables: Vec<CompiledTable>,
+    elements: Vec<CompiledElement>,
+    fixed_delays: Vec<FixedDelay>,
+    /// Time of the last evaluation
+    time: f64,
+    dt: f64,
+    /// Slots of the stocks, auxiliaries and flows in results, and their
+    /// recorded values
+    recorded: Vec<(usize, Vec<f64>)>,
+    recorded_stocks: usize,
+    recorded_auxiliaries: usize,
+    /// Recorded net rates of change of the stocks in results, if the model
+    /// records them
+    net_flows: Vec<Vec<f64>>,
+    times: Vec<f64>,
+    /// First evaluation error, reported after the integrator step
+    pub(crate) error: Option<EvalError>,
+    pub(crate) evaluations: usize,
+}
+
+impl Evaluator {
+    pub(crate) fn new(model: &Model, state: &SystemState) -> Self {
+        let stock_ids: Vec<String> = state.stocks.keys().cloned().collect();
+        let bounds = stock_ids
+            .iter()
+            .map(|id| (state.stocks[id].min_value, state.stocks[id].max_value))
+            .collect();
+        let mut ids = stock_ids.clone();
+        let mut slots: Vec<f64> = stock_ids
+            .iter()
+            .map(|id| state.stocks[id].current_value)
+            .collect();
+        let stocks: HashMap<String, usize> = stock_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.clone(), i))
+            .collect();
+
+        // Variables kept from earlier runs, then those calculated in this one
+        let order = model.evaluation_order();
+        let mut variables = HashMap::new();
+        let mut defined = vec![true; slots.len()];
+        let mut add_variable = |id: &String, value: Option<f64>| {
+            if !variables.contains_key(id) {
+                variables.insert(id.clone(), ids.len());
+                ids.push(id.clone());
+                slots.push(value.unwrap_or(0.0));
+                defined.push(value.is_some());
+            }
+        };
+        for (id, value) in &state.variables {
+            add_variable(id, Some(*value));
+        }
+        for element in &order {
+            add_variable(element.id(), None);
+        }
+        for id in state.histories.keys() {
+            add_variable(id, None);
+        }
+
+        let mut tables: Vec<CompiledTable> = Vec::new();
+        let mut lookups = HashMap::new();
+        for (id, table) in &state.lookups {
+            lookups.insert(id.clone(), tables.len());
+            tables.push(CompiledTable::new(table));
+        }
+
+        // Fixed delay outputs are set at the start of every evaluation, and
+        // the model has no circular definitions, so every element reads
+        // values already calculated in the same evaluation
+        let mut elements = Vec::new();
+        for element in &order {
+            let (function, from, to, conversion) = match element {
+                Element::Auxiliary(auxiliary) => (&auxiliary.function, None, None, 1.0),
+                Element::Flow(flow) => (
+                    &flow.rate_function,
+                    flow.from_stock
+                        .as_ref()
+                        .and_then(|id| stocks.get(id).copied()),
+                    flow.to_stock
+                        .as_ref()
+                        .and_then(|id| stocks.get(id).copied()),
+                    model.rate_conversion(flow).unwrap_or(1.0),
+                ),
+            };
+            let mut compiler = Compiler {
+                stocks: &stocks,
+                variables: &variables,
+                lookups: &lookups,
+                tables: &mut tables,
+            };
+            let code = compiler.function(function);
+            let slot = variables[element.id()];
+            elements.push(CompiledElement {
+                slot,
+                code,
+                from,
+                to,
+                conversion,
+            });
+        }
+
+        // Inputs are recorded after a complete evaluation
+        let fixed_delays = state
+            .histories
+            .iter()
+            .map(|(id, history)| {
+                let mut compiler = Compiler {
+                    stocks: &stocks,
+                    variables: &variables,
+                    lookups: &lookups,
+                    tables: &mut tables,
+                };
+                FixedDelay {
+                    id: id.clone(),
+                    slot: variables[id],
+                    input: compiler.expr(&history.input),
+                    history: history.clone(),
+                }
+            })
+            .collect();
+
+        let recorded_stocks: Vec<usize> = stock_ids
+            .iter()
+            .enumerate()
+            .filter(|(_, id)| model.record_internal || !delay::is_internal(id))
+            .map(|(i, _)| i)
+            .collect();
+        let recorded_auxiliaries: Vec<usize> =
+            model.auxiliaries.keys().map(|id| variables[id]).collect();
+        let recorded_flows = model
+            .flows
+            .keys()
+            .filter(|id| model.record_internal || !delay::is_internal(id))
+            .map(|id| variables[id]);
+        let recorded = recorded_stocks
+            .iter()
+            .chain(&recorded_auxiliaries)
+            .copied()
+            .chain(recorded_flows)
+            .map(|slot| (slot, Vec::new()))
+            .collect();
+
+        Self {
+            stock_ids,
+            bounds,
+            ids,
+            slots,
+            defined,
+            tables,
+            elements,
+            fixed_delays,
+            time: state.time,
+            dt: model.time_step,
+            recorded,
+            recorded_stocks: recorded_stocks.len(),
+            recorded_auxiliaries: recorded_auxiliaries.len(),
+            net_flows: if model.record_net_flows {
+                vec![Vec::new(); recorded_stocks.len()]
+            } else {
+                Vec::new()
+            },
+            times: Vec::new(),
+            error: None,
+            evaluations: 0,
+        }
+    }
+
+    /// Current values of the stocks in `stock_ids` order.
+    pub(crate) fn values(&self) -> Vec<f64> {
+        self.slots[..self.stock_ids.len()].to_vec()
+    }
+
+    /// Restricts values in `stock_ids` order to the bounds of their stocks,
+    /// and reports whether any value changed.
+    pub(crate) fn clamp(&self, values: &mut [f64]) -> bool {
+        let mut changed = false;
+        for (value, (min, max)) in values.iter_mut().zip(&self.bounds) {
+            let mut clamped = *value;
+            if let Some(min) = min {
+                clamped = clamped.max(*min);
+            }
+            if let Some(max) = max {
+                clamped = clamped.min(*max);
+            }
+            changed |= clamped != *value;
+            *value = clamped;
+        }
+        changed
+    }
+
+    /// Calculates the net rate of change of each stock at the given time
+    /// and stock values.
+    ///
+    /// Evaluation errors are stored and all derivatives are reported as
+    /// zero, so the integrator can finish its step before `check` is called.
+    pub(crate) fn derivatives(&mut self, time: f64, values: &[f64]) -> Vec<f64> {
+        self.evaluations += 1;
+        match self.evaluate(time, values) {
+            Ok(derivatives) => derivatives,
+            Err(error) => {
+                self.error.get_or_insert(error);
+                vec![0.0; self.stock_ids.len()]
+            }
+        }
+    }
+
+    /// Evaluates every auxiliary and flow in order, storing their values in
+    /// their slots, and sums the flows into stock derivatives.
+    pub(crate) fn evaluate(&mut self, time: f64, values: &[f64]) -> Result<Vec<f64>, EvalError> {
+        self.time = time;
+        self.slots[..values.len()].copy_from_slice(values);
+        for delay in &self.fixed_delays {
+            self.slots[delay.slot] = delay.history.output(time, self.dt);
+            self.defined[delay.slot] = true;
+        }
+
+        let mut derivatives = vec![0.0; self.stock_ids.len()];
+        for element in &self.elements {
+            let frame = Frame {
+                slots: &self.slots,
+                tables: &self.tables,
+                time,
+                dt: self.dt,
+            };
+            // Equations see flow rates in the flow's own units
+            let value = element.code.eval(&frame)?;
+            if let Some(i) = element.from {
+                derivatives[i] -= value * element.conversion;
+            }
+            if let Some(i) = element.to {
+                derivatives[i] += value * element.conversion;
+            }
+            self.slots[element.slot] = value;
+            self.defined[element.slot] = true;
+        }
+        Ok(derivatives)
+    }
+
+    /// Records the inputs of fixed delays at the given time and stock
+    /// values, and the stocks, auxiliaries, flows and net rates of change
+    /// of the stocks when `save` is set.
+    pub(crate) fn record(
+        &mut self,
+        time: f64,
+        values: &[f64],
+        save: bool,
+    ) -> Result<(), EvalError> {
+        let derivatives = self.evaluate(time, values)?;
+        let frame = Frame {
+            slots: &self.slots,
+            tables: &self.tables,
+            time,
+            dt: self.dt,
+        };
+        let inputs = self
+            .fixed_delays
+            .iter()
+            .map(|delay| delay.input.eval(&frame))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (delay, input) in self.fixed_delays.iter_mut().zip(inputs) {
+            delay.history.record(time, input, self.dt);
+        }
+
+        if !save {
+            return Ok(());
+        }
+        self.times.push(time);
+        for (slot, values) in &mut self.recorded {
+            values.push(self.slots[*slot]);
+        }
+        // Stocks are the first slots, so a stock's slot is its index
+        for ((slot, _), values) in self.recorded.iter().zip(&mut self.net_flows) {
+            values.push(derivatives[*slot]);
+        }
+        Ok(())
+    }
+
+    /// Forgets the results and fixed delay inputs recorded after `time`,
+    /// as by a step that failed.
+    pub(crate) fn discard_after(&mut self, time: f64) {
+        let rows = self.times.partition_point(|recorded| *recorded <= time);
+        self.times.truncate(rows);
+        for (_, values) in &mut self.recorded {
+            values.truncate(rows);
+        }
+        for values in &mut self.net_flows {
+            values.truncate(rows);
+        }
+        for delay in &mut self.fixed_delays {
+            delay.history.discard_after(time, self.dt);
+        }
+    }
+
+    /// The state at the last evaluated time and values, for evaluating
+    /// equations that are not compiled.
+    pub(crate) fn state(&self, state: &SystemState) -> SystemState {
+        let mut scratch = state.clone();
+        scratch.time = self.time;
+        scratch.dt = self.dt;
+        self.write_back(&mut scratch);
+        scratch
+    }
+
+    /// Writes the given stock values, and the variables last calculated
+    /// for them, into `state`.
+    pub(crate) fn commit(&mut self, state: &mut SystemState, values: &[f64]) {
+        self.slots[..values.len()].copy_from_slice(values);
+        self.write_back(state);
+    }
+
+    /// Returns the results recorded since the last call.
+    pub(crate) fn take_result(&mut self) -> SimulationResult {
+        let mut result = SimulationResult::new();
+        result.time_series = mem::take(&mut self.times);
+        let stocks = self.recorded_stocks;
+        let auxiliaries = stocks + self.recorded_auxiliaries;
+        for (i, (slot, values)) in self.recorded.iter_mut().enumerate() {
+            let id = self.ids[*slot].clone();
+            let values = mem::take(values);
+            if i < stocks {
+                result.stock_values.insert(id, values);
+            } else if i < auxiliaries {
+                result.auxiliary_values.insert(id, values);
+            } else {
+                result.flow_values.insert(id, values);
+            }
+        }
+        for ((slot, _), values) in self.recorded.iter().zip(&mut self.net_flows) {
+            result
+                .net_flow_values
+                .insert(format!("net({})", self.ids[*slot]), mem::take(values));
+        }
+        result
+    }
+
+    fn write_back(&self, state: &mut SystemState) {
+        for (stock_id, value) in self.stock_ids.iter().zip(&self.slots) {
+            state.set_stock_value(stock_id, *value);
+        }
+        let stocks = self.stock_ids.len();
+        state.variables = self.ids[stocks..]
+            .iter()
+            .zip(&self.slots[stocks..])
+            .zip(&self.defined[stocks..])
+            .filter(|(_, defined)| **defined)
+            .map(|((id, value), _)| (id.clone(), *value))
+            .collect();
+        state.histories = self
+            .fixed_delays
+            .iter()
+            .map(|delay| (delay.id.clone(), delay.history.clone()))
+            .collect();
+    }
+
+    /// Reports the first evaluation error since the last check.
+    pub(crate) fn check(&mut self) -> Result<(), EvalError> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
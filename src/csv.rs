@@ -0,0 +1,158 @@
+use std::io::{self, Write};
+
+use crate::SimulationResult;
+
+/// How results are arranged in rows and columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// A time column and one column per variable, one row per time step
+    #[default]
+    Wide,
+    /// `time`, `variable` and `value` columns, one row per variable and
+    /// time step (tidy data)
+    Long,
+}
+
+/// Settings for writing simulation results as delimited text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvOptions {
+    /// Field separator, `,` for CSV and a tab for TSV
+    pub delimiter: char,
+    pub layout: Layout,
+    /// Variables to write, in order. All stocks, auxiliaries and flows, in
+    /// the order of `SimulationResult::variable_ids`, when not given.
+    pub columns: Option<Vec<String>>,
+    /// Digits after the decimal point. Without a precision, numbers are
+    /// written with as many digits as needed to read them back exactly.
+    pub precision: Option<usize>,
+    /// Whether to write a header row
+    pub header: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            layout: Layout::Wide,
+            columns: None,
+            precision: None,
+            header: true,
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Comma separated values with a header.
+    pub fn csv() -> Self {
+        Self::default()
+    }
+
+    /// Tab separated values with a header.
+    pub fn tsv() -> Self {
+        Self {
+            delimiter: '\t',
+            ..Self::default()
+        }
+    }
+
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_columns(mut self, columns: &[&str]) -> Self {
+        self.columns = Some(columns.iter().map(|column| column.to_string()).collect());
+        self
+    }
+
+    pub fn with_precision(mut self, precision: usize) -> Self {
+        self.precision = Some(precision);
+        self
+    }
+
+    pub fn with_header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+}
+
+impl SimulationResult {
+    /// Writes the results as CSV, TSV or another delimited format.
+    ///
+    /// Fails with `InvalidInput` if a requested column is not a stock,
+    /// auxiliary or flow of the results.
+    pub fn write_csv<W: Write>(&self, mut writer: W, options: &CsvOptions) -> io::Result<()> {
+        let columns = match &options.columns {
+            Some(columns) => columns.clone(),
+            None => self.variable_ids().into_iter().cloned().collect(),
+        };
+        let series = columns
+            .iter()
+            .map(|column| {
+                self.get(column).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown column '{}'", column),
+                    )
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let delimiter = options.delimiter.to_string();
+        let number = |value: f64| match options.precision {
+            Some(precision) => format!("{:.*}", precision, value),
+            None => value.to_string(),
+        };
+        let field = |text: &str| quote(text, options.delimiter);
+
+        match options.layout {
+            Layout::Wide => {
+                if options.header {
+                    let header: Vec<_> = std::iter::once("time".to_string())
+                        .chain(columns.iter().map(|column| field(column)))
+                        .collect();
+                    writeln!(writer, "{}", header.join(&delimiter))?;
+                }
+                for (i, time) in self.time_series.iter().enumerate() {
+                    let row: Vec<_> =
+                        std::iter::once(number(*time))
+                            .chain(series.iter().map(|values| {
+                                values.get(i).map(|v| number(*v)).unwrap_or_default()
+                            }))
+                            .collect();
+                    writeln!(writer, "{}", row.join(&delimiter))?;
+                }
+            }
+            Layout::Long => {
+                if options.header {
+                    writeln!(writer, "time{0}variable{0}value", delimiter)?;
+                }
+                for (i, time) in self.time_series.iter().enumerate() {
+                    for (column, values) in columns.iter().zip(&series) {
+                        if let Some(value) = values.get(i) {
+                            writeln!(
+                                writer,
+                                "{}{d}{}{d}{}",
+                                number(*time),
+                                field(column),
+                                number(*value),
+                                d = delimiter
+                            )?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Quotes a field that contains the delimiter, a quote or a line break,
+/// doubling any quotes.
+fn quote(text: &str, delimiter: char) -> String {
+    if text.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
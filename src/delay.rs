@@ -0,0 +1,430 @@
+use crate::expr::time_tolerance;
+use crate::{BinaryOp, EvalError, Expr, Flow, FlowFunction, Function, Model, SystemState, UnaryOp};
+
+/// Prefix of the IDs of stocks and flows generated for stateful functions.
+pub(crate) const INTERNAL_PREFIX: char = '#';
+
+/// Highest order of a `DELAYN` call, which creates one stock per order.
+const MAX_ORDER: usize = 1000;
+
+/// Whether an ID belongs to a stock or flow generated for a stateful
+/// function.
+pub(crate) fn is_internal(id: &str) -> bool {
+    id.starts_with(INTERNAL_PREFIX)
+}
+
+/// A call to a stateful function, replaced by internal stocks and flows.
+pub(crate) struct DelaySite {
+    function: Function,
+    input: Expr,
+    /// Delay or averaging time
+    time: Expr,
+    initial: Option<Expr>,
+    /// IDs of the internal stocks, one per stage
+    pub(crate) stocks: Vec<String>,
+}
+
+impl DelaySite {
+    /// Values of the internal stocks when the simulation starts, calculated
+    /// from a state where every other variable has been evaluated.
+    pub(crate) fn initial_values(&self, state: &SystemState) -> Result<Vec<f64>, EvalError> {
+        let input = self.input.eval(state)?;
+        let time = self.time.eval(state)?;
+        let stages = self.stocks.len() as f64;
+
+        let value = match self.function {
+            Function::Delay1 | Function::Delay3 | Function::DelayN => {
+                let output = match &self.initial {
+                    Some(initial) => initial.eval(state)?,
+                    None => input,
+                };
+                output * time / stages
+            }
+            Function::Trend => {
+                let initial_trend = match &self.initial {
+                    Some(initial) => initial.eval(state)?,
+                    None => 0.0,
+                };
+                input / (1.0 + initial_trend * time)
+            }
+            _ => match &self.initial {
+                Some(initial) => initial.eval(state)?,
+                None => input,
+            },
+        };
+        Ok(vec![value; self.stocks.len()])
+    }
+}
+
+/// A call to `DELAYFIXED`, replaced by a variable read from a history
+/// buffer.
+pub(crate) struct FixedDelaySite {
+    /// ID of the variable holding the output, and of its history
+    pub(crate) id: String,
+    pub(crate) input: Expr,
+    time: Expr,
+    initial: Option<Expr>,
+}
+
+impl FixedDelaySite {
+    /// Creates the history buffer, with the delay time and initial value
+    /// calculated from a state where every other variable has been
+    /// evaluated.
+    pub(crate) fn history(&self, state: &SystemState) -> Result<History, EvalError> {
+        let delay = self.time.eval(state)?;
+        if delay.is_nan() || delay < 0.0 {
+            return Err(EvalError::InvalidArgument {
+                function: Function::DelayFixed,
+                reason: format!("delay time must not be negative, found {}", delay),
+            });
+        }
+        let initial = match &self.initial {
+            Some(initial) => initial.eval(state)?,
+            None => self.input.eval(state)?,
+        };
+        Ok(History {
+            input: self.input.clone(),
+            delay,
+            initial,
+            samples: Vec::new(),
+        })
+    }
+}
+
+/// Past input values of a fixed delay.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct History {
+    pub(crate) input: Expr,
+    delay: f64,
+    /// Output until the first sample is `delay` old
+    initial: f64,
+    /// Recorded (time, input) pairs, sorted by time
+    samples: Vec<(f64, f64)>,
+}
+
+impl History {
+    /// The input as it was `delay` before `time`.
+    ///
+    /// Inputs are held from one sample to the next rather than
+    /// interpolated, so a step in the input arrives as a step. Times
+    /// between samples, as with time steps that don't divide the delay,
+    /// see the last sample before them.
+    pub(crate) fn output(&self, time: f64, dt: f64) -> f64 {
+        let target = time - self.delay + time_tolerance(dt);
+        match self.samples.partition_point(|sample| sample.0 <= target) {
+            0 => self.initial,
+            i => self.samples[i - 1].1,
+        }
+    }
+
+    /// Records the input at `time`, replacing samples at or after it, and
+    /// forgets samples that can no longer be the output.
+    pub(crate) fn record(&mut self, time: f64, input: f64, dt: f64) {
+        let tolerance = time_tolerance(dt);
+        let end = self
+            .samples
+            .partition_point(|sample| sample.0 < time - tolerance);
+        self.samples.truncate(end);
+        self.samples.push((time, input));
+
+        let target = time - self.delay + tolerance;
+        let needed = self.samples.partition_point(|sample| sample.0 <= target);
+        if needed > 1 {
+            self.samples.drain(..needed - 1);
+        }
+    }
+
+    /// Forgets samples recorded after `time`, as by a step that failed.
+    pub(crate) fn discard_after(&mut self, time: f64, dt: f64) {
+        let tolerance = time_tolerance(dt);
+        let end = self
+            .samples
+            .partition_point(|sample| sample.0 <= time + tolerance);
+        self.samples.truncate(end);
+    }
+}
+
+/// A model where every stateful function call has been replaced by a
+/// reference to internal stocks and flows, or to a fixed delay variable.
+pub(crate) struct Expansion {
+    pub(crate) model: Model,
+    pub(crate) sites: Vec<DelaySite>,
+    pub(crate) fixed_delays: Vec<FixedDelaySite>,
+}
+
+/// Replaces the stateful function calls in every auxiliary and flow of the
+/// model with internal stocks and flows.
+///
+/// Internal IDs are derived from the owning element and the position of
+/// the call, so expanding the same model again yields the same IDs. The
+/// internal flows are added to the returned model, the caller adds the
+/// stocks listed in `sites` and the histories of `fixed_delays` to the
+/// state.
+pub(crate) fn expand(model: &Model) -> Result<Expansion, EvalError> {
+    let mut expanded = model.clone();
+    let mut expander = Expander {
+        model,
+        flows: Vec::new(),
+        sites: Vec::new(),
+        fixed_delays: Vec::new(),
+        owner: String::new(),
+        count: 0,
+    };
+
+    for auxiliary in expanded.auxiliaries.values_mut() {
+        expander.start(&auxiliary.id);
+        expander.expand_function(&mut auxiliary.function)?;
+    }
+    for flow in expanded.flows.values_mut() {
+        expander.start(&flow.id);
+        expander.expand_function(&mut flow.rate_function)?;
+    }
+
+    for flow in expander.flows {
+        expanded.flows.insert(flow.id.clone(), flow);
+    }
+    Ok(Expansion {
+        model: expanded,
+        sites: expander.sites,
+        fixed_delays: expander.fixed_delays,
+    })
+}
+
+struct Expander<'a> {
+    model: &'a Model,
+    flows: Vec<Flow>,
+    sites: Vec<DelaySite>,
+    fixed_delays: Vec<FixedDelaySite>,
+    /// ID of the element being expanded
+    owner: String,
+    /// Number of stateful calls expanded in the current element
+    count: usize,
+}
+
+impl Expander<'_> {
+    fn start(&mut self, owner: &str) {
+        self.owner = owner.to_string();
+        self.count = 0;
+    }
+
+    fn expand_function(&mut self, function: &mut FlowFunction) -> Result<(), EvalError> {
+        match function {
+            FlowFunction::Expression(expr) | FlowFunction::Lookup { input: expr, .. } => {
+                self.expand_expr(expr)
+            }
+            FlowFunction::Constant(_) | FlowFunction::Linear { .. } => Ok(()),
+        }
+    }
+
+    /// Expands nested calls first, so that the arguments of a stateful call
+    /// never contain other stateful calls.
+    fn expand_expr(&mut self, expr: &mut Expr) -> Result<(), EvalError> {
+        match expr {
+            Expr::Number(_) | Expr::Variable(_) | Expr::Time | Expr::Dt => {}
+            Expr::Unary(_, operand) => self.expand_expr(operand)?,
+            Expr::Binary(_, lhs, rhs) => {
+                self.expand_expr(lhs)?;
+                self.expand_expr(rhs)?;
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.expand_expr(condition)?;
+                self.expand_expr(then)?;
+                self.expand_expr(otherwise)?;
+            }
+            Expr::Lookup(_, input) => self.expand_expr(input)?,
+            Expr::Call(function, args) => {
+                for arg in args.iter_mut() {
+                    self.expand_expr(arg)?;
+                }
+                if function.is_stateful() {
+                    *expr = self.replace(*function, std::mem::take(args))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the internal stocks and flows for one call and returns the
+    /// expression for its output.
+    fn replace(&mut self, function: Function, mut args: Vec<Expr>) -> Result<Expr, EvalError> {
+        let base = format!(
+            "{}{}.{}.{}",
+            INTERNAL_PREFIX,
+            self.owner,
+            function.name().to_ascii_lowercase(),
+            self.count
+        );
+        self.count += 1;
+
+        if function == Function::DelayFixed {
+            let initial = args.get(2).cloned();
+            let time = args.swap_remove(1);
+            let input = args.swap_remove(0);
+            self.fixed_delays.push(FixedDelaySite {
+                id: base.clone(),
+                input,
+                time,
+                initial,
+            });
+            return Ok(Expr::Variable(base));
+        }
+
+        let initial = match function {
+            Function::DelayN => args.get(3).cloned(),
+            _ => args.get(2).cloned(),
+        };
+        let stages = match function {
+            Function::Delay1 | Function::Smth1 | Function::Trend => 1,
+            Function::Delay3 | Function::Smth3 => 3,
+            _ => self.constant_order(&args[2])?,
+        };
+        self.check_time(function, &args[1])?;
+        let time = args.swap_remove(1);
+        let input = args.swap_remove(0);
+
+        let stocks: Vec<String> = (0..stages).map(|i| format!("{}[{}]", base, i)).collect();
+        let stage_time = if stages == 1 {
+            time.clone()
+        } else {
+            divide(time.clone(), Expr::Number(stages as f64))
+        };
+
+        let output = match function {
+            Function::Delay1 | Function::Delay3 | Function::DelayN => {
+                // Material flows in, through each stage, and out of the last
+                self.flows.push(internal_flow(
+                    &format!("{}.in", base),
+                    input.clone(),
+                    None,
+                    Some(&stocks[0]),
+                ));
+                for (i, stock) in stocks.iter().enumerate() {
+                    self.flows.push(internal_flow(
+                        &format!("{}.out", stock),
+                        divide(Expr::Variable(stock.clone()), stage_time.clone()),
+                        Some(stock),
+                        stocks.get(i + 1),
+                    ));
+                }
+                Expr::Variable(format!("{}.out", stocks[stages - 1]))
+            }
+            _ => {
+                // Each stage adjusts toward the previous one
+                for (i, stock) in stocks.iter().enumerate() {
+                    let target = match i {
+                        0 => input.clone(),
+                        _ => Expr::Variable(stocks[i - 1].clone()),
+                    };
+                    let gap = Expr::Binary(
+                        BinaryOp::Subtract,
+                        Box::new(target),
+                        Box::new(Expr::Variable(stock.clone())),
+                    );
+                    self.flows.push(internal_flow(
+                        &format!("{}.change", stock),
+                        divide(gap, stage_time.clone()),
+                        None,
+                        Some(stock),
+                    ));
+                }
+                let smoothed = Expr::Variable(stocks[stages - 1].clone());
+                if function == Function::Trend {
+                    // (input - average) / (average * averaging time)
+                    divide(
+                        Expr::Binary(
+                            BinaryOp::Subtract,
+                            Box::new(input.clone()),
+                            Box::new(smoothed.clone()),
+                        ),
+                        Expr::Binary(
+                            BinaryOp::Multiply,
+                            Box::new(smoothed),
+                            Box::new(time.clone()),
+                        ),
+                    )
+                } else {
+                    smoothed
+                }
+            }
+        };
+
+        self.sites.push(DelaySite {
+            function,
+            input,
+            time,
+            initial,
+            stocks,
+        });
+        Ok(output)
+    }
+
+    /// The order of a `DELAYN` call, which must be a whole number from 1 to
+    /// `MAX_ORDER`, given as a number or a constant auxiliary.
+    fn constant_order(&self, order: &Expr) -> Result<usize, EvalError> {
+        match self.constant_value(order) {
+            Some(value) if value.fract() == 0.0 && (1.0..=MAX_ORDER as f64).contains(&value) => {
+                Ok(value as usize)
+            }
+            _ => Err(EvalError::InvalidArgument {
+                function: Function::DelayN,
+                reason: format!(
+                    "order must be a constant whole number from 1 to {}, found '{}'",
+                    MAX_ORDER, order
+                ),
+            }),
+        }
+    }
+
+    /// Rejects a delay or averaging time that is known before the
+    /// simulation to be zero or negative. Times calculated from other
+    /// variables are not checked.
+    fn check_time(&self, function: Function, time: &Expr) -> Result<(), EvalError> {
+        match self.constant_value(time) {
+            Some(value) if value.is_nan() || value <= 0.0 => {
+                let kind = match function {
+                    Function::Delay1 | Function::Delay3 | Function::DelayN => "delay",
+                    _ => "averaging",
+                };
+                Err(EvalError::InvalidArgument {
+                    function,
+                    reason: format!("{} time must be positive, found {}", kind, value),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The value of a number or of a constant auxiliary, possibly negated.
+    fn constant_value(&self, expr: &Expr) -> Option<f64> {
+        match expr {
+            Expr::Number(value) => Some(*value),
+            Expr::Unary(UnaryOp::Negate, operand) => {
+                self.constant_value(operand).map(|value| -value)
+            }
+            Expr::Variable(id) => match self.model.auxiliaries.get(id).map(|aux| &aux.function) {
+                Some(FlowFunction::Constant(value)) => Some(*value),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+fn divide(lhs: Expr, rhs: Expr) -> Expr {
+    Expr::Binary(BinaryOp::Divide, Box::new(lhs), Box::new(rhs))
+}
+
+fn internal_flow(id: &str, rate: Expr, from: Option<&String>, to: Option<&String>) -> Flow {
+    Flow {
+        id: id.to_string(),
+        name: id.to_string(),
+        from_stock: from.cloned(),
+        to_stock: to.cloned(),
+        rate_function: FlowFunction::Expression(rate),
+        units: String::new(),
+    }
+}
@@ -0,0 +1,1162 @@
+use std::fmt;
+
+use crate::SystemState;
+
+/// A parsed equation that can be evaluated against a `SystemState`.
+///
+/// Equations are written in a STELLA-like syntax:
+///
+/// ```text
+/// fertility * population * (1 - population / capacity)
+/// IF TIME >= 3 THEN rehearsal_rate ELSE 0
+/// MAX(0, inflow - LN(stock + 1))
+/// ```
+///
+/// Identifiers refer to stock, auxiliary or flow ids. Ids containing other
+/// characters can be written in double quotes. Keywords and function names
+/// are case-insensitive, ids are not.
+///
+/// Lookup tables added with `Model::add_lookup` are called like functions,
+/// `crowding_effect(population / capacity)`, or with
+/// `LOOKUP(crowding_effect, population / capacity)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    /// Reference to a stock, auxiliary or flow by ID
+    Variable(String),
+    /// The current simulation time
+    Time,
+    /// The simulation time step
+    Dt,
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    If {
+        condition: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+    Call(Function, Vec<Expr>),
+    /// Evaluates the lookup table with the given ID at the input value
+    Lookup(String, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+/// Built-in functions available in equations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Min,
+    Max,
+    Abs,
+    Exp,
+    Ln,
+    Log10,
+    Sqrt,
+    Sin,
+    Cos,
+    Tan,
+    /// Rounds toward negative infinity
+    Int,
+    /// Rounds toward zero
+    Trunc,
+    Round,
+    Mod,
+    /// `STEP(height, start)`: 0 before `start`, `height` from then on
+    Step,
+    /// `PULSE(volume, first, interval)`: `volume / DT` for one time step at
+    /// `first`, repeating every `interval` if given and positive
+    Pulse,
+    /// `RAMP(slope, start, end)`: rises with `slope` from `start`, and holds
+    /// its value after the optional `end`
+    Ramp,
+    /// `SINWAVE(amplitude, period)`
+    SinWave,
+    /// `COSWAVE(amplitude, period)`
+    CosWave,
+    /// `DELAY1(input, delay_time, initial)`: first order material delay
+    Delay1,
+    /// `DELAY3(input, delay_time, initial)`: third order material delay
+    Delay3,
+    /// `DELAYN(input, delay_time, order, initial)`: material delay of any
+    /// whole order from 1 to 1000. The order is fixed when the simulation
+    /// starts.
+    DelayN,
+    /// `SMTH1(input, averaging_time, initial)`: first order exponential
+    /// smoothing
+    Smth1,
+    /// `SMTH3(input, averaging_time, initial)`: third order exponential
+    /// smoothing
+    Smth3,
+    /// `TREND(input, averaging_time, initial_trend)`: fractional rate of
+    /// change of the input
+    Trend,
+    /// `DELAYFIXED(input, delay_time, initial)`: the input exactly
+    /// `delay_time` ago, or `initial` (the starting input by default) before
+    /// that. The delay time is fixed when the simulation starts. Also
+    /// accepted as `PIPELINE`.
+    DelayFixed,
+}
+
+impl Function {
+    const ALL: [Function; 26] = [
+        Function::Min,
+        Function::Max,
+        Function::Abs,
+        Function::Exp,
+        Function::Ln,
+        Function::Log10,
+        Function::Sqrt,
+        Function::Sin,
+        Function::Cos,
+        Function::Tan,
+        Function::Int,
+        Function::Trunc,
+        Function::Round,
+        Function::Mod,
+        Function::Step,
+        Function::Pulse,
+        Function::Ramp,
+        Function::SinWave,
+        Function::CosWave,
+        Function::Delay1,
+        Function::Delay3,
+        Function::DelayN,
+        Function::Smth1,
+        Function::Smth3,
+        Function::Trend,
+        Function::DelayFixed,
+    ];
+
+    /// The name of the function as written in equations.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Min => "MIN",
+            Function::Max => "MAX",
+            Function::Abs => "ABS",
+            Function::Exp => "EXP",
+            Function::Ln => "LN",
+            Function::Log10 => "LOG10",
+            Function::Sqrt => "SQRT",
+            Function::Sin => "SIN",
+            Function::Cos => "COS",
+            Function::Tan => "TAN",
+            Function::Int => "INT",
+            Function::Trunc => "TRUNC",
+            Function::Round => "ROUND",
+            Function::Mod => "MOD",
+            Function::Step => "STEP",
+            Function::Pulse => "PULSE",
+            Function::Ramp => "RAMP",
+            Function::SinWave => "SINWAVE",
+            Function::CosWave => "COSWAVE",
+            Function::Delay1 => "DELAY1",
+            Function::Delay3 => "DELAY3",
+            Function::DelayN => "DELAYN",
+            Function::Smth1 => "SMTH1",
+            Function::Smth3 => "SMTH3",
+            Function::Trend => "TREND",
+            Function::DelayFixed => "DELAYFIXED",
+        }
+    }
+
+    /// Looks up a function by name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Function> {
+        if name.eq_ignore_ascii_case("PIPELINE") {
+            return Some(Function::DelayFixed);
+        }
+        Function::ALL
+            .into_iter()
+            .find(|function| function.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Minimum and maximum number of arguments.
+    fn arity(&self) -> (usize, usize) {
+        match self {
+            Function::Min | Function::Max => (2, usize::MAX),
+            Function::Mod | Function::Step | Function::SinWave | Function::CosWave => (2, 2),
+            Function::Pulse | Function::Ramp => (2, 3),
+            Function::Delay1
+            | Function::Delay3
+            | Function::Smth1
+            | Function::Smth3
+            | Function::Trend
+            | Function::DelayFixed => (2, 3),
+            Function::DelayN => (3, 4),
+            _ => (1, 1),
+        }
+    }
+
+    /// Whether the function keeps state between time steps. Stateful
+    /// functions are expanded into internal stocks, flows or history
+    /// buffers when a model is simulated, and cannot be evaluated on their
+    /// own.
+    pub fn is_stateful(&self) -> bool {
+        matches!(
+            self,
+            Function::Delay1
+                | Function::Delay3
+                | Function::DelayN
+                | Function::Smth1
+                | Function::Smth3
+                | Function::Trend
+                | Function::DelayFixed
+        )
+    }
+
+    /// Applies the function to its evaluated arguments at the given time
+    /// and time step.
+    pub(crate) fn apply(&self, args: &[f64], time: f64, dt: f64) -> f64 {
+        match self {
+            Function::Min => args.iter().copied().fold(f64::INFINITY, f64::min),
+            Function::Max => args.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Function::Abs => args[0].abs(),
+            Function::Exp => args[0].exp(),
+            Function::Ln => args[0].ln(),
+            Function::Log10 => args[0].log10(),
+            Function::Sqrt => args[0].sqrt(),
+            Function::Sin => args[0].sin(),
+            Function::Cos => args[0].cos(),
+            Function::Tan => args[0].tan(),
+            Function::Int => args[0].floor(),
+            Function::Trunc => args[0].trunc(),
+            Function::Round => args[0].round(),
+            Function::Mod => args[0].rem_euclid(args[1]),
+            Function::Step => {
+                if time >= args[1] - time_tolerance(dt) {
+                    args[0]
+                } else {
+                    0.0
+                }
+            }
+            Function::Pulse => {
+                let (volume, first) = (args[0], args[1]);
+                let interval = args.get(2).copied().unwrap_or(0.0);
+                let tolerance = time_tolerance(dt);
+                if dt <= 0.0 || time < first - tolerance {
+                    return 0.0;
+                }
+                // Start of the most recent pulse at or before the current time
+                let start = if interval > 0.0 {
+                    first + ((time + tolerance - first) / interval).floor() * interval
+                } else {
+                    first
+                };
+                if time < start + dt - tolerance {
+                    volume / dt
+                } else {
+                    0.0
+                }
+            }
+            Function::Ramp => {
+                let (slope, start) = (args[0], args[1]);
+                let end = args.get(2).copied().unwrap_or(f64::INFINITY);
+                if time <= start {
+                    0.0
+                } else {
+                    slope * (time.min(end) - start)
+                }
+            }
+            Function::SinWave => args[0] * (2.0 * std::f64::consts::PI * time / args[1]).sin(),
+            Function::CosWave => args[0] * (2.0 * std::f64::consts::PI * time / args[1]).cos(),
+            // Stateful functions are rejected before their arguments are
+            // evaluated, see `Expr::eval`
+            Function::Delay1
+            | Function::Delay3
+            | Function::DelayN
+            | Function::Smth1
+            | Function::Smth3
+            | Function::Trend
+            | Function::DelayFixed => f64::NAN,
+        }
+    }
+}
+
+/// An equation could not be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// Byte offset into the equation where the error was found
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// An equation could not be evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    /// The equation references an ID that is not a stock, auxiliary or
+    /// flow in the current state
+    UnknownVariable(String),
+    /// The equation references a lookup table that is not in the current
+    /// state
+    UnknownLookup(String),
+    /// A lookup table input was outside the table's x range, and the table
+    /// is set to `OutOfRange::Error`
+    OutOfRange { value: f64, min: f64, max: f64 },
+    /// A stateful function such as `DELAY1` was evaluated outside of a
+    /// model simulation
+    StatefulFunction(Function),
+    /// A function argument has a value the function cannot work with
+    InvalidArgument { function: Function, reason: String },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::UnknownVariable(id) => write!(f, "unknown variable '{}'", id),
+            EvalError::UnknownLookup(id) => write!(f, "unknown lookup table '{}'", id),
+            EvalError::OutOfRange { value, min, max } => write!(
+                f,
+                "lookup input {} is outside the table range [{}, {}]",
+                value, min, max
+            ),
+            EvalError::InvalidArgument { function, reason } => {
+                write!(f, "invalid argument to {}: {}", function.name(), reason)
+            }
+            EvalError::StatefulFunction(function) => write!(
+                f,
+                "{} can only be evaluated as part of a model simulation",
+                function.name()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl Expr {
+    /// Parses an equation.
+    pub fn parse(source: &str) -> Result<Expr, ParseError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            source_len: source.len(),
+        };
+        let expr = parser.expression()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some((_, position)) => Err(ParseError {
+                message: "unexpected input after end of equation".to_string(),
+                position: *position,
+            }),
+        }
+    }
+
+    /// Evaluates the expression against a system state.
+    ///
+    /// Comparisons and logical operators evaluate to 1.0 (true) or 0.0
+    /// (false). Any non-zero value is treated as true.
+    pub fn eval(&self, state: &SystemState) -> Result<f64, EvalError> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Variable(id) => state
+                .get_value(id)
+                .ok_or_else(|| EvalError::UnknownVariable(id.clone())),
+            Expr::Time => Ok(state.time),
+            Expr::Dt => Ok(state.dt),
+            Expr::Unary(op, operand) => Ok(op.apply(operand.eval(state)?)),
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(state)?;
+                if let Some(value) = op.short_circuit(lhs) {
+                    return Ok(value);
+                }
+                Ok(op.apply(lhs, rhs.eval(state)?))
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                if condition.eval(state)? != 0.0 {
+                    then.eval(state)
+                } else {
+                    otherwise.eval(state)
+                }
+            }
+            Expr::Call(function, _) if function.is_stateful() => {
+                Err(EvalError::StatefulFunction(*function))
+            }
+            Expr::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(state))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(function.apply(&args, state.time, state.dt))
+            }
+            Expr::Lookup(table, input) => {
+                let input = input.eval(state)?;
+                state
+                    .lookups
+                    .get(table)
+                    .ok_or_else(|| EvalError::UnknownLookup(table.clone()))?
+                    .evaluate(input)
+            }
+        }
+    }
+
+    /// The IDs of all variables referenced by the expression, in order of
+    /// first appearance.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut variables = Vec::new();
+        self.collect_variables(&mut variables);
+        variables
+    }
+
+    fn collect_variables<'a>(&'a self, variables: &mut Vec<&'a str>) {
+        match self {
+            Expr::Variable(id) => {
+                if !variables.contains(&id.as_str()) {
+                    variables.push(id);
+                }
+            }
+            Expr::Number(_) | Expr::Time | Expr::Dt => {}
+            Expr::Unary(_, operand) => operand.collect_variables(variables),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.collect_variables(variables);
+                rhs.collect_variables(variables);
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                condition.collect_variables(variables);
+                then.collect_variables(variables);
+                otherwise.collect_variables(variables);
+            }
+            Expr::Call(_, args) => {
+                for arg in args {
+                    arg.collect_variables(variables);
+                }
+            }
+            Expr::Lookup(_, input) => input.collect_variables(variables),
+        }
+    }
+
+    /// The IDs of all lookup tables called by the expression, in order of
+    /// first appearance.
+    pub fn lookups(&self) -> Vec<&str> {
+        let mut lookups = Vec::new();
+        self.collect_lookups(&mut lookups);
+        lookups
+    }
+
+    fn collect_lookups<'a>(&'a self, lookups: &mut Vec<&'a str>) {
+        match self {
+            Expr::Number(_) | Expr::Variable(_) | Expr::Time | Expr::Dt => {}
+            Expr::Unary(_, operand) => operand.collect_lookups(lookups),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.collect_lookups(lookups);
+                rhs.collect_lookups(lookups);
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                condition.collect_lookups(lookups);
+                then.collect_lookups(lookups);
+                otherwise.collect_lookups(lookups);
+            }
+            Expr::Call(_, args) => {
+                for arg in args {
+                    arg.collect_lookups(lookups);
+                }
+            }
+            Expr::Lookup(table, input) => {
+                if !lookups.contains(&table.as_str()) {
+                    lookups.push(table);
+                }
+                input.collect_lookups(lookups);
+            }
+        }
+    }
+
+    /// Binding strength used to decide where parentheses are needed when
+    /// printing.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::If { .. } => 0,
+            Expr::Binary(op, _, _) => op.precedence(),
+            Expr::Unary(UnaryOp::Not, _) => 3,
+            Expr::Unary(UnaryOp::Negate, _) => 7,
+            _ => 9,
+        }
+    }
+}
+
+/// Slack for comparing the simulation time against event times, so that
+/// floating point error in the accumulated time does not shift an event by
+/// a whole time step.
+pub(crate) fn time_tolerance(dt: f64) -> f64 {
+    dt.abs() * 1e-6
+}
+
+fn bool_value(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+impl UnaryOp {
+    pub(crate) fn apply(&self, value: f64) -> f64 {
+        match self {
+            UnaryOp::Negate => -value,
+            UnaryOp::Not => bool_value(value == 0.0),
+        }
+    }
+}
+
+impl BinaryOp {
+    /// The value of a logical operator that is decided by its left operand
+    /// alone, in which case the right operand is not evaluated.
+    pub(crate) fn short_circuit(&self, lhs: f64) -> Option<f64> {
+        match self {
+            BinaryOp::And if lhs == 0.0 => Some(0.0),
+            BinaryOp::Or if lhs != 0.0 => Some(1.0),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn apply(&self, lhs: f64, rhs: f64) -> f64 {
+        match self {
+            BinaryOp::Add => lhs + rhs,
+            BinaryOp::Subtract => lhs - rhs,
+            BinaryOp::Multiply => lhs * rhs,
+            BinaryOp::Divide => lhs / rhs,
+            BinaryOp::Power => lhs.powf(rhs),
+            BinaryOp::Equal => bool_value(lhs == rhs),
+            BinaryOp::NotEqual => bool_value(lhs != rhs),
+            BinaryOp::Less => bool_value(lhs < rhs),
+            BinaryOp::LessEqual => bool_value(lhs <= rhs),
+            BinaryOp::Greater => bool_value(lhs > rhs),
+            BinaryOp::GreaterEqual => bool_value(lhs >= rhs),
+            BinaryOp::And | BinaryOp::Or => bool_value(rhs != 0.0),
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Equal
+            | BinaryOp::NotEqual
+            | BinaryOp::Less
+            | BinaryOp::LessEqual
+            | BinaryOp::Greater
+            | BinaryOp::GreaterEqual => 4,
+            BinaryOp::Add | BinaryOp::Subtract => 5,
+            BinaryOp::Multiply | BinaryOp::Divide => 6,
+            BinaryOp::Power => 8,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Power => "^",
+            BinaryOp::Equal => "=",
+            BinaryOp::NotEqual => "<>",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+        }
+    }
+}
+
+/// Prints the expression back in equation syntax, adding parentheses only
+/// where they are needed.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Equation {
+            expr: self,
+            name: Function::name,
+        }
+        .fmt(f)
+    }
+}
+
+impl Expr {
+    /// Prints the expression as `Display` does, with the function names
+    /// given by `name`, for formats that name builtins differently.
+    pub(crate) fn display_with(
+        &self,
+        name: fn(&Function) -> &'static str,
+    ) -> impl fmt::Display + '_ {
+        Equation { expr: self, name }
+    }
+}
+
+/// An expression printed with the given function names.
+struct Equation<'a> {
+    expr: &'a Expr,
+    name: fn(&Function) -> &'static str,
+}
+
+impl Equation<'_> {
+    fn with<'b>(&self, expr: &'b Expr) -> Equation<'b> {
+        Equation {
+            expr,
+            name: self.name,
+        }
+    }
+
+    fn write_operand(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        operand: &Expr,
+        precedence: u8,
+        strict: bool,
+    ) -> fmt::Result {
+        let operand_precedence = operand.precedence();
+        if operand_precedence < precedence || (strict && operand_precedence == precedence) {
+            write!(f, "({})", self.with(operand))
+        } else {
+            write!(f, "{}", self.with(operand))
+        }
+    }
+}
+
+impl fmt::Display for Equation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.expr {
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::Variable(id) => {
+                if is_plain_identifier(id) {
+                    write!(f, "{}", id)
+                } else {
+                    write!(f, "\"{}\"", id)
+                }
+            }
+            Expr::Time => write!(f, "TIME"),
+            Expr::Dt => write!(f, "DT"),
+            Expr::Unary(op, operand) => {
+                match op {
+                    UnaryOp::Negate => write!(f, "-")?,
+                    UnaryOp::Not => write!(f, "NOT ")?,
+                }
+                self.write_operand(f, operand, self.expr.precedence(), false)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                // Power is right associative, everything else is left associative
+                let right_assoc = *op == BinaryOp::Power;
+                self.write_operand(f, lhs, op.precedence(), right_assoc)?;
+                write!(f, " {} ", op.symbol())?;
+                self.write_operand(f, rhs, op.precedence(), !right_assoc)
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => write!(
+                f,
+                "IF {} THEN {} ELSE {}",
+                self.with(condition),
+                self.with(then),
+                self.with(otherwise)
+            ),
+            Expr::Call(function, args) => {
+                write!(f, "{}(", (self.name)(function))?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", self.with(arg))?;
+                }
+                write!(f, ")")
+            }
+            Expr::Lookup(table, input) => {
+                if is_plain_identifier(table) {
+                    write!(f, "LOOKUP({}, {})", table, self.with(input))
+                } else {
+                    write!(f, "LOOKUP(\"{}\", {})", table, self.with(input))
+                }
+            }
+        }
+    }
+}
+
+/// Whether an ID can be written in an equation without quotes.
+fn is_plain_identifier(id: &str) -> bool {
+    let (base, index) = match id.find('[') {
+        Some(start) => (&id[..start], Some(&id[start..])),
+        None => (id, None),
+    };
+    let mut chars = base.chars();
+    let valid_base = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    let valid_index = index.is_none_or(|index| {
+        index.len() > 2
+            && index.ends_with(']')
+            && index[1..index.len() - 1]
+                .chars()
+                .all(|c| c.is_ascii_digit())
+    });
+    valid_base
+        && valid_index
+        && Keyword::from_word(base).is_none()
+        && Function::from_name(base).is_none()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Keyword {
+    If,
+    Then,
+    Else,
+    And,
+    Or,
+    Not,
+    Time,
+    Dt,
+}
+
+impl Keyword {
+    fn from_word(word: &str) -> Option<Keyword> {
+        let keyword = match word.to_ascii_uppercase().as_str() {
+            "IF" => Keyword::If,
+            "THEN" => Keyword::Then,
+            "ELSE" => Keyword::Else,
+            "AND" => Keyword::And,
+            "OR" => Keyword::Or,
+            "NOT" => Keyword::Not,
+            "TIME" => Keyword::Time,
+            "DT" => Keyword::Dt,
+            _ => return None,
+        };
+        Some(keyword)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Keyword(Keyword),
+    Operator(&'static str),
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+const OPERATORS: [&str; 16] = [
+    "<=", ">=", "<>", "!=", "==", "&&", "||", "+", "-", "*", "/", "^", "=", "<", ">", "!",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens = Vec::new();
+    let bytes = source.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i] as char;
+        let start = i;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c.is_ascii_digit() || (c == '.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)) {
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+                let mut j = i + 1;
+                if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+                    j += 1;
+                }
+                if j < bytes.len() && bytes[j].is_ascii_digit() {
+                    i = j;
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let value = source[start..i].parse().map_err(|_| ParseError {
+                message: format!("invalid number '{}'", &source[start..i]),
+                position: start,
+            })?;
+            tokens.push((Token::Number(value), start));
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'.')
+            {
+                i += 1;
+            }
+            let word = &source[start..i];
+            // Array element, as generated by `StockArray::stock_id`
+            if bytes.get(i) == Some(&b'[') {
+                let close = source[i..].find(']').map(|offset| i + offset);
+                match close {
+                    Some(close) if source[i + 1..close].trim().parse::<usize>().is_ok() => {
+                        let index = source[i + 1..close].trim();
+                        tokens.push((Token::Identifier(format!("{}[{}]", word, index)), start));
+                        i = close + 1;
+                        continue;
+                    }
+                    _ => {
+                        return Err(ParseError {
+                            message: "expected array index".to_string(),
+                            position: i,
+                        })
+                    }
+                }
+            }
+            let token = match Keyword::from_word(word) {
+                Some(keyword) => Token::Keyword(keyword),
+                None => Token::Identifier(word.to_string()),
+            };
+            tokens.push((token, start));
+            continue;
+        }
+
+        if c == '"' {
+            let Some(end) = source[i + 1..].find('"') else {
+                return Err(ParseError {
+                    message: "unterminated quoted identifier".to_string(),
+                    position: start,
+                });
+            };
+            let name = &source[i + 1..i + 1 + end];
+            tokens.push((Token::Identifier(name.to_string()), start));
+            i += end + 2;
+            continue;
+        }
+
+        let token = match c {
+            '(' => Some(Token::LeftParen),
+            ')' => Some(Token::RightParen),
+            ',' => Some(Token::Comma),
+            _ => None,
+        };
+        if let Some(token) = token {
+            tokens.push((token, start));
+            i += 1;
+            continue;
+        }
+
+        match OPERATORS.iter().find(|op| source[i..].starts_with(*op)) {
+            Some(op) => {
+                tokens.push((Token::Operator(op), start));
+                i += op.len();
+            }
+            None => {
+                return Err(ParseError {
+                    message: format!(
+                        "unexpected character '{}'",
+                        &source[i..].chars().next().unwrap_or(c)
+                    ),
+                    position: start,
+                })
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, from lowest to highest precedence:
+/// IF, OR, AND, NOT, comparisons, + -, * /, unary minus, ^.
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    source_len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(Token, usize)> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_token(&self) -> Option<&Token> {
+        self.peek().map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<(Token, usize)> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            message: message.to_string(),
+            position: self
+                .peek()
+                .map_or(self.source_len, |(_, position)| *position),
+        }
+    }
+
+    fn eat_operator(&mut self, candidates: &[&str]) -> Option<&'static str> {
+        match self.peek_token() {
+            Some(Token::Operator(op)) if candidates.contains(op) => {
+                let op = *op;
+                self.position += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: Keyword) -> bool {
+        if self.peek_token() == Some(&Token::Keyword(keyword)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token, message: &str) -> Result<(), ParseError> {
+        if self.peek_token() == Some(&token) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, ParseError> {
+        if self.eat_keyword(Keyword::If) {
+            let condition = self.expression()?;
+            if !self.eat_keyword(Keyword::Then) {
+                return Err(self.error("expected THEN"));
+            }
+            let then = self.expression()?;
+            if !self.eat_keyword(Keyword::Else) {
+                return Err(self.error("expected ELSE"));
+            }
+            let otherwise = self.expression()?;
+            return Ok(Expr::If {
+                condition: Box::new(condition),
+                then: Box::new(then),
+                otherwise: Box::new(otherwise),
+            });
+        }
+        self.or()
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.and()?;
+        while self.eat_keyword(Keyword::Or) || self.eat_operator(&["||"]).is_some() {
+            let rhs = self.and()?;
+            lhs = Expr::Binary(BinaryOp::Or, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.not()?;
+        while self.eat_keyword(Keyword::And) || self.eat_operator(&["&&"]).is_some() {
+            let rhs = self.not()?;
+            lhs = Expr::Binary(BinaryOp::And, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn not(&mut self) -> Result<Expr, ParseError> {
+        if self.eat_keyword(Keyword::Not) || self.eat_operator(&["!"]).is_some() {
+            let operand = self.not()?;
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(operand)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.additive()?;
+        while let Some(op) = self.eat_operator(&["=", "==", "<>", "!=", "<", "<=", ">", ">="]) {
+            let op = match op {
+                "=" | "==" => BinaryOp::Equal,
+                "<>" | "!=" => BinaryOp::NotEqual,
+                "<" => BinaryOp::Less,
+                "<=" => BinaryOp::LessEqual,
+                ">" => BinaryOp::Greater,
+                _ => BinaryOp::GreaterEqual,
+            };
+            let rhs = self.additive()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn additive(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.multiplicative()?;
+        while let Some(op) = self.eat_operator(&["+", "-"]) {
+            let op = if op == "+" {
+                BinaryOp::Add
+            } else {
+                BinaryOp::Subtract
+            };
+            let rhs = self.multiplicative()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.eat_operator(&["*", "/"]) {
+            let op = if op == "*" {
+                BinaryOp::Multiply
+            } else {
+                BinaryOp::Divide
+            };
+            let rhs = self.unary()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if let Some(op) = self.eat_operator(&["-", "+"]) {
+            let operand = self.unary()?;
+            return Ok(if op == "-" {
+                Expr::Unary(UnaryOp::Negate, Box::new(operand))
+            } else {
+                operand
+            });
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.primary()?;
+        if self.eat_operator(&["^"]).is_some() {
+            // Right associative, and binds tighter than a unary minus on its left
+            let exponent = self.unary()?;
+            return Ok(Expr::Binary(
+                BinaryOp::Power,
+                Box::new(base),
+                Box::new(exponent),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let Some((token, position)) = self.next() else {
+            return Err(self.error("unexpected end of equation"));
+        };
+        match token {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Keyword(Keyword::Time) => Ok(Expr::Time),
+            Token::Keyword(Keyword::Dt) => Ok(Expr::Dt),
+            Token::LeftParen => {
+                let expr = self.expression()?;
+                self.expect(Token::RightParen, "expected ')'")?;
+                Ok(expr)
+            }
+            Token::Identifier(name) => {
+                if self.peek_token() != Some(&Token::LeftParen) {
+                    return Ok(Expr::Variable(name));
+                }
+                self.position += 1;
+                if name.eq_ignore_ascii_case("LOOKUP") {
+                    return self.lookup(position);
+                }
+                let Some(function) = Function::from_name(&name) else {
+                    // Any other call is a lookup table
+                    let input = self.expression()?;
+                    self.expect(Token::RightParen, "lookup tables take a single input")?;
+                    return Ok(Expr::Lookup(name, Box::new(input)));
+                };
+                let args = self.arguments()?;
+                let (min, max) = function.arity();
+                if args.len() < min || args.len() > max {
+                    return Err(ParseError {
+                        message: format!(
+                            "{} takes {} arguments, found {}",
+                            function.name(),
+                            if min == max {
+                                min.to_string()
+                            } else if max == usize::MAX {
+                                format!("at least {}", min)
+                            } else {
+                                format!("{} to {}", min, max)
+                            },
+                            args.len()
+                        ),
+                        position,
+                    });
+                }
+                Ok(Expr::Call(function, args))
+            }
+            _ => {
+                self.position -= 1;
+                Err(self.error("expected a number, variable or '('"))
+            }
+        }
+    }
+
+    /// Parses `LOOKUP(table, input)` after the opening parenthesis.
+    fn lookup(&mut self, position: usize) -> Result<Expr, ParseError> {
+        let Some((Token::Identifier(table), _)) = self.next() else {
+            return Err(ParseError {
+                message: "LOOKUP expects a lookup table name".to_string(),
+                position,
+            });
+        };
+        self.expect(Token::Comma, "expected ','")?;
+        let input = self.expression()?;
+        self.expect(Token::RightParen, "expected ')'")?;
+        Ok(Expr::Lookup(table, Box::new(input)))
+    }
+
+    /// Parses a comma separated argument list after the opening parenthesis.
+    fn arguments(&mut self) -> Result<Vec<Expr>, ParseError> {
+        let mut args = Vec::new();
+        if self.peek_token() == Some(&Token::RightParen) {
+            self.position += 1;
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expression()?);
+            match self.next() {
+                Some((Token::Comma, _)) => continue,
+                Some((Token::RightParen, _)) => return Ok(args),
+                _ => {
+                    self.position -= 1;
+                    return Err(self.error("expected ',' or ')'"));
+                }
+            }
+        }
+    }
+}
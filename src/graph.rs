@@ -0,0 +1,121 @@
+use crate::{delay, Model};
+
+impl Model {
+    /// The model as a stock-and-flow diagram in the Graphviz DOT language.
+    ///
+    /// Stocks are boxes and auxiliaries ellipses. Each flow is a small node
+    /// on a bold edge from the stock it drains, or a cloud, to the stock it
+    /// fills, or a cloud. Dashed edges link the variables an equation reads
+    /// to the flow or auxiliary that reads them. Lookup tables and internal
+    /// stocks are not drawn.
+    pub fn to_dot(&self) -> String {
+        let stocks: Vec<_> = self
+            .state
+            .stocks
+            .values()
+            .filter(|stock| !delay::is_internal(&stock.id))
+            .collect();
+        let flows: Vec<_> = self.flows.values().collect();
+        let auxiliaries: Vec<_> = self.auxiliaries.values().collect();
+
+        let mut dot = format!("digraph {} {{\n", quote(&self.name));
+        dot.push_str("    rankdir=LR;\n");
+
+        for stock in &stocks {
+            dot.push_str(&format!(
+                "    {} [shape=box, label={}];\n",
+                quote(&stock.id),
+                quote(label(&stock.id, &stock.name))
+            ));
+        }
+        for flow in &flows {
+            dot.push_str(&format!(
+                "    {} [shape=circle, width=0.2, fixedsize=true, xlabel={}, label=\"\"];\n",
+                quote(&flow.id),
+                quote(label(&flow.id, &flow.name))
+            ));
+        }
+        for auxiliary in &auxiliaries {
+            dot.push_str(&format!(
+                "    {} [shape=ellipse, label={}];\n",
+                quote(&auxiliary.id),
+                quote(label(&auxiliary.id, &auxiliary.name))
+            ));
+        }
+
+        for flow in &flows {
+            let from = match &flow.from_stock {
+                Some(stock) => quote(stock),
+                None => cloud(&mut dot, &format!("#source {}", flow.id)),
+            };
+            let to = match &flow.to_stock {
+                Some(stock) => quote(stock),
+                None => cloud(&mut dot, &format!("#sink {}", flow.id)),
+            };
+            dot.push_str(&format!(
+                "    {} -> {} [penwidth=2, arrowhead=none];\n",
+                from,
+                quote(&flow.id)
+            ));
+            dot.push_str(&format!(
+                "    {} -> {} [penwidth=2];\n",
+                quote(&flow.id),
+                to
+            ));
+        }
+
+        let functions = flows
+            .iter()
+            .map(|flow| (&flow.id, &flow.rate_function))
+            .chain(
+                auxiliaries
+                    .iter()
+                    .map(|auxiliary| (&auxiliary.id, &auxiliary.function)),
+            );
+        for (id, function) in functions {
+            for dependency in function.dependencies() {
+                if self.state.stocks.contains_key(dependency)
+                    || self.flows.contains_key(dependency)
+                    || self.auxiliaries.contains_key(dependency)
+                {
+                    dot.push_str(&format!(
+                        "    {} -> {} [style=dashed, color=gray40];\n",
+                        quote(dependency),
+                        quote(id)
+                    ));
+                }
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Adds a cloud node, the source or sink of a flow outside the model, and
+/// returns its quoted ID.
+fn cloud(dot: &mut String, id: &str) -> String {
+    let id = quote(id);
+    dot.push_str(&format!(
+        "    {} [shape=egg, style=dashed, label=\"\", width=0.3, height=0.2];\n",
+        id
+    ));
+    id
+}
+
+fn label<'a>(id: &'a str, name: &'a str) -> &'a str {
+    if name.is_empty() {
+        id
+    } else {
+        name
+    }
+}
+
+/// A DOT string literal. Line breaks in names become line breaks in labels.
+fn quote(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
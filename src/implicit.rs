@@ -0,0 +1,222 @@
+//! Implicit backward differentiation formulas for stiff models.
+
+/// Maximum number of Newton iterations per step.
+const MAX_NEWTON_ITERATIONS: usize = 20;
+
+/// Convergence threshold for the Newton update, relative to the values.
+const NEWTON_TOLERANCE: f64 = 1e-10;
+
+/// BDF coefficients for orders 1 through 5 as `(beta, alphas)`, where
+/// `y[n+1] = sum(alpha[j] * y[n-j]) + beta * h * f(t[n+1], y[n+1])`.
+const BDF_COEFFICIENTS: [(f64, &[f64]); 5] = [
+    (1.0, &[1.0]),
+    (2.0 / 3.0, &[4.0 / 3.0, -1.0 / 3.0]),
+    (6.0 / 11.0, &[18.0 / 11.0, -9.0 / 11.0, 2.0 / 11.0]),
+    (
+        12.0 / 25.0,
+        &[48.0 / 25.0, -36.0 / 25.0, 16.0 / 25.0, -3.0 / 25.0],
+    ),
+    (
+        60.0 / 137.0,
+        &[
+            300.0 / 137.0,
+            -300.0 / 137.0,
+            200.0 / 137.0,
+            -75.0 / 137.0,
+            12.0 / 137.0,
+        ],
+    ),
+];
+
+/// Highest supported BDF order. Orders above 6 are not zero-stable, and
+/// order 6 is only stable for decaying modes within about 18 degrees of the
+/// real axis, too few for stiff models that oscillate. Like most BDF codes,
+/// this stops at 5, which is stable within about 51 degrees.
+pub(crate) const MAX_BDF_ORDER: usize = BDF_COEFFICIENTS.len();
+
+/// Takes one BDF step from `time` to `time + dt`.
+///
+/// `history` holds the most recent values first. The order of the formula is
+/// the number of history entries, up to `MAX_BDF_ORDER`. The implicit
+/// equation is solved with Newton's method using a finite difference
+/// Jacobian of `derivative`.
+///
+/// Returns `None` if Newton's method does not converge, because its matrix
+/// is singular or it runs out of iterations.
+pub(crate) fn bdf_step<F>(
+    history: &[Vec<f64>],
+    time: f64,
+    dt: f64,
+    mut derivative: F,
+) -> Option<Vec<f64>>
+where
+    F: FnMut(f64, &[f64]) -> Vec<f64>,
+{
+    let order = history.len().clamp(1, MAX_BDF_ORDER);
+    let (beta, alphas) = BDF_COEFFICIENTS[order - 1];
+    let new_time = time + dt;
+    let size = history[0].len();
+
+    // Constant part of the implicit equation
+    let base: Vec<f64> = (0..size)
+        .map(|i| {
+            alphas
+                .iter()
+                .zip(history)
+                .map(|(alpha, values)| alpha * values[i])
+                .sum()
+        })
+        .collect();
+
+    let mut values = history[0].clone();
+    for _ in 0..MAX_NEWTON_ITERATIONS {
+        let slope = derivative(new_time, &values);
+        // Residual of y - base - beta * h * f(y) = 0
+        let residual: Vec<f64> = (0..size)
+            .map(|i| values[i] - base[i] - beta * dt * slope[i])
+            .collect();
+
+        let jacobian = jacobian(&mut derivative, new_time, &values, &slope);
+        let mut matrix: Vec<Vec<f64>> = jacobian
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                row.iter()
+                    .enumerate()
+                    .map(|(j, dfdy)| {
+                        let identity = if i == j { 1.0 } else { 0.0 };
+                        identity - beta * dt * dfdy
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let update = solve(&mut matrix, residual)?;
+
+        let mut converged = true;
+        for (value, delta) in values.iter_mut().zip(&update) {
+            *value -= delta;
+            if delta.is_nan() || delta.abs() > NEWTON_TOLERANCE * (1.0 + value.abs()) {
+                converged = false;
+            }
+        }
+        if converged {
+            return Some(values);
+        }
+    }
+    None
+}
+
+/// Chooses the order of the next BDF step from the order of the previous
+/// one, or 0 for the first step.
+///
+/// `history` holds the most recent values first. The local error of order
+/// `k` is estimated as `|nabla^(k+1) y| / (k + 1)`, from the backward
+/// differences of the history. The order moves by at most one per step, to
+/// whichever of the neighbouring orders has the smallest estimate. Until
+/// the history is long enough for the estimates, the order rises by one
+/// with each step.
+pub(crate) fn next_order(history: &[Vec<f64>], previous: usize, max_order: usize) -> usize {
+    let max_order = max_order.clamp(1, MAX_BDF_ORDER);
+    let Some(current) = error_estimate(history, previous) else {
+        return (previous + 1).min(max_order);
+    };
+    let lower = match previous {
+        1 => None,
+        _ => error_estimate(history, previous - 1),
+    };
+    if lower.is_some_and(|lower| lower < current) {
+        return previous - 1;
+    }
+    if previous < max_order {
+        match error_estimate(history, previous + 1) {
+            Some(higher) if higher >= current => {}
+            _ => return previous + 1,
+        }
+    }
+    previous
+}
+
+/// Estimated local error of a BDF step of `order`, relative to the most
+/// recent values, or `None` without enough history.
+fn error_estimate(history: &[Vec<f64>], order: usize) -> Option<f64> {
+    if order == 0 || history.len() < order + 2 {
+        return None;
+    }
+    // The (order + 1)th backward difference, with binomial coefficients
+    let mut difference = vec![0.0; history[0].len()];
+    let mut coefficient = 1.0;
+    for (j, values) in history[..order + 2].iter().enumerate() {
+        for (total, value) in difference.iter_mut().zip(values) {
+            *total += coefficient * value;
+        }
+        coefficient *= -((order + 1 - j) as f64) / (j + 1) as f64;
+    }
+    let error = difference
+        .iter()
+        .zip(&history[0])
+        .map(|(difference, value)| difference.abs() / (1.0 + value.abs()))
+        .fold(0.0, f64::max);
+    Some(error / (order + 1) as f64)
+}
+
+/// Forward difference approximation of the Jacobian `d f_i / d y_j`.
+fn jacobian<F>(derivative: &mut F, time: f64, values: &[f64], slope: &[f64]) -> Vec<Vec<f64>>
+where
+    F: FnMut(f64, &[f64]) -> Vec<f64>,
+{
+    let size = values.len();
+    let mut jacobian = vec![vec![0.0; size]; size];
+    let mut perturbed = values.to_vec();
+
+    for j in 0..size {
+        let epsilon = f64::EPSILON.sqrt() * values[j].abs().max(1.0);
+        perturbed[j] = values[j] + epsilon;
+        let shifted = derivative(time, &perturbed);
+        for i in 0..size {
+            jacobian[i][j] = (shifted[i] - slope[i]) / epsilon;
+        }
+        perturbed[j] = values[j];
+    }
+    jacobian
+}
+
+/// Solves `matrix * x = rhs` by Gaussian elimination with partial pivoting.
+/// Returns `None` if the matrix is singular.
+fn solve(matrix: &mut [Vec<f64>], mut rhs: Vec<f64>) -> Option<Vec<f64>> {
+    let size = rhs.len();
+
+    for col in 0..size {
+        let pivot = (col..size).max_by(|&a, &b| {
+            matrix[a][col]
+                .abs()
+                .partial_cmp(&matrix[b][col].abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })?;
+        if matrix[pivot][col] == 0.0 || !matrix[pivot][col].is_finite() {
+            return None;
+        }
+        matrix.swap(col, pivot);
+        rhs.swap(col, pivot);
+
+        let (upper, lower) = matrix.split_at_mut(col + 1);
+        let pivot_row = &upper[col];
+        for (offset, row) in lower.iter_mut().enumerate() {
+            let factor = row[col] / pivot_row[col];
+            if factor == 0.0 {
+                continue;
+            }
+            for (entry, pivot_entry) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *entry -= factor * pivot_entry;
+            }
+            rhs[col + 1 + offset] -= factor * rhs[col];
+        }
+    }
+
+    let mut solution = vec![0.0; size];
+    for row in (0..size).rev() {
+        let sum: f64 = (row + 1..size).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (rhs[row] - sum) / matrix[row][row];
+    }
+    Some(solution)
+}
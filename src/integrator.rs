@@ -0,0 +1,475 @@
+use std::ops::AddAssign;
+
+use crate::implicit::{bdf_step, next_order};
+
+/// The numerical method used to advance stocks from one time step to the next.
+///
+/// The fixed-step methods advance by `Model::time_step`. Higher-order methods
+/// evaluate the flows several times per step, which allows much larger time
+/// steps for the same accuracy.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Integrator {
+    /// Forward Euler, first order. One flow evaluation per step.
+    #[default]
+    Euler,
+    /// Heun's method (explicit trapezoidal RK2), second order.
+    Heun,
+    /// Explicit midpoint method (RK2), second order.
+    Midpoint,
+    /// Classic fourth order Runge-Kutta. Four flow evaluations per step.
+    RungeKutta4,
+    /// Adaptive Dormand-Prince RK5(4). The internal step size is chosen to
+    /// keep the local error within the given tolerances, and results are
+    /// interpolated back onto the `Model::time_step` output grid.
+    DormandPrince(AdaptiveSettings),
+    /// Implicit backward Euler, first order. Stable for stiff models at
+    /// step sizes where explicit methods blow up.
+    BackwardEuler,
+    /// Implicit variable-order backward differentiation formula. The order
+    /// starts at 1 and rises as history becomes available, then moves up
+    /// or down by one per step to the order with the smallest error
+    /// estimated from the recent values. It stays within `max_order`, which
+    /// must be from 1 to 5.
+    Bdf { max_order: usize },
+}
+
+/// Error tolerances and step size bounds for adaptive integration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSettings {
+    pub absolute_tolerance: f64,
+    pub relative_tolerance: f64,
+    /// Smallest allowed step. Steps at this size are accepted even when
+    /// they exceed the tolerances.
+    pub min_step: f64,
+    /// Largest allowed step
+    pub max_step: f64,
+}
+
+impl Default for AdaptiveSettings {
+    fn default() -> Self {
+        Self {
+            absolute_tolerance: 1e-6,
+            relative_tolerance: 1e-3,
+            min_step: 1e-9,
+            max_step: f64::INFINITY,
+        }
+    }
+}
+
+impl AdaptiveSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_tolerances(mut self, absolute: f64, relative: f64) -> Self {
+        self.absolute_tolerance = absolute;
+        self.relative_tolerance = relative;
+        self
+    }
+
+    pub fn with_min_step(mut self, min_step: f64) -> Self {
+        self.min_step = min_step;
+        self
+    }
+
+    pub fn with_max_step(mut self, max_step: f64) -> Self {
+        self.max_step = max_step;
+        self
+    }
+}
+
+/// Adaptive Dormand-Prince integration that continues from where the last
+/// call stopped with the step size it reached.
+pub(crate) struct AdaptiveSolver {
+    settings: AdaptiveSettings,
+    /// Size of the next step, once a step was accepted
+    step: Option<f64>,
+    pub(crate) stats: SolverStats,
+}
+
+impl AdaptiveSolver {
+    pub(crate) fn new(settings: AdaptiveSettings) -> Self {
+        Self {
+            settings,
+            step: None,
+            stats: SolverStats::default(),
+        }
+    }
+
+    /// Integrates from `start` until the last of `output_times`, calling
+    /// `output` with the interpolated values at each output time.
+    ///
+    /// `constrain` is applied to every accepted step and reports whether it
+    /// changed any value. Returns the values at the last output time.
+    pub(crate) fn integrate<F, C, O>(
+        &mut self,
+        start: f64,
+        values: Vec<f64>,
+        output_times: &[f64],
+        mut derivative: F,
+        mut constrain: C,
+        mut output: O,
+    ) -> Vec<f64>
+    where
+        F: FnMut(f64, &[f64]) -> Vec<f64>,
+        C: FnMut(&mut [f64]) -> bool,
+        O: FnMut(f64, &[f64]),
+    {
+        let settings = self.settings;
+        let Some(&end) = output_times.last() else {
+            return values;
+        };
+
+        let mut time = start;
+        let mut values = values;
+        let mut slope = derivative(time, &values);
+        let mut step = self.step.unwrap_or_else(|| {
+            (output_times[0] - start).clamp(settings.min_step, settings.max_step)
+        });
+        let mut next_output = 0;
+
+        while next_output < output_times.len() {
+            let last_step = end - time <= step;
+            let h = if last_step { end - time } else { step };
+
+            let mut stages = vec![slope.clone()];
+            for (c, row) in DP_C.iter().zip(DP_A.iter()) {
+                let terms: Vec<(f64, &Vec<f64>)> = row
+                    .iter()
+                    .zip(stages.iter())
+                    .map(|(a, k)| (a * h, k))
+                    .collect();
+                let stage_values = offset(&values, &terms);
+                stages.push(derivative(time + c * h, &stage_values));
+            }
+            // FSAL: the last stage is evaluated at the 5th order solution
+            let proposal = offset(
+                &values,
+                &DP_A[5]
+                    .iter()
+                    .zip(stages.iter())
+                    .map(|(a, k)| (a * h, k))
+                    .collect::<Vec<_>>(),
+            );
+
+            let error = settings.error_norm(&values, &proposal, &stages, h);
+            // A step from values that are already not finite cannot be
+            // improved by shrinking it, but one that produced them can
+            let error = match error.is_nan() {
+                true if values.iter().all(|value| value.is_finite()) => f64::INFINITY,
+                true => 0.0,
+                false => error,
+            };
+
+            let accepted = error <= 1.0 || h <= settings.min_step;
+            if accepted {
+                self.stats.accepted_steps += 1;
+                let new_time = if last_step { end } else { time + h };
+                let mut new_values = proposal;
+                let mut new_slope = stages.pop().unwrap_or_default();
+                if constrain(&mut new_values) {
+                    new_slope = derivative(new_time, &new_values);
+                }
+
+                while next_output < output_times.len() && output_times[next_output] <= new_time {
+                    let t = output_times[next_output];
+                    let mut dense =
+                        hermite(&values, &new_values, &slope, &new_slope, h, (t - time) / h);
+                    constrain(&mut dense);
+                    output(t, &dense);
+                    next_output += 1;
+                }
+
+                time = new_time;
+                values = new_values;
+                slope = new_slope;
+            } else {
+                self.stats.rejected_steps += 1;
+            }
+
+            let factor = if error == 0.0 {
+                5.0
+            } else {
+                (0.9 * error.powf(-0.2)).clamp(0.2, 5.0)
+            };
+            let factor = if error > 1.0 { factor.min(1.0) } else { factor };
+            let next = (h * factor).clamp(settings.min_step, settings.max_step);
+            // A last step shortened to end on time says little about the
+            // step size the next call can take
+            step = if accepted && last_step {
+                next.max(step)
+            } else {
+                next
+            };
+        }
+
+        self.step = Some(step);
+        values
+    }
+}
+
+impl AdaptiveSettings {
+    /// Root mean square of the embedded error estimate, scaled by the
+    /// tolerances. Values at or below 1.0 are acceptable.
+    fn error_norm(&self, values: &[f64], proposal: &[f64], stages: &[Vec<f64>], h: f64) -> f64 {
+        if values.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = (0..values.len())
+            .map(|i| {
+                let estimate: f64 = DP_E.iter().zip(stages).map(|(e, k)| e * k[i]).sum::<f64>() * h;
+                let scale = self.absolute_tolerance
+                    + self.relative_tolerance * values[i].abs().max(proposal[i].abs());
+                (estimate / scale).powi(2)
+            })
+            .sum();
+        (sum / values.len() as f64).sqrt()
+    }
+}
+
+/// Step counts reported by the solver for one simulation run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SolverStats {
+    pub accepted_steps: usize,
+    /// Steps discarded by an adaptive solver because the error was too large
+    pub rejected_steps: usize,
+    /// Implicit steps whose Newton iteration did not converge, and which
+    /// were retried as smaller steps
+    pub convergence_failures: usize,
+    /// Number of times the flows were evaluated for the whole model
+    pub evaluations: usize,
+}
+
+impl AddAssign for SolverStats {
+    fn add_assign(&mut self, other: SolverStats) {
+        self.accepted_steps += other.accepted_steps;
+        self.rejected_steps += other.rejected_steps;
+        self.convergence_failures += other.convergence_failures;
+        self.evaluations += other.evaluations;
+    }
+}
+
+/// Dormand-Prince nodes for stages 2 through 7.
+const DP_C: [f64; 6] = [1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+
+/// Dormand-Prince coefficients for stages 2 through 7. The last row is also
+/// the 5th order solution.
+const DP_A: [&[f64]; 6] = [
+    &[1.0 / 5.0],
+    &[3.0 / 40.0, 9.0 / 40.0],
+    &[44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0],
+    &[
+        19372.0 / 6561.0,
+        -25360.0 / 2187.0,
+        64448.0 / 6561.0,
+        -212.0 / 729.0,
+    ],
+    &[
+        9017.0 / 3168.0,
+        -355.0 / 33.0,
+        46732.0 / 5247.0,
+        49.0 / 176.0,
+        -5103.0 / 18656.0,
+    ],
+    &[
+        35.0 / 384.0,
+        0.0,
+        500.0 / 1113.0,
+        125.0 / 192.0,
+        -2187.0 / 6784.0,
+        11.0 / 84.0,
+    ],
+];
+
+/// Difference between the 5th and 4th order weights, used for the error
+/// estimate.
+const DP_E: [f64; 7] = [
+    71.0 / 57600.0,
+    0.0,
+    -71.0 / 16695.0,
+    71.0 / 1920.0,
+    -17253.0 / 339200.0,
+    22.0 / 525.0,
+    -1.0 / 40.0,
+];
+
+impl Integrator {
+    /// Advances `values` at `time` by one step of size `dt` of a fixed-step
+    /// explicit method. Adaptive methods are stepped by `AdaptiveSolver`
+    /// and implicit methods by `Stepper`.
+    ///
+    /// `derivative` returns the rate of change of every value for a given
+    /// time and set of values, in the same order as `values`.
+    pub(crate) fn step<F>(&self, time: f64, values: &[f64], dt: f64, mut derivative: F) -> Vec<f64>
+    where
+        F: FnMut(f64, &[f64]) -> Vec<f64>,
+    {
+        match self {
+            Integrator::Euler => {
+                let k1 = derivative(time, values);
+                offset(values, &[(dt, &k1)])
+            }
+            Integrator::Heun => {
+                let k1 = derivative(time, values);
+                let k2 = derivative(time + dt, &offset(values, &[(dt, &k1)]));
+                offset(values, &[(dt / 2.0, &k1), (dt / 2.0, &k2)])
+            }
+            Integrator::Midpoint => {
+                let k1 = derivative(time, values);
+                let k2 = derivative(time + dt / 2.0, &offset(values, &[(dt / 2.0, &k1)]));
+                offset(values, &[(dt, &k2)])
+            }
+            Integrator::RungeKutta4 => {
+                let k1 = derivative(time, values);
+                let k2 = derivative(time + dt / 2.0, &offset(values, &[(dt / 2.0, &k1)]));
+                let k3 = derivative(time + dt / 2.0, &offset(values, &[(dt / 2.0, &k2)]));
+                let k4 = derivative(time + dt, &offset(values, &[(dt, &k3)]));
+                offset(
+                    values,
+                    &[
+                        (dt / 6.0, &k1),
+                        (dt / 3.0, &k2),
+                        (dt / 3.0, &k3),
+                        (dt / 6.0, &k4),
+                    ],
+                )
+            }
+            Integrator::DormandPrince(_) => {
+                unreachable!("adaptive methods are stepped by AdaptiveSolver")
+            }
+            Integrator::BackwardEuler | Integrator::Bdf { .. } => {
+                unreachable!("implicit methods are stepped by Stepper")
+            }
+        }
+    }
+}
+
+/// Number of times an implicit step is halved before giving up.
+const MAX_STEP_CUTS: u32 = 10;
+
+/// Advances values with a fixed step size, keeping the history of previous
+/// steps that multistep methods need.
+pub(crate) struct Stepper {
+    method: Integrator,
+    /// Values at the start of recent steps, most recent first
+    history: Vec<Vec<f64>>,
+    /// Start times of the steps in `history`
+    times: Vec<f64>,
+    /// Orders of the steps from the values in `history`
+    orders: Vec<usize>,
+    pub(crate) stats: SolverStats,
+}
+
+impl Stepper {
+    pub(crate) fn new(method: Integrator) -> Self {
+        Self {
+            method,
+            history: Vec::new(),
+            times: Vec::new(),
+            orders: Vec::new(),
+            stats: SolverStats::default(),
+        }
+    }
+
+    /// Takes one step, or returns `None` if an implicit method does not
+    /// converge even on a much smaller step.
+    ///
+    /// Steps from a time already in the history replace it and the steps
+    /// after it, so that a step that failed can be taken again.
+    pub(crate) fn step<F>(
+        &mut self,
+        time: f64,
+        values: &[f64],
+        dt: f64,
+        mut derivative: F,
+    ) -> Option<Vec<f64>>
+    where
+        F: FnMut(f64, &[f64]) -> Vec<f64>,
+    {
+        let max_order = match self.method {
+            Integrator::BackwardEuler => 1,
+            Integrator::Bdf { max_order } => max_order,
+            method => return Some(method.step(time, values, dt, derivative)),
+        };
+        let retaken = self
+            .times
+            .iter()
+            .take_while(|start| **start >= time)
+            .count();
+        self.history.drain(..retaken);
+        self.times.drain(..retaken);
+        self.orders.drain(..retaken);
+        self.history.insert(0, values.to_vec());
+        self.times.insert(0, time);
+
+        let previous = self.orders.first().copied().unwrap_or_default();
+        let order = next_order(&self.history, previous, max_order);
+        self.orders.insert(0, order);
+        // Estimates for the next higher order need two more values
+        let kept = max_order + 2;
+        self.history.truncate(kept);
+        self.times.truncate(kept);
+        self.orders.truncate(kept);
+        match bdf_step(&self.history[..order], time, dt, &mut derivative) {
+            Some(values) => Some(values),
+            None => self.cut_step(time, values, dt, &mut derivative, 1),
+        }
+    }
+
+    /// Retries a failed implicit step as two backward Euler steps of half
+    /// the size, halving again where those fail.
+    fn cut_step<F>(
+        &mut self,
+        time: f64,
+        values: &[f64],
+        dt: f64,
+        derivative: &mut F,
+        cuts: u32,
+    ) -> Option<Vec<f64>>
+    where
+        F: FnMut(f64, &[f64]) -> Vec<f64>,
+    {
+        self.stats.convergence_failures += 1;
+        if cuts > MAX_STEP_CUTS {
+            return None;
+        }
+        let half = dt / 2.0;
+        let mut values = values.to_vec();
+        for start in [time, time + half] {
+            values = match bdf_step(&[values.clone()], start, half, &mut *derivative) {
+                Some(values) => values,
+                None => self.cut_step(start, &values, half, derivative, cuts + 1)?,
+            };
+        }
+        Some(values)
+    }
+}
+
+/// Computes `values + sum(weight * slope)` element-wise.
+fn offset(values: &[f64], terms: &[(f64, &Vec<f64>)]) -> Vec<f64> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            terms
+                .iter()
+                .fold(*value, |acc, (weight, slope)| acc + slope[i] * weight)
+        })
+        .collect()
+}
+
+/// Cubic Hermite interpolation between two solution points at fraction
+/// `theta` of the step `h`.
+fn hermite(y0: &[f64], y1: &[f64], f0: &[f64], f1: &[f64], h: f64, theta: f64) -> Vec<f64> {
+    (0..y0.len())
+        .map(|i| {
+            (1.0 - theta) * y0[i]
+                + theta * y1[i]
+                + theta
+                    * (theta - 1.0)
+                    * ((1.0 - 2.0 * theta) * (y1[i] - y0[i])
+                        + (theta - 1.0) * h * f0[i]
+                        + theta * h * f1[i])
+        })
+        .collect()
+}
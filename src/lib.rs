@@ -1,4 +1,37 @@
-use std::collections::HashMap;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod compile;
+mod csv;
+mod delay;
+mod expr;
+mod graph;
+mod implicit;
+mod integrator;
+mod lookup;
+#[cfg(any(feature = "json", feature = "toml", feature = "yaml"))]
+mod model_file;
+mod simulator;
+mod units;
+mod validate;
+mod vensim;
+mod xmile;
+mod xml;
+
+pub use csv::{CsvOptions, Layout};
+pub use expr::{BinaryOp, EvalError, Expr, Function, ParseError, UnaryOp};
+pub use indexmap::IndexMap;
+pub use integrator::{AdaptiveSettings, Integrator, SolverStats};
+pub use lookup::{Interpolation, LookupTable, OutOfRange};
+#[cfg(any(feature = "json", feature = "toml", feature = "yaml"))]
+pub use model_file::{ModelFileError, MODEL_FILE_VERSION};
+pub use simulator::Simulator;
+pub use units::{Unit, UnitError};
+pub use validate::ModelError;
+pub use vensim::VensimError;
+pub use xmile::XmileError;
+pub use xml::XmlError;
+
+use compile::Evaluator;
 
 #[derive(Debug, Clone)]
 pub struct Stock {
@@ -34,6 +67,18 @@ impl Stock {
         self.max_value = Some(max);
         self
     }
+
+    /// Restricts a value to this stock's min/max constraints.
+    pub fn clamp(&self, mut value: f64) -> f64 {
+        if let Some(min) = self.min_value {
+            value = value.max(min);
+        }
+
+        if let Some(max) = self.max_value {
+            value = value.min(max);
+        }
+        value
+    }
 }
 
 /// represents multiple related stocks.
@@ -141,6 +186,46 @@ pub enum FlowFunction {
         /// The ID of the stock whose value is used as input
         input_stock: String,
     },
+
+    /// A flow rate computed from an equation, see `Expr`.
+    Expression(Expr),
+
+    /// A graphical function: the lookup table is evaluated at the value of
+    /// the input equation.
+    Lookup {
+        table: LookupTable,
+        input: Expr,
+    },
+}
+
+impl FlowFunction {
+    /// Evaluates the function against a system state.
+    pub fn evaluate(&self, state: &SystemState) -> Result<f64, EvalError> {
+        match self {
+            FlowFunction::Constant(rate) => Ok(*rate),
+            FlowFunction::Linear {
+                slope,
+                intercept,
+                input_stock,
+            } => {
+                let input_value = state.get_stock_value(input_stock).unwrap_or(0.0);
+                Ok(slope * input_value + intercept)
+            }
+            FlowFunction::Expression(expr) => expr.eval(state),
+            FlowFunction::Lookup { table, input } => table.evaluate(input.eval(state)?),
+        }
+    }
+
+    /// IDs of the stocks, auxiliaries and flows this function reads.
+    pub fn dependencies(&self) -> Vec<&str> {
+        match self {
+            FlowFunction::Constant(_) => Vec::new(),
+            FlowFunction::Linear { input_stock, .. } => vec![input_stock.as_str()],
+            FlowFunction::Expression(expr) | FlowFunction::Lookup { input: expr, .. } => {
+                expr.variables()
+            }
+        }
+    }
 }
 
 impl Flow {
@@ -181,6 +266,67 @@ impl Flow {
         }
     }
 
+    /// Creates a new flow whose rate is computed from an equation.
+    ///
+    /// ```
+    /// # use oxidyn::Flow;
+    /// let births = Flow::expression(
+    ///     "births",
+    ///     "Births",
+    ///     "fertility * population * (1 - population / capacity)",
+    ///     "people/year",
+    /// )
+    /// .unwrap()
+    /// .to_stock("population");
+    /// ```
+    pub fn expression(
+        id: &str,
+        name: &str,
+        equation: &str,
+        units: &str,
+    ) -> Result<Self, ParseError> {
+        Ok(Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            from_stock: None,
+            to_stock: None,
+            rate_function: FlowFunction::Expression(Expr::parse(equation)?),
+            units: units.to_string(),
+        })
+    }
+
+    /// Creates a new flow whose rate is looked up from a table, using the
+    /// value of a stock, auxiliary or flow as the input.
+    pub fn lookup(id: &str, name: &str, table: LookupTable, input: &str, units: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            from_stock: None,
+            to_stock: None,
+            rate_function: FlowFunction::Lookup {
+                table,
+                input: Expr::Variable(input.to_string()),
+            },
+            units: units.to_string(),
+        }
+    }
+
+    /// Creates a new flow driven by a time series: the table's x values are
+    /// times, and the rate is interpolated at the current simulation time.
+    pub fn time_series(id: &str, name: &str, table: LookupTable, units: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            from_stock: None,
+            to_stock: None,
+            rate_function: FlowFunction::Lookup {
+                table,
+                input: Expr::Time,
+            },
+            units: units.to_string(),
+        }
+    }
+
     pub fn from_stock(mut self, stock_id: &str) -> Self {
         self.from_stock = Some(stock_id.to_string());
         self
@@ -192,38 +338,131 @@ impl Flow {
     }
 
     /// Calculates the current flow rate given a system state.
+    ///
+    /// Returns NaN if the rate function cannot be evaluated, see
+    /// `try_calculate_rate`.
     pub fn calculate_rate(&self, state: &SystemState) -> f64 {
-        match &self.rate_function {
-            FlowFunction::Constant(rate) => *rate,
-            FlowFunction::Linear {
-                slope,
-                intercept,
-                input_stock,
-            } => {
-                let input_value = state.get_stock_value(input_stock).unwrap_or(0.0);
-                slope * input_value + intercept
-            }
+        self.try_calculate_rate(state).unwrap_or(f64::NAN)
+    }
+
+    /// Calculates the current flow rate given a system state, reporting why
+    /// the rate function could not be evaluated.
+    pub fn try_calculate_rate(&self, state: &SystemState) -> Result<f64, EvalError> {
+        self.rate_function.evaluate(state)
+    }
+}
+
+/// An auxiliary (converter) is a variable computed from stocks, other
+/// auxiliaries and flows at every step, without being integrated.
+///
+/// Flow and auxiliary equations can refer to an auxiliary by its ID.
+#[derive(Debug, Clone)]
+pub struct Auxiliary {
+    pub id: String,
+    pub name: String,
+    pub function: FlowFunction,
+    pub units: String,
+}
+
+impl Auxiliary {
+    /// Creates a new auxiliary with a constant value.
+    pub fn constant(id: &str, name: &str, value: f64, units: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            function: FlowFunction::Constant(value),
+            units: units.to_string(),
         }
     }
+
+    /// Creates a new auxiliary whose value is computed from an equation.
+    pub fn expression(
+        id: &str,
+        name: &str,
+        equation: &str,
+        units: &str,
+    ) -> Result<Self, ParseError> {
+        Ok(Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            function: FlowFunction::Expression(Expr::parse(equation)?),
+            units: units.to_string(),
+        })
+    }
+
+    /// Creates a new auxiliary whose value is looked up from a table, using
+    /// the value of a stock, auxiliary or flow as the input.
+    pub fn lookup(id: &str, name: &str, table: LookupTable, input: &str, units: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            function: FlowFunction::Lookup {
+                table,
+                input: Expr::Variable(input.to_string()),
+            },
+            units: units.to_string(),
+        }
+    }
+
+    /// Creates a new auxiliary driven by a time series: the table's x values
+    /// are times, and the value is interpolated at the current simulation
+    /// time.
+    pub fn time_series(id: &str, name: &str, table: LookupTable, units: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            function: FlowFunction::Lookup {
+                table,
+                input: Expr::Time,
+            },
+            units: units.to_string(),
+        }
+    }
+
+    /// Calculates the current value given a system state.
+    ///
+    /// Returns NaN if the function cannot be evaluated, see
+    /// `try_calculate_value`.
+    pub fn calculate_value(&self, state: &SystemState) -> f64 {
+        self.try_calculate_value(state).unwrap_or(f64::NAN)
+    }
+
+    pub fn try_calculate_value(&self, state: &SystemState) -> Result<f64, EvalError> {
+        self.function.evaluate(state)
+    }
 }
 
 /// Represents the current state of the model.
 #[derive(Debug, Clone, Default)]
 pub struct SystemState {
-    /// Stocks indexed by IDs
-    pub stocks: HashMap<String, Stock>,
+    /// Stocks indexed by IDs, in the order they were added
+    pub stocks: IndexMap<String, Stock>,
     /// Current sim time
     pub time: f64,
+    /// Time step of the running simulation, available to equations as `DT`
+    pub dt: f64,
+    /// Values of computed variables (auxiliaries and flow rates) at the
+    /// current time, indexed by IDs
+    pub variables: IndexMap<String, f64>,
+    /// Lookup tables that equations can call, indexed by IDs
+    pub lookups: IndexMap<String, LookupTable>,
+    /// Input histories of fixed delays, indexed by the IDs of their outputs
+    pub(crate) histories: IndexMap<String, delay::History>,
 }
 
 impl SystemState {
     pub fn new() -> Self {
         Self {
-            stocks: HashMap::new(),
+            stocks: IndexMap::new(),
             time: 0.0,
+            dt: 0.0,
+            variables: IndexMap::new(),
+            lookups: IndexMap::new(),
+            histories: IndexMap::new(),
         }
     }
 
+    /// IDs of the stocks, in the order they were added.
     pub fn get_stock_names(&self) -> Vec<&str> {
         self.stocks.keys().map(|k| k.as_str()).collect()
     }
@@ -233,6 +472,12 @@ impl SystemState {
         self.stocks.get(stock_id).map(|stock| stock.current_value)
     }
 
+    /// Looks up the current value of a stock or computed variable by ID.
+    pub fn get_value(&self, id: &str) -> Option<f64> {
+        self.get_stock_value(id)
+            .or_else(|| self.variables.get(id).copied())
+    }
+
     // Sets the stock value
     pub fn set_stock_value(&mut self, stock_id: &str, value: f64) {
         if let Some(stock) = self.stocks.get_mut(stock_id) {
@@ -245,8 +490,33 @@ impl SystemState {
 pub struct Model {
     pub name: String,
     pub state: SystemState,
-    pub flows: HashMap<String, Flow>,
+    pub flows: IndexMap<String, Flow>,
+    pub auxiliaries: IndexMap<String, Auxiliary>,
+    /// Start of the simulation period. Results are reported at the start
+    /// time plus whole multiples of the time step.
+    pub start_time: f64,
     pub time_step: f64,
+    /// End of the simulation period, run to by `try_simulate_to_stop`.
+    /// `simulate` still takes the duration to run.
+    pub stop_time: Option<f64>,
+    /// Interval between saved results, a whole multiple of the time step
+    /// like Vensim's `SAVEPER`. Every time step is saved when not set.
+    pub save_interval: Option<f64>,
+    /// Units of the simulation time, "time" by default. Flow units are
+    /// checked against stock units per this unit, and rates declared per
+    /// another known time unit are converted to it.
+    pub time_units: String,
+    /// Numerical method used to advance the stocks, Euler by default
+    pub integrator: Integrator,
+    /// Whether the internal stocks created for delay and smoothing functions
+    /// are included in simulation results. Their IDs start with `#`.
+    pub record_internal: bool,
+    /// Whether simulation results include the net flow of each recorded
+    /// stock, as `net(<stock>)`
+    pub record_net_flows: bool,
+    /// IDs of stocks, flows and auxiliaries that replaced an earlier one,
+    /// reported by `validate`
+    duplicates: Vec<String>,
 }
 
 impl Model {
@@ -254,102 +524,391 @@ impl Model {
         Self {
             name: name.to_string(),
             state: SystemState::new(),
-            flows: HashMap::new(),
+            flows: IndexMap::new(),
+            auxiliaries: IndexMap::new(),
+            start_time: 0.0,
             time_step: 0.1,
+            stop_time: None,
+            save_interval: None,
+            time_units: "time".to_string(),
+            integrator: Integrator::Euler,
+            record_internal: false,
+            record_net_flows: false,
+            duplicates: Vec::new(),
         }
     }
 
+    /// Adds a stock to the model. A stock with the same ID replaces the
+    /// existing one, which `validate` reports.
     pub fn add_stock(&mut self, stock: Stock) -> &mut Self {
-        self.state.stocks.insert(stock.id.clone(), stock);
+        if let Some(replaced) = self.state.stocks.insert(stock.id.clone(), stock) {
+            self.duplicates.push(replaced.id);
+        }
         self
     }
 
     /// Adds a stock array to the model by expanding it into individual stocks.
     pub fn add_stock_array(&mut self, stock_array: StockArray) -> &mut Self {
         for stock in stock_array.expand() {
-            self.state.stocks.insert(stock.id.clone(), stock);
+            self.add_stock(stock);
         }
         self
     }
 
+    /// Adds a flow to the model. A flow with the same ID replaces the
+    /// existing one, which `validate` reports.
     pub fn add_flow(&mut self, flow: Flow) -> &mut Self {
-        self.flows.insert(flow.id.clone(), flow);
+        if let Some(replaced) = self.flows.insert(flow.id.clone(), flow) {
+            self.duplicates.push(replaced.id);
+        }
+        self
+    }
+
+    /// Adds an auxiliary to the model. An auxiliary with the same ID
+    /// replaces the existing one, which `validate` reports.
+    pub fn add_auxiliary(&mut self, auxiliary: Auxiliary) -> &mut Self {
+        if let Some(replaced) = self.auxiliaries.insert(auxiliary.id.clone(), auxiliary) {
+            self.duplicates.push(replaced.id);
+        }
+        self
+    }
+
+    /// IDs of the stocks, in the order they were added, without the
+    /// internal stocks of delay and smoothing functions.
+    pub fn stock_ids(&self) -> impl Iterator<Item = &str> {
+        self.state
+            .stocks
+            .keys()
+            .map(String::as_str)
+            .filter(|id| !delay::is_internal(id))
+    }
+
+    /// IDs of the flows, in the order they were added.
+    pub fn flow_ids(&self) -> impl Iterator<Item = &str> {
+        self.flows.keys().map(String::as_str)
+    }
+
+    /// IDs of the auxiliaries, in the order they were added.
+    pub fn auxiliary_ids(&self) -> impl Iterator<Item = &str> {
+        self.auxiliaries.keys().map(String::as_str)
+    }
+
+    /// Adds a named lookup table that flow and auxiliary equations can call
+    /// like a function. A table with the same ID replaces the existing one,
+    /// which `validate` reports.
+    pub fn add_lookup(&mut self, id: &str, table: LookupTable) -> &mut Self {
+        if self.state.lookups.insert(id.to_string(), table).is_some() {
+            self.duplicates.push(id.to_string());
+        }
+        self
+    }
+
+    /// Sets the start of the simulation period and moves the current time
+    /// to it. Stock values are not changed.
+    pub fn set_start_time(&mut self, time: f64) -> &mut Self {
+        self.start_time = time;
+        self.state.time = time;
+        self
+    }
+
+    pub fn set_stop_time(&mut self, time: f64) -> &mut Self {
+        self.stop_time = Some(time);
         self
     }
+
     pub fn set_time_step(&mut self, dt: f64) -> &mut Self {
         self.time_step = dt;
         self
     }
 
-    pub fn simulate(&mut self, duration: f64) -> SimulationResult {
-        let mut result = SimulationResult::new();
-        let end_time = self.state.time + duration;
+    pub fn set_save_interval(&mut self, interval: f64) -> &mut Self {
+        self.save_interval = Some(interval);
+        self
+    }
 
-        result.record_state(self.state.time, &self.state);
-        while self.state.time < end_time {
-            let snapshot = self.state.clone();
+    pub fn set_integrator(&mut self, integrator: Integrator) -> &mut Self {
+        self.integrator = integrator;
+        self
+    }
 
-            let mut derivatives = HashMap::new();
-            for stock_id in self.state.stocks.keys() {
-                derivatives.insert(stock_id.clone(), 0.0);
-            }
+    pub fn set_record_internal(&mut self, record_internal: bool) -> &mut Self {
+        self.record_internal = record_internal;
+        self
+    }
 
-            for flow in self.flows.values() {
-                let rate = flow.calculate_rate(&snapshot);
+    pub fn set_record_net_flows(&mut self, record_net_flows: bool) -> &mut Self {
+        self.record_net_flows = record_net_flows;
+        self
+    }
 
-                if let Some(from_stock) = &flow.from_stock {
-                    if let Some(derivative) = derivatives.get_mut(from_stock) {
-                        *derivative -= rate;
-                    }
-                }
-                if let Some(to_stock) = &flow.to_stock {
-                    if let Some(derivative) = derivatives.get_mut(to_stock) {
-                        *derivative += rate;
-                    }
+    /// Returns a copy of the model with delay and smoothing functions
+    /// replaced by internal stocks and flows, and fixed delays by variables
+    /// read from their input histories.
+    ///
+    /// Internal stocks and histories missing from `state` are added and
+    /// initialized for the current time. Those already present keep their
+    /// values, so consecutive simulations continue where the last one
+    /// stopped.
+    ///
+    /// Fails on circular definitions, whose elements would read values
+    /// not yet calculated in the same evaluation.
+    fn expand(&self, state: &mut SystemState) -> Result<Model, ModelError> {
+        let expansion = delay::expand(self)?;
+        if let Some(ids) = expansion.model.circular_definitions().into_iter().next() {
+            return Err(ModelError::CircularDefinition { ids });
+        }
+
+        let internal: Vec<&String> = expansion
+            .sites
+            .iter()
+            .flat_map(|site| &site.stocks)
+            .collect();
+        state
+            .stocks
+            .retain(|id, _| !delay::is_internal(id) || internal.contains(&id));
+
+        let mut new_sites = Vec::new();
+        for site in &expansion.sites {
+            if !state.stocks.contains_key(&site.stocks[0]) {
+                for id in &site.stocks {
+                    state.stocks.insert(id.clone(), Stock::new(id, id, 0.0, ""));
                 }
+                new_sites.push(site);
             }
+        }
 
-            for (stock_id, derivative) in derivatives {
-                if let Some(stock) = self.state.stocks.get_mut(&stock_id) {
-                    let mut new_value = stock.current_value + derivative * self.time_step;
+        state
+            .histories
+            .retain(|id, _| expansion.fixed_delays.iter().any(|site| &site.id == id));
+        let mut new_fixed_delays = Vec::new();
+        for site in &expansion.fixed_delays {
+            match state.histories.get_mut(&site.id) {
+                Some(history) => history.input = site.input.clone(),
+                None => {
+                    state.variables.insert(site.id.clone(), 0.0);
+                    new_fixed_delays.push(site);
+                }
+            }
+        }
 
-                    if let Some(min) = stock.min_value {
-                        new_value = new_value.max(min);
+        // The input of one call can depend on the output of another, so
+        // repeat until every call has seen the initialized outputs it uses
+        for _ in 0..new_sites.len() + new_fixed_delays.len() {
+            for site in &new_fixed_delays {
+                let mut evaluator = Evaluator::new(&expansion.model, state);
+                evaluator.evaluate(state.time, &evaluator.values())?;
+                let history = site.history(&evaluator.state(state))?;
+                state.histories.insert(site.id.clone(), history);
+            }
+            for site in &new_sites {
+                let mut evaluator = Evaluator::new(&expansion.model, state);
+                evaluator.evaluate(state.time, &evaluator.values())?;
+                let initial = site.initial_values(&evaluator.state(state))?;
+                for (id, value) in site.stocks.iter().zip(initial) {
+                    if let Some(stock) = state.stocks.get_mut(id) {
+                        stock.initial_value = value;
+                        stock.current_value = value;
                     }
+                }
+            }
+        }
+        Ok(expansion.model)
+    }
 
-                    if let Some(max) = stock.max_value {
-                        new_value = new_value.min(max);
-                    }
+    /// Loops of auxiliaries and flows that are defined in terms of each
+    /// other without a stock or stateful function in between. Each loop
+    /// lists its IDs from the first one reached, in the order auxiliaries
+    /// and then flows were added, each followed by one it depends on.
+    ///
+    /// Stateful functions only break loops once they are expanded into
+    /// internal stocks.
+    pub(crate) fn circular_definitions(&self) -> Vec<Vec<String>> {
+        fn visit<'a>(
+            id: &'a str,
+            model: &'a Model,
+            path: &mut Vec<&'a str>,
+            done: &mut Vec<&'a str>,
+            loops: &mut Vec<Vec<String>>,
+        ) {
+            if done.contains(&id) {
+                return;
+            }
+            if let Some(start) = path.iter().position(|visiting| *visiting == id) {
+                loops.push(path[start..].iter().map(|id| id.to_string()).collect());
+                return;
+            }
+            let function = match (model.auxiliaries.get(id), model.flows.get(id)) {
+                (Some(auxiliary), _) => &auxiliary.function,
+                (None, Some(flow)) => &flow.rate_function,
+                (None, None) => return,
+            };
+            path.push(id);
+            for dependency in function.dependencies() {
+                visit(dependency, model, path, done, loops);
+            }
+            path.pop();
+            done.push(id);
+        }
 
-                    stock.current_value = new_value;
+        let mut path = Vec::new();
+        let mut done = Vec::new();
+        let mut loops = Vec::new();
+        for id in self.auxiliaries.keys().chain(self.flows.keys()) {
+            visit(id, self, &mut path, &mut done, &mut loops);
+        }
+        loops
+    }
+
+    /// Orders auxiliaries and flows so that every element is calculated
+    /// after the auxiliaries and flows its function depends on. Elements
+    /// without such dependencies keep their order, auxiliaries first.
+    /// Loops found by `circular_definitions` are broken where they are
+    /// first entered.
+    fn evaluation_order(&self) -> Vec<Element<'_>> {
+        fn visit<'a>(
+            element: Element<'a>,
+            model: &'a Model,
+            visited: &mut Vec<&'a str>,
+            order: &mut Vec<Element<'a>>,
+        ) {
+            let (id, function) = match element {
+                Element::Auxiliary(auxiliary) => (&auxiliary.id, &auxiliary.function),
+                Element::Flow(flow) => (&flow.id, &flow.rate_function),
+            };
+            if visited.contains(&id.as_str()) {
+                return;
+            }
+            visited.push(id);
+            for dependency in function.dependencies() {
+                if let Some(auxiliary) = model.auxiliaries.get(dependency) {
+                    visit(Element::Auxiliary(auxiliary), model, visited, order);
+                } else if let Some(flow) = model.flows.get(dependency) {
+                    visit(Element::Flow(flow), model, visited, order);
                 }
             }
+            order.push(element);
+        }
 
-            self.state.time += self.time_step;
-            result.record_state(self.state.time, &self.state);
+        let mut visited = Vec::new();
+        let mut order = Vec::new();
+        for auxiliary in self.auxiliaries.values() {
+            visit(
+                Element::Auxiliary(auxiliary),
+                self,
+                &mut visited,
+                &mut order,
+            );
+        }
+        for flow in self.flows.values() {
+            visit(Element::Flow(flow), self, &mut visited, &mut order);
+        }
+        order
+    }
+
+    /// Runs the model forward by `duration` time units.
+    ///
+    /// The model is not validated first, see `try_simulate`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a flow cannot be evaluated, for example when an equation
+    /// references an unknown variable.
+    pub fn simulate(&mut self, duration: f64) -> SimulationResult {
+        match self.run(duration) {
+            Ok(result) => result,
+            Err(error) => panic!("simulation of model '{}' failed: {}", self.name, error),
+        }
+    }
+
+    /// Validates the model and runs it forward by `duration` time units.
+    ///
+    /// Returns the problems found by `validate` without running an invalid
+    /// model, or the evaluation error that stopped the simulation. The
+    /// model state is only updated when the simulation succeeds.
+    pub fn try_simulate(&mut self, duration: f64) -> Result<SimulationResult, Vec<ModelError>> {
+        let errors = self.validate();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        self.run(duration).map_err(|error| vec![error])
+    }
+
+    /// Validates the model and runs it from the current time to the stop
+    /// time.
+    pub fn try_simulate_to_stop(&mut self) -> Result<SimulationResult, Vec<ModelError>> {
+        let Some(stop) = self.stop_time else {
+            return Err(vec![ModelError::MissingStopTime]);
+        };
+        self.try_simulate(stop - self.state.time)
+    }
+
+    /// Simulates the model, whose state is only changed on success.
+    fn run(&mut self, duration: f64) -> Result<SimulationResult, ModelError> {
+        let state = self.state.clone();
+        let end = self.state.time + duration;
+        let result = Simulator::new(self).and_then(|mut simulator| {
+            simulator.step_until(end)?;
+            Ok(simulator.into_result())
+        });
+        if result.is_err() {
+            self.state = state;
         }
         result
     }
 }
 
+/// An auxiliary or flow, in evaluation order.
+#[derive(Clone, Copy)]
+enum Element<'a> {
+    Auxiliary(&'a Auxiliary),
+    Flow(&'a Flow),
+}
+
+impl Element<'_> {
+    fn id(&self) -> &String {
+        match self {
+            Element::Auxiliary(auxiliary) => &auxiliary.id,
+            Element::Flow(flow) => &flow.id,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct SimulationResult {
     pub time_series: Vec<f64>,
-    pub stock_values: HashMap<String, Vec<f64>>,
+    pub stock_values: IndexMap<String, Vec<f64>>,
+    pub auxiliary_values: IndexMap<String, Vec<f64>>,
+    /// Rates of the flows, in the flows' own units
+    pub flow_values: IndexMap<String, Vec<f64>>,
+    /// Net rate of change of each stock, its inflows less its outflows in
+    /// stock units per time unit, keyed by `net(<stock>)`. Only recorded
+    /// when the model's `record_net_flows` is set.
+    pub net_flow_values: IndexMap<String, Vec<f64>>,
+    /// Step counts reported by the integrator
+    pub solver_stats: SolverStats,
 }
 
 impl SimulationResult {
     pub fn new() -> Self {
         Self {
             time_series: Vec::new(),
-            stock_values: HashMap::new(),
+            stock_values: IndexMap::new(),
+            auxiliary_values: IndexMap::new(),
+            flow_values: IndexMap::new(),
+            net_flow_values: IndexMap::new(),
+            solver_stats: SolverStats::default(),
         }
     }
 
+    /// Records the time and the value of every stock, except the internal
+    /// stocks of delay and smoothing functions.
     pub fn record_state(&mut self, time: f64, state: &SystemState) {
         self.time_series.push(time);
 
         for (stock_id, stock) in &state.stocks {
+            if delay::is_internal(stock_id) {
+                continue;
+            }
             self.stock_values
                 .entry(stock_id.clone())
                 .or_default()
@@ -357,6 +916,66 @@ impl SimulationResult {
         }
     }
 
+    /// Appends the results of a later run. Its results at or before the
+    /// last time of these ones are skipped, such as the first results of a
+    /// run that continued from them. Values of variables missing from
+    /// either results are NaN.
+    pub fn append(&mut self, other: SimulationResult) {
+        let skipped = match self.time_series.last() {
+            Some(last) => other
+                .time_series
+                .iter()
+                .take_while(|time| *time <= last)
+                .count(),
+            None => 0,
+        };
+        let rows = self.time_series.len();
+        self.time_series
+            .extend_from_slice(&other.time_series[skipped..]);
+        for (own, other) in [
+            (&mut self.stock_values, other.stock_values),
+            (&mut self.auxiliary_values, other.auxiliary_values),
+            (&mut self.flow_values, other.flow_values),
+            (&mut self.net_flow_values, other.net_flow_values),
+        ] {
+            for (id, values) in other {
+                own.entry(id)
+                    .or_insert_with(|| vec![f64::NAN; rows])
+                    .extend(values.into_iter().skip(skipped));
+            }
+            for values in own.values_mut() {
+                values.resize(self.time_series.len(), f64::NAN);
+            }
+        }
+        self.solver_stats += other.solver_stats;
+    }
+
+    /// IDs of the recorded stocks, auxiliaries, flows and then net flows,
+    /// each in the order they were added to the model. This is the order in
+    /// which variables are written to files.
+    pub fn variable_ids(&self) -> Vec<&String> {
+        self.iter().map(|(id, _)| id).collect()
+    }
+
+    /// The recorded values of every stock, auxiliary, flow and net flow, in
+    /// the order of `variable_ids`.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Vec<f64>)> {
+        self.stock_values
+            .iter()
+            .chain(&self.auxiliary_values)
+            .chain(&self.flow_values)
+            .chain(&self.net_flow_values)
+    }
+
+    /// The recorded values of a stock, auxiliary, flow or net flow.
+    pub fn get(&self, id: &str) -> Option<&Vec<f64>> {
+        self.stock_values
+            .get(id)
+            .or_else(|| self.auxiliary_values.get(id))
+            .or_else(|| self.flow_values.get(id))
+            .or_else(|| self.net_flow_values.get(id))
+    }
+
     pub fn print_summary(&self) {
         println!("Simulation Results Summary:");
         println!(
@@ -370,8 +989,33 @@ impl SimulationResult {
             let final_val = values.last().unwrap_or(&0.0);
             println!("Stock '{}': {:.3} -> {:.3}", stock_id, initial, final_val);
         }
+
+        for (auxiliary_id, values) in &self.auxiliary_values {
+            let initial = values.first().unwrap_or(&0.0);
+            let final_val = values.last().unwrap_or(&0.0);
+            println!(
+                "Auxiliary '{}': {:.3} -> {:.3}",
+                auxiliary_id, initial, final_val
+            );
+        }
+
+        for (flow_id, values) in &self.flow_values {
+            let initial = values.first().unwrap_or(&0.0);
+            let final_val = values.last().unwrap_or(&0.0);
+            println!("Flow '{}': {:.3} -> {:.3}", flow_id, initial, final_val);
+        }
+
+        for (net_flow_id, values) in &self.net_flow_values {
+            let initial = values.first().unwrap_or(&0.0);
+            let final_val = values.last().unwrap_or(&0.0);
+            println!(
+                "Net flow '{}': {:.3} -> {:.3}",
+                net_flow_id, initial, final_val
+            );
+        }
     }
 
+    /// Prints a table of the given stocks, auxiliaries, flows or net flows.
     pub fn print_detailed(&self, stock_names: &[&str]) {
         println!("\nDetailed Results:");
         print!("{:>8}", "Time");
@@ -388,7 +1032,7 @@ impl SimulationResult {
         for (i, time) in self.time_series.iter().enumerate() {
             print!("{:8.2}", time);
             for stock_id in stock_names {
-                if let Some(values) = self.stock_values.get(*stock_id) {
+                if let Some(values) = self.get(stock_id) {
                     if i < values.len() {
                         print!("{:12.3}", values[i]);
                     } else {
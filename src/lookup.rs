@@ -0,0 +1,233 @@
+use crate::EvalError;
+
+/// A graphical function (lookup table) defined by x/y points.
+///
+/// Used to express nonlinear relationships, such as the effect of crowding
+/// on the birth rate, by drawing the curve rather than writing an equation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupTable {
+    /// Points sorted by x
+    pub points: Vec<(f64, f64)>,
+    pub interpolation: Interpolation,
+    pub out_of_range: OutOfRange,
+}
+
+/// How values between the points of a lookup table are calculated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Interpolation {
+    /// Straight lines between points
+    #[default]
+    Linear,
+    /// The value of the closest point at or below the input (discrete)
+    Step,
+    /// Natural cubic spline through all points
+    Cubic,
+}
+
+/// What a lookup table returns for inputs outside its x range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum OutOfRange {
+    /// Use the value at the nearest end of the table
+    #[default]
+    Clamp,
+    /// Continue the first and last segments as straight lines
+    Extrapolate,
+    /// Fail the evaluation with `EvalError::OutOfRange`
+    Error,
+}
+
+impl LookupTable {
+    /// Creates a linear, clamped lookup table. Points are sorted by x.
+    pub fn new(mut points: Vec<(f64, f64)>) -> Self {
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self {
+            points,
+            interpolation: Interpolation::Linear,
+            out_of_range: OutOfRange::Clamp,
+        }
+    }
+
+    /// Creates a lookup table with evenly spaced x values from `x_min` to
+    /// `x_max`, as graphical functions are usually drawn.
+    pub fn from_range(x_min: f64, x_max: f64, y_values: &[f64]) -> Self {
+        let last = y_values.len().saturating_sub(1).max(1) as f64;
+        let points = y_values
+            .iter()
+            .enumerate()
+            .map(|(i, y)| (x_min + (x_max - x_min) * i as f64 / last, *y))
+            .collect();
+        Self::new(points)
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    pub fn with_out_of_range(mut self, out_of_range: OutOfRange) -> Self {
+        self.out_of_range = out_of_range;
+        self
+    }
+
+    /// Looks up the value for `x`.
+    ///
+    /// An empty table always evaluates to 0.0.
+    pub fn evaluate(&self, x: f64) -> Result<f64, EvalError> {
+        self.evaluate_with(x, None)
+    }
+
+    /// Looks up the value for `x`, using the spline second derivatives of a
+    /// cubic table when already calculated.
+    fn evaluate_with(&self, x: f64, spline: Option<&[f64]>) -> Result<f64, EvalError> {
+        let (Some(first), Some(last)) = (self.points.first(), self.points.last()) else {
+            return Ok(0.0);
+        };
+        if self.points.len() == 1 || x.is_nan() {
+            return Ok(if x.is_nan() { f64::NAN } else { first.1 });
+        }
+
+        if x < first.0 || x > last.0 {
+            match self.out_of_range {
+                OutOfRange::Clamp => return Ok(if x < first.0 { first.1 } else { last.1 }),
+                OutOfRange::Error => {
+                    return Err(EvalError::OutOfRange {
+                        value: x,
+                        min: first.0,
+                        max: last.0,
+                    })
+                }
+                OutOfRange::Extrapolate if self.interpolation == Interpolation::Step => {
+                    return Ok(if x < first.0 { first.1 } else { last.1 })
+                }
+                OutOfRange::Extrapolate => {
+                    let n = self.points.len();
+                    let segment = if x < first.0 { 0 } else { n - 2 };
+                    return Ok(linear(self.points[segment], self.points[segment + 1], x));
+                }
+            }
+        }
+
+        // Index of the segment [x_i, x_i+1] containing x
+        let segment = self
+            .points
+            .partition_point(|point| point.0 <= x)
+            .clamp(1, self.points.len() - 1)
+            - 1;
+        let (x0, y0) = self.points[segment];
+        let (x1, y1) = self.points[segment + 1];
+
+        Ok(match self.interpolation {
+            Interpolation::Linear => linear((x0, y0), (x1, y1), x),
+            Interpolation::Step => {
+                if x >= x1 {
+                    y1
+                } else {
+                    y0
+                }
+            }
+            Interpolation::Cubic => {
+                let computed;
+                let m = match spline {
+                    Some(m) => m,
+                    None => {
+                        computed = self.spline_second_derivatives();
+                        &computed
+                    }
+                };
+                let h = x1 - x0;
+                if h == 0.0 {
+                    return Ok(y1);
+                }
+                let a = (x1 - x) / h;
+                let b = (x - x0) / h;
+                a * y0
+                    + b * y1
+                    + ((a.powi(3) - a) * m[segment] + (b.powi(3) - b) * m[segment + 1]) * h * h
+                        / 6.0
+            }
+        })
+    }
+
+    /// Second derivatives of the natural cubic spline at each point.
+    fn spline_second_derivatives(&self) -> Vec<f64> {
+        let n = self.points.len();
+        let mut m = vec![0.0; n];
+        if n < 3 {
+            return m;
+        }
+
+        // Tridiagonal system for the interior points, solved with the
+        // Thomas algorithm
+        let mut diagonal = vec![0.0; n];
+        let mut rhs = vec![0.0; n];
+        let mut upper = vec![0.0; n];
+        for i in 1..n - 1 {
+            let (x_prev, y_prev) = self.points[i - 1];
+            let (x, y) = self.points[i];
+            let (x_next, y_next) = self.points[i + 1];
+            let h0 = x - x_prev;
+            let h1 = x_next - x;
+            if h0 == 0.0 || h1 == 0.0 {
+                continue;
+            }
+            let lower = h0 / 6.0;
+            diagonal[i] = (h0 + h1) / 3.0;
+            upper[i] = h1 / 6.0;
+            rhs[i] = (y_next - y) / h1 - (y - y_prev) / h0;
+
+            if i > 1 && diagonal[i - 1] != 0.0 {
+                let factor = lower / diagonal[i - 1];
+                diagonal[i] -= factor * upper[i - 1];
+                rhs[i] -= factor * rhs[i - 1];
+            }
+        }
+        for i in (1..n - 1).rev() {
+            if diagonal[i] != 0.0 {
+                m[i] = (rhs[i] - upper[i] * m[i + 1]) / diagonal[i];
+            }
+        }
+        m
+    }
+}
+
+/// A lookup table prepared for evaluation during a simulation, with the
+/// spline of a cubic table calculated once.
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledTable {
+    table: LookupTable,
+    spline: Vec<f64>,
+}
+
+impl CompiledTable {
+    pub(crate) fn new(table: &LookupTable) -> Self {
+        let spline = match table.interpolation {
+            Interpolation::Cubic => table.spline_second_derivatives(),
+            _ => Vec::new(),
+        };
+        Self {
+            table: table.clone(),
+            spline,
+        }
+    }
+
+    pub(crate) fn evaluate(&self, x: f64) -> Result<f64, EvalError> {
+        self.table.evaluate_with(x, Some(&self.spline))
+    }
+}
+
+fn linear((x0, y0): (f64, f64), (x1, y1): (f64, f64), x: f64) -> f64 {
+    if x1 == x0 {
+        return y1;
+    }
+    y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+}
@@ -1,3 +1,741 @@
-fn main() {
-    print!("Hello, world!");
+//! The `oxidyn` command-line tool, which runs model files and writes their
+//! results for use in scripts.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::path::Path;
+use std::process::ExitCode;
+
+use oxidyn::{AdaptiveSettings, CsvOptions, FlowFunction, Integrator, Model, SimulationResult};
+
+const USAGE: &str = "\
+Usage: oxidyn <COMMAND> <MODEL> [OPTIONS]
+
+Commands:
+  run       Run a model and write its results
+  validate  Check a model for errors and unit problems
+  inspect   List the stocks, flows, auxiliaries and lookups of a model
+  graph     Write a stock-and-flow diagram of a model in Graphviz DOT
+
+Models are read from oxidyn model files (.json, .toml, .yaml), XMILE files
+(.xmile, .stmx, .itmx, .xml) or Vensim files (.mdl).
+
+Run options:
+      --set <ID>=<VALUE>     Set the initial value of a stock, or replace a
+                             flow or auxiliary with a constant. Repeatable.
+      --start <TIME>         Start time
+      --stop <TIME>          Stop time
+      --dt <DT>              Time step
+      --save-interval <TIME> Interval between saved results, a multiple of
+                             the time step
+      --method <METHOD>      euler, heun, midpoint, rk4, dormand_prince,
+                             backward_euler or bdf
+  -f, --format <FORMAT>      table, csv, tsv or json. By default, from the
+                             output file extension, or a table.
+  -o, --output <FILE>        Write the results to FILE instead of stdout
+      --columns <IDS>        Comma separated variables to write, all stocks,
+                             auxiliaries and flows by default
+      --net-flows            Also record the net flow of each stock, written
+                             as net(<ID>)
+      --precision <DIGITS>   Digits after the decimal point
+
+Validate options:
+      --strict               Fail on unit problems as well as errors
+
+Graph options:
+  -o, --output <FILE>        Write the diagram to FILE instead of stdout
+
+  -h, --help                 Print this help
+  -V, --version              Print the version
+";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(args) {
+        Ok(code) => code,
+        Err(error) => {
+            for line in error.to_string().lines() {
+                eprintln!("oxidyn: {}", line);
+            }
+            match error {
+                CliError::Usage(_) => {
+                    eprintln!("Try 'oxidyn --help' for more information.");
+                    ExitCode::from(2)
+                }
+                CliError::Failed(_) => ExitCode::FAILURE,
+            }
+        }
+    }
+}
+
+/// Why the command did not complete.
+#[derive(Debug)]
+enum CliError {
+    /// The arguments are invalid
+    Usage(String),
+    /// The model could not be loaded, run or written
+    Failed(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(message) | CliError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<ExitCode, CliError> {
+    let mut args = Args::new(args);
+    match args.next().as_deref() {
+        Some("run") => {
+            run_model(RunOptions::parse(&mut args)?)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some("validate") => validate(ModelOptions::parse(&mut args, "validate")?),
+        Some("inspect") => {
+            inspect(ModelOptions::parse(&mut args, "inspect")?)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some("graph") => {
+            graph(ModelOptions::parse(&mut args, "graph")?)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some("-h" | "--help" | "help") | None => {
+            print!("{}", USAGE);
+            Ok(ExitCode::SUCCESS)
+        }
+        Some("-V" | "--version") => {
+            println!("oxidyn {}", env!("CARGO_PKG_VERSION"));
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(command) => Err(CliError::Usage(format!("unknown command '{}'", command))),
+    }
+}
+
+/// Command-line arguments, read one at a time. Options take their value
+/// from the next argument or after `=`, as in `--dt 0.5` or `--dt=0.5`.
+struct Args {
+    args: std::vec::IntoIter<String>,
+    /// Value given after `=` in the last option
+    value: Option<String>,
+}
+
+impl Args {
+    fn new(args: Vec<String>) -> Self {
+        Self {
+            args: args.into_iter(),
+            value: None,
+        }
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let arg = self.args.next()?;
+        match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => {
+                self.value = Some(value.to_string());
+                Some(option.to_string())
+            }
+            _ => {
+                self.value = None;
+                Some(arg)
+            }
+        }
+    }
+
+    /// The value of `option`.
+    fn value(&mut self, option: &str) -> Result<String, CliError> {
+        self.value
+            .take()
+            .or_else(|| self.args.next())
+            .ok_or_else(|| CliError::Usage(format!("{} needs a value", option)))
+    }
+
+    /// The value of `option` as a number.
+    fn number<T: std::str::FromStr>(&mut self, option: &str) -> Result<T, CliError> {
+        let value = self.value(option)?;
+        value
+            .parse()
+            .map_err(|_| CliError::Usage(format!("{} expects a number, found '{}'", option, value)))
+    }
+}
+
+/// How results are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Table,
+    Csv,
+    Tsv,
+    Json,
+}
+
+impl Format {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "table" => Some(Format::Table),
+            "csv" => Some(Format::Csv),
+            "tsv" => Some(Format::Tsv),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Arguments of the `run` command.
+#[derive(Debug, Default)]
+struct RunOptions {
+    model: String,
+    /// Values given with `--set`, in order
+    parameters: Vec<(String, f64)>,
+    start: Option<f64>,
+    stop: Option<f64>,
+    dt: Option<f64>,
+    save_interval: Option<f64>,
+    method: Option<Integrator>,
+    format: Option<Format>,
+    output: Option<String>,
+    columns: Option<Vec<String>>,
+    net_flows: bool,
+    precision: Option<usize>,
+}
+
+impl RunOptions {
+    fn parse(args: &mut Args) -> Result<Self, CliError> {
+        let mut options = RunOptions::default();
+        let mut model = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--set" => {
+                    let value = args.value("--set")?;
+                    let (id, number) = value.split_once('=').ok_or_else(|| {
+                        CliError::Usage(format!("--set expects ID=VALUE, found '{}'", value))
+                    })?;
+                    let number = number.trim().parse().map_err(|_| {
+                        CliError::Usage(format!(
+                            "--set expects a number for '{}', found '{}'",
+                            id, number
+                        ))
+                    })?;
+                    options.parameters.push((id.trim().to_string(), number));
+                }
+                "--start" => options.start = Some(args.number("--start")?),
+                "--stop" => options.stop = Some(args.number("--stop")?),
+                "--dt" => options.dt = Some(args.number("--dt")?),
+                "--save-interval" => options.save_interval = Some(args.number("--save-interval")?),
+                "--method" => {
+                    let name = args.value("--method")?;
+                    options.method =
+                        Some(method(&name).ok_or_else(|| {
+                            CliError::Usage(format!("unknown method '{}'", name))
+                        })?);
+                }
+                "-f" | "--format" => {
+                    let name = args.value("--format")?;
+                    options.format =
+                        Some(Format::parse(&name).ok_or_else(|| {
+                            CliError::Usage(format!("unknown format '{}'", name))
+                        })?);
+                }
+                "-o" | "--output" => options.output = Some(args.value("--output")?),
+                "--columns" => {
+                    let columns = args.value("--columns")?;
+                    options.columns = Some(
+                        columns
+                            .split(',')
+                            .map(|column| column.trim().to_string())
+                            .collect(),
+                    );
+                }
+                "--net-flows" => options.net_flows = true,
+                "--precision" => options.precision = Some(args.number("--precision")?),
+                option if option.starts_with('-') && option != "-" => {
+                    return Err(CliError::Usage(format!("unknown option '{}'", option)));
+                }
+                _ if model.is_some() => {
+                    return Err(CliError::Usage(format!("unexpected argument '{}'", arg)));
+                }
+                _ => model = Some(arg),
+            }
+        }
+        options.model = model.ok_or_else(|| CliError::Usage("no model file given".to_string()))?;
+        Ok(options)
+    }
+
+    /// The format given with `--format`, or the one named by the output
+    /// file extension.
+    fn format(&self) -> Format {
+        self.format
+            .or_else(|| {
+                let path = Path::new(self.output.as_deref()?);
+                Format::parse(path.extension()?.to_str()?)
+            })
+            .unwrap_or(Format::Table)
+    }
+}
+
+/// Arguments of the `validate`, `inspect` and `graph` commands.
+#[derive(Debug, Default)]
+struct ModelOptions {
+    model: String,
+    /// Where `graph` writes the diagram
+    output: Option<String>,
+    /// Whether `validate` fails on unit problems
+    strict: bool,
+}
+
+impl ModelOptions {
+    fn parse(args: &mut Args, command: &str) -> Result<Self, CliError> {
+        let mut options = ModelOptions::default();
+        let mut model = None;
+        while let Some(arg) = args.next() {
+            match (command, arg.as_str()) {
+                ("graph", "-o" | "--output") => options.output = Some(args.value("--output")?),
+                ("validate", "--strict") => options.strict = true,
+                (_, option) if option.starts_with('-') && option != "-" => {
+                    return Err(CliError::Usage(format!(
+                        "unknown option '{}' for {}",
+                        option, command
+                    )));
+                }
+                _ if model.is_some() => {
+                    return Err(CliError::Usage(format!("unexpected argument '{}'", arg)));
+                }
+                _ => model = Some(arg),
+            }
+        }
+        options.model = model.ok_or_else(|| CliError::Usage("no model file given".to_string()))?;
+        Ok(options)
+    }
+}
+
+/// The name of an integrator, as given to `--method`.
+fn method_name(integrator: &Integrator) -> &'static str {
+    match integrator {
+        Integrator::Euler => "euler",
+        Integrator::Heun => "heun",
+        Integrator::Midpoint => "midpoint",
+        Integrator::RungeKutta4 => "rk4",
+        Integrator::DormandPrince(_) => "dormand_prince",
+        Integrator::BackwardEuler => "backward_euler",
+        Integrator::Bdf { .. } => "bdf",
+    }
+}
+
+/// The integrator named `name`, with default settings, using the names of
+/// model files.
+fn method(name: &str) -> Option<Integrator> {
+    let method = match name.to_ascii_lowercase().replace('-', "_").as_str() {
+        "euler" => Integrator::Euler,
+        "heun" => Integrator::Heun,
+        "midpoint" => Integrator::Midpoint,
+        "rk4" => Integrator::RungeKutta4,
+        "dormand_prince" => Integrator::DormandPrince(AdaptiveSettings::new()),
+        "backward_euler" => Integrator::BackwardEuler,
+        "bdf" => Integrator::Bdf { max_order: 5 },
+        _ => return None,
+    };
+    Some(method)
+}
+
+fn run_model(options: RunOptions) -> Result<(), CliError> {
+    let mut model = load_model(&options.model)?;
+
+    for (id, value) in &options.parameters {
+        set_parameter(&mut model, id, *value)?;
+    }
+    if let Some(start) = options.start {
+        model.set_start_time(start);
+    }
+    if let Some(stop) = options.stop {
+        model.set_stop_time(stop);
+    }
+    if let Some(dt) = options.dt {
+        model.set_time_step(dt);
+    }
+    if let Some(interval) = options.save_interval {
+        model.set_save_interval(interval);
+    }
+    if options.net_flows {
+        model.set_record_net_flows(true);
+    }
+    if let Some(method) = options.method {
+        // Keep the model's settings when it already uses this method
+        if mem::discriminant(&method) != mem::discriminant(&model.integrator) {
+            model.set_integrator(method);
+        }
+    }
+    if model.stop_time.is_none() {
+        return Err(CliError::Failed(format!(
+            "{}: the model has no stop time, give one with --stop",
+            options.model
+        )));
+    }
+
+    let result = model.try_simulate_to_stop().map_err(|errors| {
+        let errors: Vec<_> = errors.iter().map(|error| error.to_string()).collect();
+        CliError::Failed(errors.join("\n"))
+    })?;
+    write_results(&result, &options)
+}
+
+/// Prints the problems `validate` and `check_units` find in the model.
+/// Fails if there are errors, or with `--strict` any problem.
+fn validate(options: ModelOptions) -> Result<ExitCode, CliError> {
+    let model = load_model(&options.model)?;
+    let errors = model.validate();
+    let warnings = model.check_units();
+    for error in &errors {
+        println!("error: {}", error);
+    }
+    for warning in &warnings {
+        println!("warning: {}", warning);
+    }
+
+    if errors.is_empty() && warnings.is_empty() {
+        println!("{}: no problems found", options.model);
+    } else {
+        println!(
+            "{}: {}, {}",
+            options.model,
+            count(errors.len(), "error"),
+            count(warnings.len(), "warning")
+        );
+    }
+    if errors.is_empty() && (warnings.is_empty() || !options.strict) {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+fn count(n: usize, noun: &str) -> String {
+    match n {
+        1 => format!("1 {}", noun),
+        n => format!("{} {}s", n, noun),
+    }
+}
+
+/// Prints the simulation settings and elements of the model, with the
+/// equations of flows and auxiliaries and the variables they read.
+fn inspect(options: ModelOptions) -> Result<(), CliError> {
+    let model = load_model(&options.model)?;
+    let mut out = String::new();
+
+    out.push_str(&format!("Model: {}\n", model.name));
+    let stop = match model.stop_time {
+        Some(stop) => format!(" to {}", stop),
+        None => String::new(),
+    };
+    let save_interval = match model.save_interval {
+        Some(interval) => format!(", saved every {}", interval),
+        None => String::new(),
+    };
+    out.push_str(&format!(
+        "Time: {}{} {}, dt {}{}, method {}\n",
+        model.state.time,
+        stop,
+        model.time_units,
+        model.time_step,
+        save_interval,
+        method_name(&model.integrator)
+    ));
+
+    let stocks: Vec<_> = model.state.stocks.values().collect();
+    let flows: Vec<_> = model.flows.values().collect();
+    let auxiliaries: Vec<_> = model.auxiliaries.values().collect();
+    let lookups: Vec<_> = model.state.lookups.iter().collect();
+
+    out.push_str(&format!("\nStocks ({}):\n", stocks.len()));
+    for stock in stocks {
+        out.push_str(&format!(
+            "  {}{}\n",
+            element(&stock.id, &stock.name, &stock.units),
+            bounds(stock.min_value, stock.max_value)
+        ));
+        out.push_str(&format!("    initial: {}\n", stock.initial_value));
+        let connected = |to: bool| -> Vec<&str> {
+            flows
+                .iter()
+                .filter(|flow| {
+                    let stock_id = if to { &flow.to_stock } else { &flow.from_stock };
+                    stock_id.as_deref() == Some(stock.id.as_str())
+                })
+                .map(|flow| flow.id.as_str())
+                .collect()
+        };
+        list(&mut out, "inflows", &connected(true));
+        list(&mut out, "outflows", &connected(false));
+    }
+
+    out.push_str(&format!("\nFlows ({}):\n", flows.len()));
+    for flow in &flows {
+        out.push_str(&format!(
+            "  {}\n",
+            element(&flow.id, &flow.name, &flow.units)
+        ));
+        out.push_str(&format!(
+            "    {} -> {}\n",
+            flow.from_stock.as_deref().unwrap_or("(source)"),
+            flow.to_stock.as_deref().unwrap_or("(sink)")
+        ));
+        function(&mut out, &flow.rate_function);
+    }
+
+    out.push_str(&format!("\nAuxiliaries ({}):\n", auxiliaries.len()));
+    for auxiliary in auxiliaries {
+        out.push_str(&format!(
+            "  {}\n",
+            element(&auxiliary.id, &auxiliary.name, &auxiliary.units)
+        ));
+        function(&mut out, &auxiliary.function);
+    }
+
+    out.push_str(&format!("\nLookups ({}):\n", lookups.len()));
+    for (id, table) in lookups {
+        out.push_str(&format!("  {}: {} points\n", id, table.points.len()));
+    }
+
+    print!("{}", out);
+    Ok(())
+}
+
+/// An element's ID, with its name when different and its units.
+fn element(id: &str, name: &str, units: &str) -> String {
+    let mut text = id.to_string();
+    if !name.is_empty() && name != id {
+        text.push_str(&format!(" \"{}\"", name.replace('\n', " ")));
+    }
+    if !units.is_empty() {
+        text.push_str(&format!(" [{}]", units));
+    }
+    text
+}
+
+fn bounds(min: Option<f64>, max: Option<f64>) -> String {
+    match (min, max) {
+        (None, None) => String::new(),
+        (Some(min), None) => format!(", min {}", min),
+        (None, Some(max)) => format!(", max {}", max),
+        (Some(min), Some(max)) => format!(", min {}, max {}", min, max),
+    }
+}
+
+fn list(out: &mut String, label: &str, ids: &[&str]) {
+    if !ids.is_empty() {
+        out.push_str(&format!("    {}: {}\n", label, ids.join(", ")));
+    }
+}
+
+/// Writes the equation of a flow or auxiliary and what it reads.
+fn function(out: &mut String, function: &FlowFunction) {
+    let equation = match function {
+        FlowFunction::Constant(value) => value.to_string(),
+        FlowFunction::Linear {
+            slope,
+            intercept,
+            input_stock,
+        } => format!("{} * {} + {}", slope, input_stock, intercept),
+        FlowFunction::Expression(expr) => expr.to_string(),
+        FlowFunction::Lookup { table, input } => {
+            format!("lookup of {} ({} points)", input, table.points.len())
+        }
+    };
+    out.push_str(&format!("    = {}\n", equation));
+    list(out, "uses", &function.dependencies());
+    if let FlowFunction::Expression(expr) | FlowFunction::Lookup { input: expr, .. } = function {
+        list(out, "lookups", &expr.lookups());
+    }
+}
+
+/// Writes the model as a Graphviz DOT diagram.
+fn graph(options: ModelOptions) -> Result<(), CliError> {
+    let model = load_model(&options.model)?;
+    let dot = model.to_dot();
+    match &options.output {
+        Some(path) => fs::write(path, dot)
+            .map_err(|error| CliError::Failed(format!("cannot write {}: {}", path, error))),
+        None => {
+            print!("{}", dot);
+            Ok(())
+        }
+    }
+}
+
+/// Reads a model file, choosing the format by its extension.
+fn load_model(path: &str) -> Result<Model, CliError> {
+    let source = fs::read_to_string(path)
+        .map_err(|error| CliError::Failed(format!("cannot read {}: {}", path, error)))?;
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    let model = match extension.as_str() {
+        "xmile" | "stmx" | "itmx" | "xml" => Model::from_xmile(&source).map_err(|e| e.to_string()),
+        "mdl" => Model::from_vensim(&source).map_err(|e| e.to_string()),
+        #[cfg(feature = "json")]
+        "json" => Model::from_json(&source).map_err(|e| e.to_string()),
+        #[cfg(feature = "toml")]
+        "toml" => Model::from_toml(&source).map_err(|e| e.to_string()),
+        #[cfg(feature = "yaml")]
+        "yaml" | "yml" => Model::from_yaml(&source).map_err(|e| e.to_string()),
+        extension => {
+            let feature = match extension {
+                "json" => "json",
+                "toml" => "toml",
+                "yaml" | "yml" => "yaml",
+                _ => {
+                    return Err(CliError::Failed(format!(
+                        "{}: unknown model file type, expected .json, .toml, .yaml, .xmile or .mdl",
+                        path
+                    )))
+                }
+            };
+            Err(format!(
+                "this oxidyn was built without the `{}` feature needed to read it",
+                feature
+            ))
+        }
+    };
+    model.map_err(|message| CliError::Failed(format!("{}: {}", path, message)))
+}
+
+/// Sets the initial value of a stock, or replaces the function of a flow or
+/// auxiliary with a constant.
+fn set_parameter(model: &mut Model, id: &str, value: f64) -> Result<(), CliError> {
+    if let Some(stock) = model.state.stocks.get_mut(id) {
+        stock.initial_value = value;
+        stock.current_value = value;
+    } else if let Some(auxiliary) = model.auxiliaries.get_mut(id) {
+        auxiliary.function = FlowFunction::Constant(value);
+    } else if let Some(flow) = model.flows.get_mut(id) {
+        flow.rate_function = FlowFunction::Constant(value);
+    } else {
+        return Err(CliError::Failed(format!(
+            "--set: '{}' is not a stock, flow or auxiliary of the model",
+            id
+        )));
+    }
+    Ok(())
+}
+
+fn write_results(result: &SimulationResult, options: &RunOptions) -> Result<(), CliError> {
+    let columns = match &options.columns {
+        Some(columns) => columns.clone(),
+        None => result.variable_ids().into_iter().cloned().collect(),
+    };
+    let series = columns
+        .iter()
+        .map(|column| {
+            result
+                .get(column)
+                .map(Vec::as_slice)
+                .ok_or_else(|| CliError::Failed(format!("unknown column '{}'", column)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let output = options.output.as_deref().unwrap_or("stdout");
+    let failed = |error: io::Error| CliError::Failed(format!("cannot write {}: {}", output, error));
+    let mut writer: Box<dyn Write> = match &options.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path).map_err(failed)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    let csv = |csv: CsvOptions| CsvOptions {
+        columns: Some(columns.clone()),
+        precision: options.precision,
+        ..csv
+    };
+    match options.format() {
+        Format::Table => write_table(&mut writer, result, &columns, &series, options.precision),
+        Format::Csv => result.write_csv(&mut writer, &csv(CsvOptions::csv())),
+        Format::Tsv => result.write_csv(&mut writer, &csv(CsvOptions::tsv())),
+        Format::Json => write_json(&mut writer, result, &columns, &series, options.precision),
+    }
+    .and_then(|()| writer.flush())
+    .map_err(failed)
+}
+
+/// Writes the results as an aligned text table.
+fn write_table(
+    writer: &mut dyn Write,
+    result: &SimulationResult,
+    columns: &[String],
+    series: &[&[f64]],
+    precision: Option<usize>,
+) -> io::Result<()> {
+    let precision = precision.unwrap_or(3);
+    let widths: Vec<_> = std::iter::once("time")
+        .chain(columns.iter().map(String::as_str))
+        .map(|name| name.chars().count().max(10))
+        .collect();
+
+    let header: Vec<_> = std::iter::once("time")
+        .chain(columns.iter().map(String::as_str))
+        .zip(&widths)
+        .map(|(name, width)| format!("{:>1$}", name, width))
+        .collect();
+    writeln!(writer, "{}", header.join("  "))?;
+    let rule: Vec<_> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    writeln!(writer, "{}", rule.join("  "))?;
+
+    for (i, time) in result.time_series.iter().enumerate() {
+        let row: Vec<_> = std::iter::once(Some(*time))
+            .chain(series.iter().map(|values| values.get(i).copied()))
+            .zip(&widths)
+            .map(|(value, width)| match value {
+                Some(value) => format!("{:>width$.precision$}", value),
+                None => " ".repeat(*width),
+            })
+            .collect();
+        writeln!(writer, "{}", row.join("  "))?;
+    }
+    Ok(())
+}
+
+/// Writes the results as a JSON object with a `time` array and an array per
+/// variable. Values that are not finite are written as `null`.
+fn write_json(
+    writer: &mut dyn Write,
+    result: &SimulationResult,
+    columns: &[String],
+    series: &[&[f64]],
+    precision: Option<usize>,
+) -> io::Result<()> {
+    let number = |value: &f64| match precision {
+        _ if !value.is_finite() => "null".to_string(),
+        Some(precision) => format!("{:.*}", precision, value),
+        None => value.to_string(),
+    };
+    let array = |values: &[f64]| {
+        let values: Vec<_> = values.iter().map(number).collect();
+        format!("[{}]", values.join(", "))
+    };
+
+    writeln!(writer, "{{")?;
+    write!(writer, "  \"time\": {}", array(&result.time_series))?;
+    for (column, values) in columns.iter().zip(series) {
+        write!(writer, ",\n  {}: {}", json_string(column), array(values))?;
+    }
+    writeln!(writer, "\n}}")
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
 }
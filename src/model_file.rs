@@ -0,0 +1,814 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    delay, AdaptiveSettings, Auxiliary, Expr, Flow, FlowFunction, Integrator, Interpolation,
+    LookupTable, Model, OutOfRange, Stock,
+};
+
+/// The version of the model file format written by this version of oxidyn.
+/// Files with a later version are rejected.
+pub const MODEL_FILE_VERSION: u32 = 1;
+
+/// A model file could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelFileError {
+    /// The file is not valid JSON, TOML or YAML, or does not match the
+    /// model file schema
+    Syntax {
+        message: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    /// The file was written for a format version this version of oxidyn
+    /// cannot read
+    UnsupportedVersion(u32),
+    /// An element refers to a stock that is not in the file
+    UnknownStock {
+        /// Path to the reference, such as `flows[2].to`
+        location: String,
+        stock: String,
+        /// Line of the reference, where it can be found
+        line: Option<usize>,
+    },
+    /// An equation refers to a variable that is not a stock, flow or
+    /// auxiliary in the file
+    UnknownVariable {
+        /// Path to the equation, such as `flows[2].equation`
+        location: String,
+        variable: String,
+        /// Line of the equation, where it can be found
+        line: Option<usize>,
+    },
+    /// An element is invalid, such as an equation that does not parse
+    Invalid {
+        /// Path to the element, such as `auxiliaries[0].equation`
+        location: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ModelFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelFileError::Syntax { message, .. } => write!(f, "{}", message),
+            ModelFileError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported model file version {}, expected at most {}",
+                version, MODEL_FILE_VERSION
+            ),
+            ModelFileError::UnknownStock {
+                location,
+                stock,
+                line,
+            } => {
+                write!(f, "{}: unknown stock '{}'", location, stock)?;
+                if let Some(line) = line {
+                    write!(f, " at line {}", line)?;
+                }
+                Ok(())
+            }
+            ModelFileError::UnknownVariable {
+                location,
+                variable,
+                line,
+            } => {
+                write!(f, "{}: unknown variable '{}'", location, variable)?;
+                if let Some(line) = line {
+                    write!(f, " at line {}", line)?;
+                }
+                Ok(())
+            }
+            ModelFileError::Invalid { location, message } => write!(f, "{}: {}", location, message),
+        }
+    }
+}
+
+impl std::error::Error for ModelFileError {}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for ModelFileError {
+    fn from(error: serde_json::Error) -> Self {
+        ModelFileError::Syntax {
+            message: error.to_string(),
+            line: Some(error.line()),
+            column: Some(error.column()),
+        }
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl From<serde_yaml::Error> for ModelFileError {
+    fn from(error: serde_yaml::Error) -> Self {
+        let location = error.location();
+        ModelFileError::Syntax {
+            message: error.to_string(),
+            line: location.as_ref().map(|location| location.line()),
+            column: location.as_ref().map(|location| location.column()),
+        }
+    }
+}
+
+/// Reading and writing oxidyn's own model files.
+///
+/// A model file lists the simulation settings, stocks, flows, auxiliaries
+/// and lookup tables of a model, with equations as text. Models are saved
+/// with their stocks' current values at the current time, so a loaded model
+/// starts where the saved one was. The state of delays is not saved.
+impl Model {
+    #[cfg(feature = "json")]
+    pub fn from_json(source: &str) -> Result<Model, ModelFileError> {
+        let file: ModelFile = serde_json::from_str(source)?;
+        file.into_model(source)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&ModelFile::from_model(self))
+            .expect("model files contain only strings, numbers and lists")
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml(source: &str) -> Result<Model, ModelFileError> {
+        let file: ModelFile = toml::from_str(source).map_err(|error| {
+            let position = error.span().map(|span| line_and_column(source, span.start));
+            ModelFileError::Syntax {
+                message: error.to_string(),
+                line: position.map(|position| position.0),
+                column: position.map(|position| position.1),
+            }
+        })?;
+        file.into_model(source)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> String {
+        toml::to_string(&ModelFile::from_model(self))
+            .expect("model files contain only strings, numbers and lists")
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(source: &str) -> Result<Model, ModelFileError> {
+        let file: ModelFile = serde_yaml::from_str(source)?;
+        file.into_model(source)
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn to_yaml(&self) -> String {
+        serde_yaml::to_string(&ModelFile::from_model(self))
+            .expect("model files contain only strings, numbers and lists")
+    }
+}
+
+/// The 1-based line and column of a byte offset.
+#[cfg(feature = "toml")]
+fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/// The line of a reference such as `flows[2].linear.input_stock`: the
+/// first line of the `flows[2]` entry that sets `input_stock`.
+fn line_of(source: &str, location: &str) -> Option<usize> {
+    let (entry, path) = location.split_once('.')?;
+    let (section, index) = entry.strip_suffix(']')?.split_once('[')?;
+    let index = index.parse().ok()?;
+    let key = path.rsplit('.').next()?;
+
+    let lines: Vec<&str> = source.lines().collect();
+    let entry = toml_entry(&lines, section, index)
+        .or_else(|| yaml_entry(&lines, section, index))
+        .or_else(|| json_entry(source, section, index))?;
+    entry
+        .clone()
+        .find(|&i| sets_key(lines[i], key))
+        .or(Some(entry.start))
+        .map(|i| i + 1)
+}
+
+/// Whether a line sets `key`, as `key = `, `key: ` or `"key": `.
+fn sets_key(line: &str, key: &str) -> bool {
+    line.match_indices(key).any(|(start, _)| {
+        let (before, after) = (&line[..start], &line[start + key.len()..]);
+        let (before, after) = match (before.strip_suffix('"'), after.strip_prefix('"')) {
+            (Some(before), Some(after)) => (before, after),
+            (None, None) => (before, after),
+            _ => return false,
+        };
+        !before.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+            && after.trim_start().starts_with([':', '='])
+    })
+}
+
+/// The lines of the `index`th `[[section]]` table of a TOML file.
+fn toml_entry(lines: &[&str], section: &str, index: usize) -> Option<Range<usize>> {
+    let header = format!("[[{}]]", section);
+    let start = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.trim() == header)
+        .nth(index)?
+        .0;
+    let nested = [format!("[{}.", section), format!("[[{}.", section)];
+    let end = (start + 1..lines.len())
+        .find(|&i| {
+            let line = lines[i].trim();
+            let is_header = line
+                .trim_start_matches('[')
+                .starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '"');
+            line.starts_with('[') && is_header && !nested.iter().any(|n| line.starts_with(n))
+        })
+        .unwrap_or(lines.len());
+    Some(start..end)
+}
+
+/// The lines of the `index`th item of the `section:` list of a YAML file.
+fn yaml_entry(lines: &[&str], section: &str, index: usize) -> Option<Range<usize>> {
+    let key = format!("{}:", section);
+    let start = lines.iter().position(|line| line.trim_end() == key)?;
+
+    let mut items = Vec::new();
+    let mut indent = None;
+    let mut end = lines.len();
+    for (i, line) in lines.iter().enumerate().skip(start + 1) {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let depth = line.len() - trimmed.len();
+        let is_item = trimmed == "-" || trimmed.starts_with("- ");
+        match indent {
+            None if is_item => indent = Some(depth),
+            Some(indent) if is_item && depth == indent => {}
+            Some(indent) if depth > indent => continue,
+            _ => {
+                end = i;
+                break;
+            }
+        }
+        items.push(i);
+    }
+    let first = *items.get(index)?;
+    Some(first..items.get(index + 1).copied().unwrap_or(end))
+}
+
+/// The lines of the `index`th object of the top-level `"section"` array of
+/// a JSON file.
+fn json_entry(source: &str, section: &str, index: usize) -> Option<Range<usize>> {
+    let key = format!("\"{}\"", section);
+    let bytes = source.as_bytes();
+    let mut depth: usize = 0;
+    let mut section_depth = None;
+    let mut count = 0;
+    let mut start = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                let mut end = i + 1;
+                while end < bytes.len() && bytes[end] != b'"' {
+                    end += if bytes[end] == b'\\' { 2 } else { 1 };
+                }
+                let is_key = source[(end + 1).min(source.len())..]
+                    .trim_start()
+                    .starts_with(':');
+                if depth == 1 && is_key && source.get(i..=end) == Some(key.as_str()) {
+                    section_depth = Some(2);
+                }
+                i = end;
+            }
+            b'{' | b'[' => {
+                depth += 1;
+                if section_depth.map(|depth| depth + 1) == Some(depth) && bytes[i] == b'{' {
+                    if count == index {
+                        start = Some(i);
+                    }
+                    count += 1;
+                }
+            }
+            b'}' | b']' => {
+                if section_depth == Some(depth) {
+                    return None;
+                }
+                if let Some(start) =
+                    start.filter(|_| section_depth.map(|depth| depth + 1) == Some(depth))
+                {
+                    let line = |offset: usize| source[..offset].matches('\n').count();
+                    return Some(line(start)..line(i) + 1);
+                }
+                depth = depth.saturating_sub(1);
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModelFile {
+    version: u32,
+    name: String,
+    #[serde(default)]
+    simulation: Simulation,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    stocks: Vec<StockEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    flows: Vec<FlowEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    auxiliaries: Vec<AuxiliaryEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    lookups: Vec<LookupEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Simulation {
+    start: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<f64>,
+    dt: f64,
+    /// Interval between saved results, every `dt` when not given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    save_interval: Option<f64>,
+    time_units: String,
+    method: Method,
+    /// Settings of the adaptive and BDF methods
+    #[serde(skip_serializing_if = "Option::is_none")]
+    absolute_tolerance: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    relative_tolerance: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_step: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_step: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_order: Option<usize>,
+    #[serde(skip_serializing_if = "is_false")]
+    record_internal: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    record_net_flows: bool,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        let model = Model::new("");
+        Simulation::from_model(&model)
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Method {
+    Euler,
+    Heun,
+    Midpoint,
+    #[serde(rename = "rk4")]
+    RungeKutta4,
+    DormandPrince,
+    BackwardEuler,
+    Bdf,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StockEntry {
+    id: String,
+    #[serde(default)]
+    name: String,
+    initial: f64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    units: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FlowEntry {
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    equation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    linear: Option<Linear>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lookup: Option<Table>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    units: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AuxiliaryEntry {
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    equation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    linear: Option<Linear>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lookup: Option<Table>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    units: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Linear {
+    slope: f64,
+    #[serde(default)]
+    intercept: f64,
+    input_stock: String,
+}
+
+/// A lookup table applied to the value of the element's equation.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Table {
+    points: Vec<(f64, f64)>,
+    #[serde(default)]
+    interpolation: Interpolation,
+    #[serde(default)]
+    out_of_range: OutOfRange,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LookupEntry {
+    id: String,
+    points: Vec<(f64, f64)>,
+    #[serde(default)]
+    interpolation: Interpolation,
+    #[serde(default)]
+    out_of_range: OutOfRange,
+}
+
+impl Simulation {
+    fn from_model(model: &Model) -> Self {
+        let mut simulation = Simulation {
+            start: model.state.time,
+            stop: model.stop_time,
+            dt: model.time_step,
+            save_interval: model.save_interval,
+            time_units: model.time_units.clone(),
+            method: Method::Euler,
+            absolute_tolerance: None,
+            relative_tolerance: None,
+            min_step: None,
+            max_step: None,
+            max_order: None,
+            record_internal: model.record_internal,
+            record_net_flows: model.record_net_flows,
+        };
+        simulation.method = match model.integrator {
+            Integrator::Euler => Method::Euler,
+            Integrator::Heun => Method::Heun,
+            Integrator::Midpoint => Method::Midpoint,
+            Integrator::RungeKutta4 => Method::RungeKutta4,
+            Integrator::DormandPrince(settings) => {
+                simulation.absolute_tolerance = Some(settings.absolute_tolerance);
+                simulation.relative_tolerance = Some(settings.relative_tolerance);
+                simulation.min_step = Some(settings.min_step);
+                // Formats like JSON have no infinity
+                simulation.max_step = Some(settings.max_step).filter(|step| step.is_finite());
+                Method::DormandPrince
+            }
+            Integrator::BackwardEuler => Method::BackwardEuler,
+            Integrator::Bdf { max_order } => {
+                simulation.max_order = Some(max_order);
+                Method::Bdf
+            }
+        };
+        simulation
+    }
+
+    fn integrator(&self) -> Integrator {
+        match self.method {
+            Method::Euler => Integrator::Euler,
+            Method::Heun => Integrator::Heun,
+            Method::Midpoint => Integrator::Midpoint,
+            Method::RungeKutta4 => Integrator::RungeKutta4,
+            Method::DormandPrince => {
+                let defaults = AdaptiveSettings::default();
+                Integrator::DormandPrince(AdaptiveSettings {
+                    absolute_tolerance: self
+                        .absolute_tolerance
+                        .unwrap_or(defaults.absolute_tolerance),
+                    relative_tolerance: self
+                        .relative_tolerance
+                        .unwrap_or(defaults.relative_tolerance),
+                    min_step: self.min_step.unwrap_or(defaults.min_step),
+                    max_step: self.max_step.unwrap_or(defaults.max_step),
+                })
+            }
+            Method::BackwardEuler => Integrator::BackwardEuler,
+            Method::Bdf => Integrator::Bdf {
+                max_order: self.max_order.unwrap_or(5),
+            },
+        }
+    }
+}
+
+impl ModelFile {
+    fn from_model(model: &Model) -> Self {
+        let stocks: Vec<_> = model
+            .state
+            .stocks
+            .values()
+            .filter(|stock| !delay::is_internal(&stock.id))
+            .map(|stock| StockEntry {
+                id: stock.id.clone(),
+                name: stock.name.clone(),
+                initial: stock.current_value,
+                units: stock.units.clone(),
+                min: stock.min_value,
+                max: stock.max_value,
+            })
+            .collect();
+
+        let flows: Vec<_> = model
+            .flows
+            .values()
+            .map(|flow| {
+                let (equation, linear, lookup) = write_function(&flow.rate_function);
+                FlowEntry {
+                    id: flow.id.clone(),
+                    name: flow.name.clone(),
+                    from: flow.from_stock.clone(),
+                    to: flow.to_stock.clone(),
+                    equation,
+                    linear,
+                    lookup,
+                    units: flow.units.clone(),
+                }
+            })
+            .collect();
+
+        let auxiliaries: Vec<_> = model
+            .auxiliaries
+            .values()
+            .map(|auxiliary| {
+                let (equation, linear, lookup) = write_function(&auxiliary.function);
+                AuxiliaryEntry {
+                    id: auxiliary.id.clone(),
+                    name: auxiliary.name.clone(),
+                    equation,
+                    linear,
+                    lookup,
+                    units: auxiliary.units.clone(),
+                }
+            })
+            .collect();
+
+        let lookups: Vec<_> = model
+            .state
+            .lookups
+            .iter()
+            .map(|(id, table)| LookupEntry {
+                id: id.clone(),
+                points: table.points.clone(),
+                interpolation: table.interpolation,
+                out_of_range: table.out_of_range,
+            })
+            .collect();
+
+        ModelFile {
+            version: MODEL_FILE_VERSION,
+            name: model.name.clone(),
+            simulation: Simulation::from_model(model),
+            stocks,
+            flows,
+            auxiliaries,
+            lookups,
+        }
+    }
+
+    fn into_model(self, source: &str) -> Result<Model, ModelFileError> {
+        if self.version == 0 || self.version > MODEL_FILE_VERSION {
+            return Err(ModelFileError::UnsupportedVersion(self.version));
+        }
+
+        let mut model = Model::new(&self.name);
+        model.set_start_time(self.simulation.start);
+        model.stop_time = self.simulation.stop;
+        model.save_interval = self.simulation.save_interval;
+        model
+            .set_time_step(self.simulation.dt)
+            .set_time_units(&self.simulation.time_units)
+            .set_integrator(self.simulation.integrator())
+            .set_record_internal(self.simulation.record_internal)
+            .set_record_net_flows(self.simulation.record_net_flows);
+
+        let mut ids = HashSet::new();
+        let mut check_id = |id: &str, location: String| {
+            if ids.insert(id.to_string()) {
+                Ok(())
+            } else {
+                Err(ModelFileError::Invalid {
+                    location,
+                    message: format!("duplicate ID '{}'", id),
+                })
+            }
+        };
+        for (i, entry) in self.stocks.iter().enumerate() {
+            check_id(&entry.id, format!("stocks[{}]", i))?;
+        }
+        for (i, entry) in self.flows.iter().enumerate() {
+            check_id(&entry.id, format!("flows[{}]", i))?;
+        }
+        for (i, entry) in self.auxiliaries.iter().enumerate() {
+            check_id(&entry.id, format!("auxiliaries[{}]", i))?;
+        }
+        for (i, entry) in self.lookups.iter().enumerate() {
+            check_id(&entry.id, format!("lookups[{}]", i))?;
+        }
+
+        let stock_ids: HashSet<&str> = self.stocks.iter().map(|entry| entry.id.as_str()).collect();
+        let check_stock = |stock: &str, location: String| {
+            if stock_ids.contains(stock) {
+                Ok(())
+            } else {
+                Err(ModelFileError::UnknownStock {
+                    line: line_of(source, &location),
+                    location,
+                    stock: stock.to_string(),
+                })
+            }
+        };
+        let variable_ids: HashSet<String> = self
+            .stocks
+            .iter()
+            .map(|entry| &entry.id)
+            .chain(self.flows.iter().map(|entry| &entry.id))
+            .chain(self.auxiliaries.iter().map(|entry| &entry.id))
+            .cloned()
+            .collect();
+        let check_function = |function: &FlowFunction, location: &str| match function {
+            FlowFunction::Constant(_) => Ok(()),
+            FlowFunction::Linear { input_stock, .. } => {
+                check_stock(input_stock, format!("{}.linear.input_stock", location))
+            }
+            FlowFunction::Expression(expr) | FlowFunction::Lookup { input: expr, .. } => match expr
+                .variables()
+                .into_iter()
+                .find(|variable| !variable_ids.contains(*variable))
+            {
+                Some(variable) => {
+                    let location = format!("{}.equation", location);
+                    Err(ModelFileError::UnknownVariable {
+                        line: line_of(source, &location),
+                        location,
+                        variable: variable.to_string(),
+                    })
+                }
+                None => Ok(()),
+            },
+        };
+
+        for entry in &self.stocks {
+            let mut stock = Stock::new(
+                &entry.id,
+                name_or_id(&entry.name, &entry.id),
+                entry.initial,
+                &entry.units,
+            );
+            stock.min_value = entry.min;
+            stock.max_value = entry.max;
+            model.add_stock(stock);
+        }
+        for (i, entry) in self.flows.into_iter().enumerate() {
+            let location = format!("flows[{}]", i);
+            if let Some(stock) = &entry.from {
+                check_stock(stock, format!("{}.from", location))?;
+            }
+            if let Some(stock) = &entry.to {
+                check_stock(stock, format!("{}.to", location))?;
+            }
+            let function = read_function(entry.equation, entry.linear, entry.lookup, &location)?;
+            check_function(&function, &location)?;
+            model.add_flow(Flow {
+                name: name_or_id(&entry.name, &entry.id).to_string(),
+                id: entry.id,
+                from_stock: entry.from,
+                to_stock: entry.to,
+                rate_function: function,
+                units: entry.units,
+            });
+        }
+        for (i, entry) in self.auxiliaries.into_iter().enumerate() {
+            let location = format!("auxiliaries[{}]", i);
+            let function = read_function(entry.equation, entry.linear, entry.lookup, &location)?;
+            check_function(&function, &location)?;
+            model.add_auxiliary(Auxiliary {
+                name: name_or_id(&entry.name, &entry.id).to_string(),
+                id: entry.id,
+                function,
+                units: entry.units,
+            });
+        }
+        for entry in self.lookups {
+            let table = LookupTable::new(entry.points)
+                .with_interpolation(entry.interpolation)
+                .with_out_of_range(entry.out_of_range);
+            model.add_lookup(&entry.id, table);
+        }
+        Ok(model)
+    }
+}
+
+fn name_or_id<'a>(name: &'a str, id: &'a str) -> &'a str {
+    if name.is_empty() {
+        id
+    } else {
+        name
+    }
+}
+
+fn write_function(function: &FlowFunction) -> (Option<String>, Option<Linear>, Option<Table>) {
+    match function {
+        FlowFunction::Constant(value) => (Some(value.to_string()), None, None),
+        FlowFunction::Linear {
+            slope,
+            intercept,
+            input_stock,
+        } => (
+            None,
+            Some(Linear {
+                slope: *slope,
+                intercept: *intercept,
+                input_stock: input_stock.clone(),
+            }),
+            None,
+        ),
+        FlowFunction::Expression(expr) => (Some(expr.to_string()), None, None),
+        FlowFunction::Lookup { table, input } => (
+            Some(input.to_string()),
+            None,
+            Some(Table {
+                points: table.points.clone(),
+                interpolation: table.interpolation,
+                out_of_range: table.out_of_range,
+            }),
+        ),
+    }
+}
+
+/// The function of a flow or auxiliary: an equation, a linear function of
+/// a stock, or a lookup table applied to an equation.
+fn read_function(
+    equation: Option<String>,
+    linear: Option<Linear>,
+    lookup: Option<Table>,
+    location: &str,
+) -> Result<FlowFunction, ModelFileError> {
+    let invalid = |message: &str| ModelFileError::Invalid {
+        location: location.to_string(),
+        message: message.to_string(),
+    };
+    let parse = |equation: &str| {
+        Expr::parse(equation).map_err(|error| ModelFileError::Invalid {
+            location: format!("{}.equation", location),
+            message: error.to_string(),
+        })
+    };
+
+    match (equation, linear, lookup) {
+        (Some(equation), None, None) => Ok(match parse(&equation)? {
+            Expr::Number(value) => FlowFunction::Constant(value),
+            expr => FlowFunction::Expression(expr),
+        }),
+        (Some(equation), None, Some(table)) => Ok(FlowFunction::Lookup {
+            table: LookupTable::new(table.points)
+                .with_interpolation(table.interpolation)
+                .with_out_of_range(table.out_of_range),
+            input: parse(&equation)?,
+        }),
+        (None, Some(linear), None) => Ok(FlowFunction::Linear {
+            slope: linear.slope,
+            intercept: linear.intercept,
+            input_stock: linear.input_stock,
+        }),
+        (None, None, Some(_)) => Err(invalid("a lookup needs an equation for its input")),
+        (None, None, None) => Err(invalid("needs an equation or a linear function")),
+        _ => Err(invalid("has both an equation and a linear function")),
+    }
+}
@@ -0,0 +1,361 @@
+use std::cell::RefCell;
+use std::mem;
+
+use crate::compile::Evaluator;
+use crate::integrator::{AdaptiveSolver, Stepper};
+use crate::{
+    delay, FlowFunction, Integrator, Model, ModelError, SimulationResult, SolverStats, SystemState,
+};
+
+/// Fraction of a time step within which a time counts as on a step
+pub(crate) const GRID_TOLERANCE: f64 = 1e-6;
+
+/// The time steps of a model, at `origin + index * dt`.
+#[derive(Clone)]
+struct TimeGrid {
+    origin: f64,
+    /// Index of the current time
+    index: i64,
+    dt: f64,
+    /// Number of time steps between saved results
+    save_every: i64,
+}
+
+impl TimeGrid {
+    /// The steps of the model from `time`. A time within `GRID_TOLERANCE`
+    /// of a step from the start time is on the model's time grid, and
+    /// other times start a grid of their own.
+    fn new(model: &Model, time: f64) -> Self {
+        let dt = model.time_step;
+        let offset = (time - model.start_time) / dt;
+        let (origin, index) = if (offset - offset.round()).abs() <= GRID_TOLERANCE {
+            (model.start_time, offset.round() as i64)
+        } else {
+            (time, 0)
+        };
+        let save_every = model
+            .save_interval
+            .map_or(1, |interval| ((interval / dt).round() as i64).max(1));
+        TimeGrid {
+            origin,
+            index,
+            dt,
+            save_every,
+        }
+    }
+
+    fn time(&self) -> f64 {
+        let steps = self.index as f64;
+        // With a whole number of steps per time unit, dividing by it gives
+        // 0.3 for 3 steps of 0.1 where multiplying gives 0.30000000000000004
+        let per_unit = (1.0 / self.dt).round();
+        if per_unit >= 1.0 && (per_unit * self.dt - 1.0).abs() <= f64::EPSILON {
+            self.origin + steps / per_unit
+        } else {
+            self.origin + steps * self.dt
+        }
+    }
+
+    /// The number of steps to `end`, rounded up unless `end` is within
+    /// `GRID_TOLERANCE` of a step.
+    fn steps_until(&self, end: f64) -> usize {
+        let steps = (end - self.time()) / self.dt;
+        let steps = if (steps - steps.round()).abs() <= GRID_TOLERANCE {
+            steps.round()
+        } else {
+            steps.ceil()
+        };
+        steps.max(0.0) as usize
+    }
+
+    fn on_save_interval(&self) -> bool {
+        self.index.rem_euclid(self.save_every) == 0
+    }
+}
+
+/// How the stocks are advanced, with the state kept between steps.
+enum Integration {
+    Fixed(Stepper),
+    Adaptive(AdaptiveSolver),
+}
+
+impl Integration {
+    fn new(method: Integrator) -> Self {
+        match method {
+            Integrator::DormandPrince(settings) => {
+                Integration::Adaptive(AdaptiveSolver::new(settings))
+            }
+            method => Integration::Fixed(Stepper::new(method)),
+        }
+    }
+}
+
+/// Runs a model one time step at a time, so that it can be inspected and
+/// changed between steps.
+///
+/// Stepping through a period takes the same steps as one `simulate` call
+/// over it. The adaptive method is the exception, as its steps end at the
+/// time each call steps to, which keeps results within its tolerances of
+/// those of one call. The model state follows the simulator after every
+/// call, and results are kept until taken with `take_result`.
+pub struct Simulator<'a> {
+    model: &'a mut Model,
+    evaluator: Evaluator,
+    integration: Integration,
+    /// Stock values in the order of the evaluator's stocks
+    values: Vec<f64>,
+    grid: TimeGrid,
+    /// Results of earlier compilations of the model, and the solver
+    /// statistics not yet in them
+    result: SimulationResult,
+    stats: SolverStats,
+}
+
+impl Model {
+    /// Validates the model and starts stepping through it from the current
+    /// time. The values at the current time are the first saved results.
+    pub fn simulator(&mut self) -> Result<Simulator<'_>, Vec<ModelError>> {
+        let errors = self.validate();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Simulator::new(self).map_err(|error| vec![error])
+    }
+}
+
+impl<'a> Simulator<'a> {
+    pub(crate) fn new(model: &'a mut Model) -> Result<Self, ModelError> {
+        if let Some(error) = model.integrator_errors().into_iter().next() {
+            return Err(error);
+        }
+        for flow in model.flows.values() {
+            model.rate_conversion(flow)?;
+        }
+        let mut state = model.state.clone();
+        let expanded = model.expand(&mut state)?;
+        model.state = state;
+
+        let grid = TimeGrid::new(model, model.state.time);
+        model.state.time = grid.time();
+        let mut evaluator = Evaluator::new(&expanded, &model.state);
+        let values = evaluator.values();
+        evaluator.record(grid.time(), &values, true)?;
+        Ok(Simulator {
+            model,
+            evaluator,
+            integration: Integration::new(expanded.integrator),
+            values,
+            grid,
+            result: SimulationResult::new(),
+            stats: SolverStats::default(),
+        })
+    }
+
+    /// The current simulation time.
+    pub fn time(&self) -> f64 {
+        self.grid.time()
+    }
+
+    /// The model being simulated, with its state at the current time.
+    pub fn model(&self) -> &Model {
+        self.model
+    }
+
+    /// The stock values and calculated variables at the current time.
+    pub fn current_state(&self) -> &SystemState {
+        &self.model.state
+    }
+
+    /// Advances by one time step. Its results are saved when it ends on the
+    /// save interval.
+    pub fn step(&mut self) -> Result<(), ModelError> {
+        self.advance(1, false)
+    }
+
+    /// Advances to `time`, rounded up to a whole number of time steps, and
+    /// saves the results there even off the save interval as `simulate`
+    /// does for the end of its run. Does nothing for a time already
+    /// reached.
+    pub fn step_until(&mut self, time: f64) -> Result<(), ModelError> {
+        let steps = self.grid.steps_until(time);
+        self.advance(steps, true)
+    }
+
+    /// Sets the current value of a stock. The simulation continues from
+    /// the new value, and the next saved results show it.
+    pub fn set_stock_value(&mut self, id: &str, value: f64) -> Result<(), ModelError> {
+        let index = self
+            .evaluator
+            .stock_ids
+            .iter()
+            .position(|stock| stock == id && !delay::is_internal(stock))
+            .ok_or_else(|| ModelError::UnknownId(id.to_string()))?;
+        self.values[index] = value;
+        // Values from before the change no longer lie on the trajectory
+        self.integration = Integration::new(self.model.integrator);
+        self.evaluator.evaluate(self.time(), &self.values)?;
+        self.commit();
+        Ok(())
+    }
+
+    /// Replaces the equation of an auxiliary or flow with a constant.
+    pub fn set_parameter(&mut self, id: &str, value: f64) -> Result<(), ModelError> {
+        let function = if let Some(auxiliary) = self.model.auxiliaries.get_mut(id) {
+            &mut auxiliary.function
+        } else if let Some(flow) = self.model.flows.get_mut(id) {
+            &mut flow.rate_function
+        } else {
+            return Err(ModelError::UnknownId(id.to_string()));
+        };
+        *function = FlowFunction::Constant(value);
+        self.reload()
+    }
+
+    /// Changes the model, for example to replace equations or add elements,
+    /// and continues from the current state. The change is undone when the
+    /// changed model is not valid, and its problems are returned followed
+    /// by any error from continuing with the original model.
+    pub fn update<F>(&mut self, change: F) -> Result<(), Vec<ModelError>>
+    where
+        F: FnOnce(&mut Model),
+    {
+        let original = self.model.clone();
+        change(self.model);
+        let mut errors = self.model.validate();
+        if errors.is_empty() {
+            match self.reload() {
+                Ok(()) => return Ok(()),
+                Err(error) => errors.push(error),
+            }
+        }
+        *self.model = original;
+        if let Err(error) = self.reload() {
+            errors.push(error);
+        }
+        Err(errors)
+    }
+
+    /// Returns the results saved since the last call, which
+    /// `SimulationResult::append` can add to earlier ones.
+    pub fn take_result(&mut self) -> SimulationResult {
+        self.collect();
+        let mut result = mem::take(&mut self.result);
+        result.solver_stats = mem::take(&mut self.stats);
+        result
+    }
+
+    /// Returns the results not yet taken with `take_result`.
+    pub fn into_result(mut self) -> SimulationResult {
+        self.take_result()
+    }
+
+    /// Takes `steps` steps, saving the results of the last one when
+    /// `save_last` is set, and then updates the model state.
+    fn advance(&mut self, steps: usize, save_last: bool) -> Result<(), ModelError> {
+        let outcome = match &mut self.integration {
+            Integration::Fixed(stepper) => {
+                let evaluator = &mut self.evaluator;
+                let mut outcome = Ok(());
+                for step in 1..=steps {
+                    let values = stepper.step(
+                        self.grid.time(),
+                        &self.values,
+                        self.grid.dt,
+                        |time, values| evaluator.derivatives(time, values),
+                    );
+                    self.stats += mem::take(&mut stepper.stats);
+                    if let Err(error) = evaluator.check() {
+                        outcome = Err(error.into());
+                        break;
+                    }
+                    let Some(mut values) = values else {
+                        outcome = Err(ModelError::ConvergenceFailure {
+                            time: self.grid.time(),
+                        });
+                        break;
+                    };
+                    evaluator.clamp(&mut values);
+
+                    let mut grid = self.grid.clone();
+                    grid.index += 1;
+                    let save = grid.on_save_interval() || (save_last && step == steps);
+                    if let Err(error) = evaluator.record(grid.time(), &values, save) {
+                        outcome = Err(error.into());
+                        break;
+                    }
+                    self.grid = grid;
+                    self.values = values;
+                    self.stats.accepted_steps += 1;
+                }
+                outcome
+            }
+            Integration::Adaptive(solver) => {
+                let mut grid = self.grid.clone();
+                let mut output_times = Vec::with_capacity(steps);
+                let mut saves = Vec::with_capacity(steps);
+                for step in 1..=steps {
+                    grid.index += 1;
+                    output_times.push(grid.time());
+                    saves.push(grid.on_save_interval() || (save_last && step == steps));
+                }
+
+                // Outputs are recorded as the solver reaches them, so that
+                // fixed delays see their inputs during the run
+                let evaluator = RefCell::new(&mut self.evaluator);
+                let mut saves = saves.into_iter();
+                let values = solver.integrate(
+                    self.grid.time(),
+                    self.values.clone(),
+                    &output_times,
+                    |time, values| evaluator.borrow_mut().derivatives(time, values),
+                    |values| evaluator.borrow().clamp(values),
+                    |time, values| {
+                        let save = saves.next().unwrap_or_default();
+                        let mut evaluator = evaluator.borrow_mut();
+                        if let Err(error) = evaluator.record(time, values, save) {
+                            evaluator.error.get_or_insert(error);
+                        }
+                    },
+                );
+                self.stats += mem::take(&mut solver.stats);
+                let outcome = self.evaluator.check();
+                if outcome.is_ok() {
+                    self.grid = grid;
+                    self.values = values;
+                } else {
+                    self.evaluator.discard_after(self.grid.time());
+                }
+                outcome.map_err(ModelError::from)
+            }
+        };
+        self.commit();
+        outcome
+    }
+
+    /// Writes the current stock values and variables into the model state.
+    fn commit(&mut self) {
+        self.evaluator.commit(&mut self.model.state, &self.values);
+        self.model.state.time = self.grid.time();
+    }
+
+    /// Moves the results of the evaluator into `result`.
+    fn collect(&mut self) {
+        self.result.append(self.evaluator.take_result());
+        self.stats.evaluations += mem::take(&mut self.evaluator.evaluations);
+    }
+
+    /// Compiles the model again, continuing from the current state.
+    fn reload(&mut self) -> Result<(), ModelError> {
+        self.commit();
+        self.collect();
+        let mut state = self.model.state.clone();
+        let expanded = self.model.expand(&mut state)?;
+        self.model.state = state;
+
+        self.grid = TimeGrid::new(self.model, self.model.state.time);
+        self.evaluator = Evaluator::new(&expanded, &self.model.state);
+        self.values = self.evaluator.values();
+        self.integration = Integration::new(expanded.integrator);
+        Ok(())
+    }
+}
@@ -0,0 +1,774 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Div, Mul};
+
+use crate::{BinaryOp, Expr, Flow, FlowFunction, Function, Model, ModelError, ParseError, UnaryOp};
+
+/// Units of measure, as a product of named base units raised to integer
+/// powers. `people/time` is `people^1 * time^-1`.
+///
+/// Units with no base units are dimensionless, written `dmnl` or `1`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Unit {
+    /// Exponents of the base units, never zero
+    pub factors: BTreeMap<String, i32>,
+}
+
+impl Unit {
+    pub fn dimensionless() -> Self {
+        Self::default()
+    }
+
+    /// A single base unit, such as `people`.
+    pub fn base(name: &str) -> Self {
+        let mut factors = BTreeMap::new();
+        factors.insert(name.to_string(), 1);
+        Self { factors }
+    }
+
+    /// Parses units such as `people/time`, `m^2/s^2` or `1/(person*year)`.
+    ///
+    /// Names are case sensitive. `dmnl`, `dimensionless` and `unitless` in
+    /// any case, and `1`, stand for dimensionless.
+    pub fn parse(units: &str) -> Result<Self, ParseError> {
+        let mut parser = UnitParser {
+            source: units,
+            position: 0,
+        };
+        let unit = parser.product()?;
+        parser.skip_whitespace();
+        if parser.position < units.len() {
+            return Err(parser.error("unexpected character"));
+        }
+        Ok(unit)
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        self.factors.is_empty()
+    }
+
+    /// Raises the units to an integer power.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an exponent overflows, see `checked_powi`.
+    pub fn powi(&self, exponent: i32) -> Self {
+        self.checked_powi(exponent).expect("unit exponent overflow")
+    }
+
+    /// Raises the units to an integer power, `None` if an exponent
+    /// overflows.
+    pub fn checked_powi(&self, exponent: i32) -> Option<Self> {
+        let mut factors = BTreeMap::new();
+        if exponent != 0 {
+            for (name, power) in &self.factors {
+                factors.insert(name.clone(), power.checked_mul(exponent)?);
+            }
+        }
+        Some(Self { factors })
+    }
+
+    /// The product of two units, `None` if an exponent overflows.
+    pub fn checked_mul(&self, other: &Unit) -> Option<Self> {
+        self.clone().combine(other, 1)
+    }
+
+    /// The quotient of two units, `None` if an exponent overflows.
+    pub fn checked_div(&self, other: &Unit) -> Option<Self> {
+        self.clone().combine(other, -1)
+    }
+
+    /// The square root, if every exponent is even.
+    pub fn sqrt(&self) -> Option<Self> {
+        let mut factors = BTreeMap::new();
+        for (name, power) in &self.factors {
+            if power % 2 != 0 {
+                return None;
+            }
+            factors.insert(name.clone(), power / 2);
+        }
+        Some(Self { factors })
+    }
+
+    fn combine(mut self, other: &Unit, sign: i32) -> Option<Self> {
+        for (name, power) in &other.factors {
+            let entry = self.factors.entry(name.clone()).or_insert(0);
+            *entry = entry.checked_add(power.checked_mul(sign)?)?;
+            if *entry == 0 {
+                self.factors.remove(name);
+            }
+        }
+        Some(self)
+    }
+}
+
+impl Mul for &Unit {
+    type Output = Unit;
+
+    /// # Panics
+    ///
+    /// Panics if an exponent overflows, see `Unit::checked_mul`.
+    fn mul(self, rhs: &Unit) -> Unit {
+        self.checked_mul(rhs).expect("unit exponent overflow")
+    }
+}
+
+impl Div for &Unit {
+    type Output = Unit;
+
+    /// # Panics
+    ///
+    /// Panics if an exponent overflows, see `Unit::checked_div`.
+    fn div(self, rhs: &Unit) -> Unit {
+        self.checked_div(rhs).expect("unit exponent overflow")
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_factors(f: &mut fmt::Formatter<'_>, factors: &[(&String, i32)]) -> fmt::Result {
+            for (i, (name, power)) in factors.iter().enumerate() {
+                if i > 0 {
+                    write!(f, "*")?;
+                }
+                write!(f, "{}", name)?;
+                if *power != 1 {
+                    write!(f, "^{}", power)?;
+                }
+            }
+            Ok(())
+        }
+
+        if self.is_dimensionless() {
+            return write!(f, "dmnl");
+        }
+        let numerator: Vec<_> = self
+            .factors
+            .iter()
+            .filter(|(_, power)| **power > 0)
+            .map(|(name, power)| (name, *power))
+            .collect();
+        let denominator: Vec<_> = self
+            .factors
+            .iter()
+            .filter(|(_, power)| **power < 0)
+            .map(|(name, power)| (name, -power))
+            .collect();
+
+        if numerator.is_empty() {
+            write!(f, "1")?;
+        } else {
+            write_factors(f, &numerator)?;
+        }
+        match denominator.len() {
+            0 => Ok(()),
+            1 => {
+                write!(f, "/")?;
+                write_factors(f, &denominator)
+            }
+            _ => {
+                write!(f, "/(")?;
+                write_factors(f, &denominator)?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Recursive-descent parser for units. Products and quotients are
+/// evaluated left to right, so `a/b*c` is `a*c/b`.
+struct UnitParser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl UnitParser<'_> {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            message: message.to_string(),
+            position: self.position,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.source[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.source[self.position..].chars().next()
+    }
+
+    fn product(&mut self) -> Result<Unit, ParseError> {
+        let mut unit = self.power()?;
+        while let Some(operator @ ('*' | '/')) = self.peek() {
+            self.position += 1;
+            let rhs = self.power()?;
+            let product = if operator == '*' {
+                unit.checked_mul(&rhs)
+            } else {
+                unit.checked_div(&rhs)
+            };
+            unit = product.ok_or_else(|| self.error("exponent too large"))?;
+        }
+        Ok(unit)
+    }
+
+    fn power(&mut self) -> Result<Unit, ParseError> {
+        let unit = self.atom()?;
+        if self.peek() != Some('^') {
+            return Ok(unit);
+        }
+        self.position += 1;
+        self.skip_whitespace();
+
+        let rest = &self.source[self.position..];
+        let sign = usize::from(rest.starts_with('-'));
+        let digits = rest[sign..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len() - sign);
+        let exponent = rest[..sign + digits]
+            .parse::<i32>()
+            .map_err(|_| self.error("expected an integer exponent"))?;
+        let unit = unit
+            .checked_powi(exponent)
+            .ok_or_else(|| self.error("exponent too large"))?;
+        self.position += sign + digits;
+        Ok(unit)
+    }
+
+    fn atom(&mut self) -> Result<Unit, ParseError> {
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let unit = self.product()?;
+                if self.peek() != Some(')') {
+                    return Err(self.error("expected ')'"));
+                }
+                self.position += 1;
+                Ok(unit)
+            }
+            Some(c) if c.is_alphanumeric() || matches!(c, '_' | '$' | '%') => {
+                let rest = &self.source[self.position..];
+                let length = rest
+                    .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '$' | '%' | '.')))
+                    .unwrap_or(rest.len());
+                let name = &rest[..length];
+                let dimensionless = ["1", "dmnl", "dimensionless", "unitless"]
+                    .iter()
+                    .any(|keyword| name.eq_ignore_ascii_case(keyword));
+                let unit = match name {
+                    _ if dimensionless => Unit::dimensionless(),
+                    _ if name.starts_with(|c: char| c.is_ascii_digit()) => {
+                        return Err(self.error("numbers other than 1 are not units"))
+                    }
+                    _ => Unit::base(name),
+                };
+                self.position += length;
+                Ok(unit)
+            }
+            Some(_) => Err(self.error("expected a unit name")),
+            None => Err(self.error("unexpected end of units")),
+        }
+    }
+}
+
+/// A unit inconsistency found by `Model::check_units`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnitError {
+    /// The units of an element could not be parsed
+    Invalid { element: String, error: ParseError },
+    /// A flow's units are not the units of a connected stock per model
+    /// time unit
+    FlowMismatch {
+        flow: String,
+        stock: String,
+        expected: Unit,
+        found: Unit,
+    },
+    /// Values with different units are added, compared or otherwise
+    /// combined in an equation
+    Incompatible {
+        element: String,
+        left: Unit,
+        right: Unit,
+    },
+    /// A function argument must be dimensionless, such as the input to `EXP`
+    NotDimensionless {
+        element: String,
+        function: Function,
+        found: Unit,
+    },
+    /// The units of an equation's result differ from the units declared
+    /// for its element
+    EquationMismatch {
+        element: String,
+        declared: Unit,
+        found: Unit,
+    },
+    /// A flow's rate would need converting between calendar time units
+    /// (months, years) and fixed ones (seconds to weeks)
+    AmbiguousConversion { flow: String, from: Unit, to: Unit },
+}
+
+impl fmt::Display for UnitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnitError::Invalid { element, error } => {
+                write!(f, "invalid units for '{}': {}", element, error)
+            }
+            UnitError::FlowMismatch {
+                flow,
+                stock,
+                expected,
+                found,
+            } => write!(
+                f,
+                "flow '{}' has units {} but stock '{}' needs {}",
+                flow, found, stock, expected
+            ),
+            UnitError::Incompatible {
+                element,
+                left,
+                right,
+            } => write!(
+                f,
+                "'{}' combines incompatible units {} and {}",
+                element, left, right
+            ),
+            UnitError::NotDimensionless {
+                element,
+                function,
+                found,
+            } => write!(
+                f,
+                "'{}' passes {} to {}, which needs a dimensionless argument",
+                element,
+                found,
+                function.name()
+            ),
+            UnitError::EquationMismatch {
+                element,
+                declared,
+                found,
+            } => write!(
+                f,
+                "equation of '{}' has units {} but {} are declared",
+                element, found, declared
+            ),
+            UnitError::AmbiguousConversion { flow, from, to } => write!(
+                f,
+                "converting flow '{}' from {} to {} is ambiguous, months and years have no fixed length",
+                flow, from, to
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UnitError {}
+
+impl Model {
+    pub fn set_time_units(&mut self, units: &str) -> &mut Self {
+        self.time_units = units.to_string();
+        self
+    }
+
+    /// The factor that converts a flow's rate from its declared units to
+    /// the units of its stock per model time unit.
+    ///
+    /// Rates are converted when the declared units only differ by their
+    /// time units, such as `people/year` into a `people` stock in a model
+    /// that runs in months. Flows without a stock with units are converted
+    /// by the single time unit in their denominator. The factor is 1 when
+    /// no conversion applies, including when the model time units are not
+    /// a known time unit.
+    pub fn rate_conversion(&self, flow: &Flow) -> Result<f64, ModelError> {
+        let (Ok(found), Ok(time)) = (Unit::parse(&flow.units), Unit::parse(&self.time_units))
+        else {
+            return Ok(1.0);
+        };
+        let stock = flow
+            .from_stock
+            .iter()
+            .chain(&flow.to_stock)
+            .filter_map(|id| self.state.stocks.get(id))
+            .find_map(|stock| Unit::parse(&stock.units).ok());
+        let stock = match stock {
+            Some(stock) => stock,
+            None => {
+                let mut per = found
+                    .factors
+                    .iter()
+                    .filter(|(name, power)| **power < 0 && time_unit(name).is_some());
+                match (per.next(), per.next()) {
+                    (Some((name, _)), None) => match found.checked_mul(&Unit::base(name)) {
+                        Some(stock) => stock,
+                        None => return Ok(1.0),
+                    },
+                    _ => return Ok(1.0),
+                }
+            }
+        };
+        let Some(expected) = stock.checked_div(&time) else {
+            return Ok(1.0);
+        };
+
+        match convert(&found, &expected) {
+            Conversion::Scale(factor) => Ok(factor),
+            Conversion::Ambiguous => Err(ModelError::AmbiguousTimeUnits {
+                flow: flow.id.clone(),
+                units: flow.units.clone(),
+                time_units: self.time_units.clone(),
+            }),
+            Conversion::Same | Conversion::Incompatible => Ok(1.0),
+        }
+    }
+
+    /// Checks that flows move their stock's units per model time unit, and
+    /// that equations combine compatible units.
+    ///
+    /// Elements with empty units are not checked, and numbers in equations
+    /// take whatever units make the equation consistent.
+    pub fn check_units(&self) -> Vec<UnitError> {
+        let mut errors = Vec::new();
+        let time = declared_units("time units", &self.time_units, &mut errors);
+
+        let mut ids: Vec<&String> = self
+            .state
+            .stocks
+            .keys()
+            .chain(self.auxiliaries.keys())
+            .chain(self.flows.keys())
+            .collect();
+        ids.sort();
+        ids.dedup();
+
+        let mut units = BTreeMap::new();
+        for id in &ids {
+            let declared = match (self.state.stocks.get(*id), self.auxiliaries.get(*id)) {
+                (Some(stock), _) => &stock.units,
+                (None, Some(auxiliary)) => &auxiliary.units,
+                (None, None) => &self.flows[*id].units,
+            };
+            if let Some(unit) = declared_units(id, declared, &mut errors) {
+                units.insert(id.to_string(), unit);
+            }
+        }
+
+        let mut checker = UnitChecker {
+            units: &units,
+            time,
+            element: String::new(),
+            errors,
+        };
+        for id in ids {
+            let function = match (self.auxiliaries.get(id), self.flows.get(id)) {
+                (Some(auxiliary), _) => &auxiliary.function,
+                (None, Some(flow)) => &flow.rate_function,
+                (None, None) => continue,
+            };
+            checker.element = id.clone();
+
+            if let (Some(flow), Some(found), Some(time)) =
+                (self.flows.get(id), units.get(id), &checker.time)
+            {
+                for stock in flow.from_stock.iter().chain(&flow.to_stock) {
+                    let Some(stock_unit) = units.get(stock) else {
+                        continue;
+                    };
+                    let Some(expected) = stock_unit.checked_div(time) else {
+                        continue;
+                    };
+                    match convert(found, &expected) {
+                        Conversion::Same | Conversion::Scale(_) => {}
+                        Conversion::Ambiguous => {
+                            checker.errors.push(UnitError::AmbiguousConversion {
+                                flow: id.clone(),
+                                from: found.clone(),
+                                to: expected,
+                            })
+                        }
+                        Conversion::Incompatible => checker.errors.push(UnitError::FlowMismatch {
+                            flow: id.clone(),
+                            stock: stock.clone(),
+                            expected,
+                            found: found.clone(),
+                        }),
+                    }
+                }
+            }
+
+            let found = match function {
+                FlowFunction::Expression(expr) => checker.infer(expr),
+                FlowFunction::Lookup { input, .. } => {
+                    checker.infer(input);
+                    None
+                }
+                FlowFunction::Constant(_) | FlowFunction::Linear { .. } => None,
+            };
+            if let (Some(found), Some(declared)) = (found, units.get(id)) {
+                if found != *declared {
+                    checker.errors.push(UnitError::EquationMismatch {
+                        element: id.clone(),
+                        declared: declared.clone(),
+                        found,
+                    });
+                }
+            }
+        }
+        checker.errors
+    }
+}
+
+/// How a value in one unit is expressed in another.
+enum Conversion {
+    Same,
+    /// Multiply by the factor
+    Scale(f64),
+    /// Only convertible by assuming the length of months or years in days
+    Ambiguous,
+    Incompatible,
+}
+
+/// Kinds of time units. Calendar units can't be converted to fixed ones
+/// without choosing a length for the year.
+#[derive(Clone, Copy, PartialEq)]
+enum Clock {
+    Fixed,
+    Calendar,
+}
+
+/// The kind of a time unit and its length, in seconds for fixed units and
+/// in months for calendar units.
+fn time_unit(name: &str) -> Option<(Clock, f64)> {
+    Some(match name.to_ascii_lowercase().as_str() {
+        "s" | "sec" | "second" | "seconds" => (Clock::Fixed, 1.0),
+        "min" | "minute" | "minutes" => (Clock::Fixed, 60.0),
+        "h" | "hr" | "hour" | "hours" => (Clock::Fixed, 3600.0),
+        "day" | "days" => (Clock::Fixed, 86400.0),
+        "wk" | "week" | "weeks" => (Clock::Fixed, 604800.0),
+        "mo" | "month" | "months" => (Clock::Calendar, 1.0),
+        "yr" | "year" | "years" => (Clock::Calendar, 12.0),
+        _ => return None,
+    })
+}
+
+/// Replaces time units by seconds or months, returning the scale of the
+/// original units relative to the result. `None` if an exponent overflows.
+fn normalize(unit: &Unit, merge_clocks: bool) -> Option<(f64, Unit)> {
+    let mut scale = 1.0;
+    let mut normalized = Unit::dimensionless();
+    for (name, power) in &unit.factors {
+        let base = match time_unit(name) {
+            Some((clock, length)) => {
+                scale *= length.powi(*power);
+                match clock {
+                    Clock::Calendar if !merge_clocks => Unit::base("month"),
+                    _ => Unit::base("second"),
+                }
+            }
+            None => Unit::base(name),
+        };
+        normalized = normalized.checked_mul(&base.checked_powi(*power)?)?;
+    }
+    Some((scale, normalized))
+}
+
+fn convert(from: &Unit, to: &Unit) -> Conversion {
+    if from == to {
+        return Conversion::Same;
+    }
+    let (Some((from_scale, from_normalized)), Some((to_scale, to_normalized))) =
+        (normalize(from, false), normalize(to, false))
+    else {
+        return Conversion::Incompatible;
+    };
+    if from_normalized == to_normalized {
+        Conversion::Scale(from_scale / to_scale)
+    } else if normalize(from, true).map(|(_, unit)| unit)
+        == normalize(to, true).map(|(_, unit)| unit)
+    {
+        Conversion::Ambiguous
+    } else {
+        Conversion::Incompatible
+    }
+}
+
+/// Parses the units declared for an element, `None` if they are empty or
+/// invalid.
+fn declared_units(element: &str, units: &str, errors: &mut Vec<UnitError>) -> Option<Unit> {
+    if units.trim().is_empty() {
+        return None;
+    }
+    match Unit::parse(units) {
+        Ok(unit) => Some(unit),
+        Err(error) => {
+            errors.push(UnitError::Invalid {
+                element: element.to_string(),
+                error,
+            });
+            None
+        }
+    }
+}
+
+/// Infers the units of expressions. `None` stands for units that are not
+/// known, such as those of numbers, and is compatible with anything.
+struct UnitChecker<'a> {
+    units: &'a BTreeMap<String, Unit>,
+    time: Option<Unit>,
+    element: String,
+    errors: Vec<UnitError>,
+}
+
+impl UnitChecker<'_> {
+    fn infer(&mut self, expr: &Expr) -> Option<Unit> {
+        match expr {
+            Expr::Number(_) => None,
+            Expr::Variable(id) => self.units.get(id).cloned(),
+            Expr::Time | Expr::Dt => self.time.clone(),
+            Expr::Unary(UnaryOp::Negate, operand) => self.infer(operand),
+            Expr::Unary(UnaryOp::Not, operand) => {
+                self.infer(operand);
+                Some(Unit::dimensionless())
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.infer(lhs);
+                let rhs = match (op, rhs.as_ref()) {
+                    (BinaryOp::Power, Expr::Number(exponent)) => {
+                        return match lhs {
+                            Some(unit) if exponent.fract() == 0.0 => {
+                                i32::try_from(*exponent as i64)
+                                    .ok()
+                                    .and_then(|exponent| unit.checked_powi(exponent))
+                            }
+                            Some(unit) if unit.is_dimensionless() => Some(unit),
+                            _ => None,
+                        };
+                    }
+                    _ => self.infer(rhs),
+                };
+                match op {
+                    BinaryOp::Add | BinaryOp::Subtract => self.common(lhs, rhs),
+                    BinaryOp::Multiply => lhs?.checked_mul(&rhs?),
+                    BinaryOp::Divide => lhs?.checked_div(&rhs?),
+                    BinaryOp::Power => match lhs {
+                        Some(unit) if unit.is_dimensionless() => Some(unit),
+                        _ => None,
+                    },
+                    BinaryOp::And | BinaryOp::Or => Some(Unit::dimensionless()),
+                    _ => {
+                        // Comparisons
+                        self.common(lhs, rhs);
+                        Some(Unit::dimensionless())
+                    }
+                }
+            }
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.infer(condition);
+                let then = self.infer(then);
+                let otherwise = self.infer(otherwise);
+                self.common(then, otherwise)
+            }
+            Expr::Lookup(_, input) => {
+                self.infer(input);
+                None
+            }
+            Expr::Call(function, args) => {
+                let args: Vec<Option<Unit>> = args.iter().map(|arg| self.infer(arg)).collect();
+                self.call(*function, args)
+            }
+        }
+    }
+
+    fn call(&mut self, function: Function, mut args: Vec<Option<Unit>>) -> Option<Unit> {
+        let time = self.time.clone();
+        match function {
+            Function::Min | Function::Max | Function::Mod => args
+                .into_iter()
+                .reduce(|lhs, rhs| self.common(lhs, rhs))
+                .flatten(),
+            Function::Abs | Function::Int | Function::Trunc | Function::Round => {
+                args.swap_remove(0)
+            }
+            Function::Sqrt => args[0].as_ref().and_then(Unit::sqrt),
+            Function::Exp
+            | Function::Ln
+            | Function::Log10
+            | Function::Sin
+            | Function::Cos
+            | Function::Tan => {
+                if let Some(found) = &args[0] {
+                    if !found.is_dimensionless() {
+                        self.errors.push(UnitError::NotDimensionless {
+                            element: self.element.clone(),
+                            function,
+                            found: found.clone(),
+                        });
+                    }
+                }
+                Some(Unit::dimensionless())
+            }
+            Function::Step => {
+                self.common(args[1].take(), time);
+                args.swap_remove(0)
+            }
+            Function::Pulse => {
+                for arg in args.drain(1..) {
+                    self.common(arg, time.clone());
+                }
+                args[0].take()?.checked_div(&time?)
+            }
+            Function::Ramp => {
+                for arg in args.drain(1..) {
+                    self.common(arg, time.clone());
+                }
+                args[0].take()?.checked_mul(&time?)
+            }
+            Function::SinWave | Function::CosWave => {
+                self.common(args[1].take(), time);
+                args.swap_remove(0)
+            }
+            Function::Trend => {
+                self.common(args[1].take(), time.clone());
+                Unit::dimensionless().checked_div(&time?)
+            }
+            Function::Delay1
+            | Function::Delay3
+            | Function::DelayN
+            | Function::Smth1
+            | Function::Smth3
+            | Function::DelayFixed => {
+                self.common(args[1].take(), time);
+                let initial = match function {
+                    Function::DelayN => args.get_mut(3),
+                    _ => args.get_mut(2),
+                };
+                let initial = initial.and_then(Option::take);
+                self.common(args[0].take(), initial)
+            }
+        }
+    }
+
+    /// The units shared by two operands, reporting them if they differ.
+    fn common(&mut self, lhs: Option<Unit>, rhs: Option<Unit>) -> Option<Unit> {
+        match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => {
+                if lhs != rhs {
+                    self.errors.push(UnitError::Incompatible {
+                        element: self.element.clone(),
+                        left: lhs.clone(),
+                        right: rhs,
+                    });
+                }
+                Some(lhs)
+            }
+            (lhs, rhs) => lhs.or(rhs),
+        }
+    }
+}
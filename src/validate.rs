@@ -0,0 +1,336 @@
+use std::fmt;
+
+use crate::implicit::MAX_BDF_ORDER;
+use crate::simulator::GRID_TOLERANCE;
+use crate::{delay, EvalError, FlowFunction, Integrator, Model};
+
+/// A problem with the structure or settings of a model.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelError {
+    /// A flow or auxiliary refers to a stock that does not exist
+    UnknownStock { element: String, stock: String },
+    /// An equation refers to a variable that is not a stock, auxiliary or
+    /// flow of the model
+    UnknownVariable { element: String, variable: String },
+    /// An equation calls a lookup table that was not added to the model
+    UnknownLookup { element: String, lookup: String },
+    /// Two stocks, flows, auxiliaries or lookup tables share an ID, or one
+    /// was added twice and the first one was replaced
+    DuplicateId(String),
+    /// The time step is zero, negative or not a number
+    InvalidTimeStep(f64),
+    /// The stop time is not after the start time
+    InvalidStopTime { start: f64, stop: f64 },
+    /// The save interval is not a whole multiple of the time step
+    InvalidSaveInterval { interval: f64, time_step: f64 },
+    /// The model was run to its stop time without one
+    MissingStopTime,
+    /// An adaptive integrator's tolerances are not positive and finite
+    InvalidTolerances { absolute: f64, relative: f64 },
+    /// An adaptive integrator's minimum step is not positive and finite,
+    /// or is greater than its maximum step
+    InvalidStepBounds { min: f64, max: f64 },
+    /// The maximum order of a BDF integrator is not from 1 to 5
+    InvalidBdfOrder(usize),
+    /// An implicit integrator could not solve for the values at the end of
+    /// a step, even after cutting it into smaller steps
+    ConvergenceFailure { time: f64 },
+    /// A stock, flow or auxiliary to change during a simulation does not
+    /// exist
+    UnknownId(String),
+    /// Auxiliaries and flows are defined in terms of each other, each one
+    /// depending on the next and the last on the first
+    CircularDefinition { ids: Vec<String> },
+    /// A stock's minimum is greater than its maximum
+    InvalidBounds { stock: String, min: f64, max: f64 },
+    /// A stock starts outside its minimum and maximum
+    InitialValueOutOfBounds { stock: String, value: f64 },
+    /// A flow's rate can only be converted to the model time units by
+    /// assuming the length of months or years in days
+    AmbiguousTimeUnits {
+        flow: String,
+        units: String,
+        time_units: String,
+    },
+    /// An equation failed to evaluate during the simulation, or a stateful
+    /// function has invalid arguments
+    Evaluation(EvalError),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::UnknownStock { element, stock } => {
+                write!(f, "'{}' refers to unknown stock '{}'", element, stock)
+            }
+            ModelError::UnknownVariable { element, variable } => {
+                write!(f, "'{}' refers to unknown variable '{}'", element, variable)
+            }
+            ModelError::UnknownLookup { element, lookup } => {
+                write!(f, "'{}' calls unknown lookup table '{}'", element, lookup)
+            }
+            ModelError::DuplicateId(id) => write!(f, "ID '{}' is used more than once", id),
+            ModelError::InvalidTimeStep(dt) => {
+                write!(f, "time step must be positive, found {}", dt)
+            }
+            ModelError::InvalidStopTime { start, stop } => {
+                write!(f, "stop time {} is not after start time {}", stop, start)
+            }
+            ModelError::InvalidSaveInterval {
+                interval,
+                time_step,
+            } => write!(
+                f,
+                "save interval {} is not a whole multiple of the time step {}",
+                interval, time_step
+            ),
+            ModelError::MissingStopTime => write!(f, "the model has no stop time"),
+            ModelError::InvalidTolerances { absolute, relative } => write!(
+                f,
+                "tolerances must be positive, found absolute {} and relative {}",
+                absolute, relative
+            ),
+            ModelError::InvalidStepBounds { min, max } => write!(
+                f,
+                "step bounds must be positive with minimum at most maximum, found {} and {}",
+                min, max
+            ),
+            ModelError::InvalidBdfOrder(order) => write!(
+                f,
+                "BDF order must be from 1 to {}, found {}",
+                MAX_BDF_ORDER, order
+            ),
+            ModelError::ConvergenceFailure { time } => write!(
+                f,
+                "the implicit integrator did not converge on the step from time {}",
+                time
+            ),
+            ModelError::UnknownId(id) => {
+                write!(f, "'{}' is not a stock, flow or auxiliary of the model", id)
+            }
+            ModelError::CircularDefinition { ids } => {
+                let path: Vec<_> = ids
+                    .iter()
+                    .chain(ids.first())
+                    .map(|id| format!("'{}'", id))
+                    .collect();
+                write!(f, "circular definition {}", path.join(" -> "))
+            }
+            ModelError::InvalidBounds { stock, min, max } => write!(
+                f,
+                "stock '{}' has minimum {} greater than maximum {}",
+                stock, min, max
+            ),
+            ModelError::InitialValueOutOfBounds { stock, value } => write!(
+                f,
+                "stock '{}' starts at {}, outside its bounds",
+                stock, value
+            ),
+            ModelError::AmbiguousTimeUnits {
+                flow,
+                units,
+                time_units,
+            } => write!(
+                f,
+                "flow '{}' in {} cannot be converted to {} without assuming the length of a month or year",
+                flow, units, time_units
+            ),
+            ModelError::Evaluation(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+impl From<EvalError> for ModelError {
+    fn from(error: EvalError) -> Self {
+        ModelError::Evaluation(error)
+    }
+}
+
+impl Model {
+    /// Checks the model for problems that would make a simulation silently
+    /// wrong, such as references to misspelled IDs.
+    ///
+    /// Returns every problem found, in the same order for the same model.
+    /// An empty list means the model can be simulated.
+    pub fn validate(&self) -> Vec<ModelError> {
+        let mut errors = Vec::new();
+
+        if !(self.time_step.is_finite() && self.time_step > 0.0) {
+            errors.push(ModelError::InvalidTimeStep(self.time_step));
+        } else if let Some(interval) = self.save_interval {
+            let steps = interval / self.time_step;
+            if !(steps.round() >= 1.0 && (steps - steps.round()).abs() <= GRID_TOLERANCE) {
+                errors.push(ModelError::InvalidSaveInterval {
+                    interval,
+                    time_step: self.time_step,
+                });
+            }
+        }
+        if let Some(stop) = self.stop_time {
+            if !(stop.is_finite() && stop > self.start_time) {
+                errors.push(ModelError::InvalidStopTime {
+                    start: self.start_time,
+                    stop,
+                });
+            }
+        }
+
+        errors.extend(self.integrator_errors());
+
+        let mut duplicates = self.duplicates.clone();
+        duplicates.extend(
+            self.flows
+                .keys()
+                .chain(self.auxiliaries.keys())
+                .filter(|id| self.state.stocks.contains_key(*id))
+                .cloned(),
+        );
+        duplicates.extend(
+            self.flows
+                .keys()
+                .filter(|id| self.auxiliaries.contains_key(*id))
+                .cloned(),
+        );
+        duplicates.extend(
+            self.state
+                .lookups
+                .keys()
+                .filter(|id| {
+                    self.state.stocks.contains_key(*id)
+                        || self.flows.contains_key(*id)
+                        || self.auxiliaries.contains_key(*id)
+                })
+                .cloned(),
+        );
+        duplicates.sort();
+        duplicates.dedup();
+        errors.extend(duplicates.into_iter().map(ModelError::DuplicateId));
+
+        let mut stocks: Vec<_> = self
+            .state
+            .stocks
+            .values()
+            .filter(|stock| !delay::is_internal(&stock.id))
+            .collect();
+        stocks.sort_by(|a, b| a.id.cmp(&b.id));
+        for stock in stocks {
+            if let (Some(min), Some(max)) = (stock.min_value, stock.max_value) {
+                if min > max {
+                    errors.push(ModelError::InvalidBounds {
+                        stock: stock.id.clone(),
+                        min,
+                        max,
+                    });
+                    continue;
+                }
+            }
+            if stock.clamp(stock.initial_value) != stock.initial_value {
+                errors.push(ModelError::InitialValueOutOfBounds {
+                    stock: stock.id.clone(),
+                    value: stock.initial_value,
+                });
+            }
+        }
+
+        let mut elements: Vec<(&String, &FlowFunction, Vec<&String>)> = Vec::new();
+        for flow in self.flows.values() {
+            let stocks = flow.from_stock.iter().chain(&flow.to_stock).collect();
+            elements.push((&flow.id, &flow.rate_function, stocks));
+        }
+        for auxiliary in self.auxiliaries.values() {
+            elements.push((&auxiliary.id, &auxiliary.function, Vec::new()));
+        }
+        elements.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (id, function, connected) in elements {
+            if let Some(flow) = self.flows.get(id) {
+                if let Err(error) = self.rate_conversion(flow) {
+                    errors.push(error);
+                }
+            }
+            for stock in connected {
+                if !self.state.stocks.contains_key(stock) {
+                    errors.push(ModelError::UnknownStock {
+                        element: id.clone(),
+                        stock: stock.clone(),
+                    });
+                }
+            }
+            match function {
+                FlowFunction::Constant(_) => {}
+                FlowFunction::Linear { input_stock, .. } => {
+                    if !self.state.stocks.contains_key(input_stock) {
+                        errors.push(ModelError::UnknownStock {
+                            element: id.clone(),
+                            stock: input_stock.clone(),
+                        });
+                    }
+                }
+                FlowFunction::Expression(expr) | FlowFunction::Lookup { input: expr, .. } => {
+                    for variable in expr.variables() {
+                        if !self.state.stocks.contains_key(variable)
+                            && !self.auxiliaries.contains_key(variable)
+                            && !self.flows.contains_key(variable)
+                        {
+                            errors.push(ModelError::UnknownVariable {
+                                element: id.clone(),
+                                variable: variable.to_string(),
+                            });
+                        }
+                    }
+                    for lookup in expr.lookups() {
+                        if !self.state.lookups.contains_key(lookup) {
+                            errors.push(ModelError::UnknownLookup {
+                                element: id.clone(),
+                                lookup: lookup.to_string(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        // Loops through stateful functions are broken by their stocks
+        match delay::expand(self) {
+            Ok(expansion) => errors.extend(
+                expansion
+                    .model
+                    .circular_definitions()
+                    .into_iter()
+                    .map(|ids| ModelError::CircularDefinition { ids }),
+            ),
+            Err(error) => errors.push(ModelError::Evaluation(error)),
+        }
+        errors
+    }
+
+    /// Problems with the settings of the integrator, which would make it
+    /// fail or never finish.
+    pub(crate) fn integrator_errors(&self) -> Vec<ModelError> {
+        let mut errors = Vec::new();
+
+        if let Integrator::Bdf { max_order } = self.integrator {
+            if !(1..=MAX_BDF_ORDER).contains(&max_order) {
+                errors.push(ModelError::InvalidBdfOrder(max_order));
+            }
+        }
+        if let Integrator::DormandPrince(settings) = self.integrator {
+            let positive = |value: f64| value.is_finite() && value > 0.0;
+            if !(positive(settings.absolute_tolerance) && positive(settings.relative_tolerance)) {
+                errors.push(ModelError::InvalidTolerances {
+                    absolute: settings.absolute_tolerance,
+                    relative: settings.relative_tolerance,
+                });
+            }
+            if !(positive(settings.min_step) && settings.min_step <= settings.max_step) {
+                errors.push(ModelError::InvalidStepBounds {
+                    min: settings.min_step,
+                    max: settings.max_step,
+                });
+            }
+        }
+        errors
+    }
+}
@@ -0,0 +1,670 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::xmile::{canonical, initialize_stocks};
+use crate::{Auxiliary, BinaryOp, Expr, Flow, FlowFunction, LookupTable, Model, ParseError, Stock};
+use crate::{SystemState, UnaryOp};
+
+/// A Vensim model could not be read into a model.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VensimError {
+    /// An equation or section is malformed
+    Invalid(String),
+    /// The model uses a construct oxidyn cannot represent, such as
+    /// subscripts, macros or a function oxidyn does not have
+    Unsupported(String),
+    /// An equation could not be parsed
+    Equation { element: String, error: ParseError },
+}
+
+impl fmt::Display for VensimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VensimError::Invalid(message) => write!(f, "invalid Vensim model: {}", message),
+            VensimError::Unsupported(message) => write!(f, "unsupported Vensim: {}", message),
+            VensimError::Equation { element, error } => {
+                write!(f, "invalid equation for '{}': {}", element, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VensimError {}
+
+/// Names of the control section variables, as IDs.
+const INITIAL_TIME: &str = "initial_time";
+const FINAL_TIME: &str = "final_time";
+const TIME_STEP: &str = "time_step";
+const SAVEPER: &str = "saveper";
+
+impl Model {
+    /// Reads a model from the text of a Vensim `.mdl` file.
+    ///
+    /// Variable names become IDs as for XMILE. `INTEG` variables become
+    /// stocks: when the rate is a sum and difference of variables, those
+    /// variables become the stock's inflows and outflows, and otherwise the
+    /// rate becomes a flow named `<stock>_net_flow`. The control section
+    /// sets the start time, stop time, time step, save interval (`SAVEPER`)
+    /// and time units.
+    pub fn from_vensim(source: &str) -> Result<Model, VensimError> {
+        let mut equations = Vec::new();
+        for text in sections(source) {
+            let mut parts = text.splitn(3, '~');
+            let equation = parts.next().unwrap_or_default().trim();
+            // Group headers, such as the control section's
+            if equation.is_empty() || equation.starts_with('*') {
+                continue;
+            }
+            let units = parts.next().unwrap_or_default();
+            equations.push(Equation::read(equation, units)?);
+        }
+
+        let mut model = Model::new("model");
+        let lookups: HashSet<String> = equations
+            .iter()
+            .filter(|equation| matches!(equation.definition, Definition::Lookup(_)))
+            .map(|equation| equation.id.clone())
+            .collect();
+        let mut reader = EquationReader {
+            lookups,
+            control: HashMap::new(),
+        };
+        let control = reader.control(&equations)?;
+
+        let mut stocks = Vec::new();
+        let mut variables = Vec::new();
+        let mut initial_values = Vec::new();
+        for equation in &equations {
+            let id = &equation.id;
+            match &equation.definition {
+                Definition::Lookup(table) => {
+                    model.add_lookup(id, lookup_table(table, id)?);
+                }
+                Definition::Equation(_) if is_control(id) => {}
+                Definition::Equation(text) => match call(text) {
+                    Some((name, args)) if function_name(name) == "INTEG" => {
+                        let [rate, initial] = args[..] else {
+                            return Err(VensimError::Invalid(format!(
+                                "INTEG takes 2 arguments in '{}'",
+                                id
+                            )));
+                        };
+                        let rate = reader.equation(rate, id)?;
+                        initial_values.push((id.clone(), reader.equation(initial, id)?));
+                        model.add_stock(Stock::new(id, &equation.name, 0.0, &equation.units));
+                        stocks.push((equation, rate));
+                    }
+                    Some((name, args)) if function_name(name) == "WITH_LOOKUP" => {
+                        let [input, table] = args[..] else {
+                            return Err(VensimError::Invalid(format!(
+                                "WITH LOOKUP takes 2 arguments in '{}'",
+                                id
+                            )));
+                        };
+                        let table = table
+                            .trim()
+                            .strip_prefix('(')
+                            .and_then(|table| table.strip_suffix(')'))
+                            .ok_or_else(|| {
+                                VensimError::Invalid(format!("invalid lookup in '{}'", id))
+                            })?;
+                        let function = FlowFunction::Lookup {
+                            table: lookup_table(table, id)?,
+                            input: reader.equation(input, id)?,
+                        };
+                        variables.push((equation, function));
+                    }
+                    _ => {
+                        let function = match reader.equation(text, id)? {
+                            Expr::Number(value) => FlowFunction::Constant(value),
+                            expr => FlowFunction::Expression(expr),
+                        };
+                        variables.push((equation, function));
+                    }
+                },
+            }
+        }
+
+        // Variables that a stock's rate adds or subtracts become its flows
+        let mut candidates: HashSet<&str> = variables
+            .iter()
+            .map(|(equation, _)| equation.id.as_str())
+            .collect();
+        let mut inflows: HashMap<String, String> = HashMap::new();
+        let mut outflows: HashMap<String, String> = HashMap::new();
+        let mut net_flows = Vec::new();
+        for (stock, rate) in stocks {
+            let mut terms = Vec::new();
+            let split = flow_terms(&rate, true, &mut terms)
+                && terms.iter().all(|(id, inflow)| {
+                    let connections = if *inflow { &inflows } else { &outflows };
+                    candidates.contains(id.as_str()) && !connections.contains_key(id)
+                })
+                && terms
+                    .iter()
+                    .enumerate()
+                    .all(|(i, term)| !terms[..i].contains(term));
+            if !split {
+                net_flows.push(Flow {
+                    id: format!("{}_net_flow", stock.id),
+                    name: format!("{} net flow", stock.name),
+                    from_stock: None,
+                    to_stock: Some(stock.id.clone()),
+                    rate_function: FlowFunction::Expression(rate),
+                    units: String::new(),
+                });
+                continue;
+            }
+            for (id, inflow) in terms {
+                let connections = if inflow { &mut inflows } else { &mut outflows };
+                connections.insert(id, stock.id.clone());
+            }
+        }
+        candidates.retain(|id| inflows.contains_key(*id) || outflows.contains_key(*id));
+
+        for (equation, function) in variables {
+            let id = &equation.id;
+            if candidates.contains(id.as_str()) {
+                model.add_flow(Flow {
+                    id: id.clone(),
+                    name: equation.name.clone(),
+                    from_stock: outflows.remove(id),
+                    to_stock: inflows.remove(id),
+                    rate_function: function,
+                    units: equation.units.clone(),
+                });
+            } else {
+                model.add_auxiliary(Auxiliary {
+                    id: id.clone(),
+                    name: equation.name.clone(),
+                    function,
+                    units: equation.units.clone(),
+                });
+            }
+        }
+        for flow in net_flows {
+            model.add_flow(flow);
+        }
+
+        control.apply(&mut model);
+        initialize_stocks(&mut model, initial_values).map_err(|(id, error)| {
+            VensimError::Invalid(format!(
+                "cannot calculate the initial value of '{}': {}",
+                id, error
+            ))
+        })?;
+        Ok(model)
+    }
+}
+
+/// The `|` terminated sections of the equations part of a model file,
+/// without the encoding marker and the sketch that follows the equations.
+fn sections(source: &str) -> impl Iterator<Item = &str> {
+    let mut equations = source.trim_start();
+    if equations.starts_with("{UTF-8}") {
+        equations = &equations["{UTF-8}".len()..];
+    }
+    for end in ["\\\\\\---///", "///---\\\\\\"] {
+        if let Some(position) = equations.find(end) {
+            equations = &equations[..position];
+        }
+    }
+    equations
+        .split('|')
+        .filter(|section| !section.trim().is_empty())
+}
+
+/// The left hand side and definition of one equation, with its units.
+struct Equation {
+    id: String,
+    name: String,
+    definition: Definition,
+    /// Units without the optional `[min, max]` range
+    units: String,
+}
+
+enum Definition {
+    /// The text after `=`
+    Equation(String),
+    /// The points of a standalone lookup, between its parentheses
+    Lookup(String),
+}
+
+impl Equation {
+    fn read(text: &str, units: &str) -> Result<Equation, VensimError> {
+        // Lines ending with a backslash continue on the next line
+        let text = text.replace("\\\r\n", " ").replace("\\\n", " ");
+        let (name, rest) = match text.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').ok_or_else(|| {
+                    VensimError::Invalid(format!("unterminated name in '{}'", text.trim()))
+                })?;
+                (&quoted[..end], quoted[end + 1..].trim_start())
+            }
+            None => {
+                let end = text.find(['=', '(', '[', ':']).unwrap_or(text.len());
+                (text[..end].trim(), &text[end..])
+            }
+        };
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        let id = canonical(&name);
+
+        let definition = if rest.starts_with("==") || rest.starts_with(":=") {
+            return Err(VensimError::Unsupported(format!(
+                "data equations ('{}')",
+                id
+            )));
+        } else if let Some(equation) = rest.strip_prefix('=') {
+            Definition::Equation(equation.trim().to_string())
+        } else if rest.starts_with('(') {
+            let table = rest
+                .trim_end()
+                .strip_suffix(')')
+                .ok_or_else(|| VensimError::Invalid(format!("unterminated lookup '{}'", id)))?;
+            Definition::Lookup(table[1..].to_string())
+        } else if rest.starts_with('[') {
+            return Err(VensimError::Unsupported(format!("subscripts ('{}')", id)));
+        } else if rest.to_ascii_uppercase().starts_with(":MACRO:") {
+            return Err(VensimError::Unsupported("macros".to_string()));
+        } else if rest.starts_with(':') {
+            return Err(VensimError::Unsupported(format!(
+                "equation type '{}' ('{}')",
+                rest.split_whitespace().next().unwrap_or_default(),
+                id
+            )));
+        } else {
+            return Err(VensimError::Unsupported(format!(
+                "data variables ('{}')",
+                id
+            )));
+        };
+
+        let units = units.split('[').next().unwrap_or_default().trim();
+        Ok(Equation {
+            id,
+            name,
+            definition,
+            units: units.to_string(),
+        })
+    }
+}
+
+fn is_control(id: &str) -> bool {
+    [INITIAL_TIME, FINAL_TIME, TIME_STEP, SAVEPER].contains(&id)
+}
+
+/// Simulation settings from the control section.
+struct Control {
+    initial_time: f64,
+    final_time: Option<f64>,
+    time_step: f64,
+    save_interval: Option<f64>,
+    time_units: Option<String>,
+}
+
+impl Control {
+    fn apply(&self, model: &mut Model) {
+        model.set_start_time(self.initial_time);
+        model.stop_time = self.final_time;
+        model.save_interval = self.save_interval;
+        model.set_time_step(self.time_step);
+        if let Some(time_units) = &self.time_units {
+            model.set_time_units(time_units);
+        }
+    }
+}
+
+/// Reads the points of a lookup, with an optional `[(xmin,ymin)-(xmax,ymax)]`
+/// range first. Vensim lookups hold their end values out of range.
+fn lookup_table(text: &str, id: &str) -> Result<LookupTable, VensimError> {
+    let invalid = || VensimError::Invalid(format!("invalid lookup '{}'", id));
+    let mut rest = text.trim_start();
+    if rest.starts_with('[') {
+        let end = rest.find(']').ok_or_else(invalid)?;
+        rest = rest[end + 1..].trim_start();
+    }
+
+    let mut points = Vec::new();
+    while !rest.is_empty() {
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+        let point = rest.strip_prefix('(').ok_or_else(invalid)?;
+        let end = point.find(')').ok_or_else(invalid)?;
+        let (x, y) = point[..end].split_once(',').ok_or_else(invalid)?;
+        let x = x.trim().parse::<f64>().map_err(|_| invalid())?;
+        let y = y.trim().parse::<f64>().map_err(|_| invalid())?;
+        points.push((x, y));
+        rest = point[end + 1..].trim_start();
+    }
+    if points.is_empty() {
+        return Err(invalid());
+    }
+    Ok(LookupTable::new(points))
+}
+
+/// Splits `NAME(arguments)` into the name and its top level arguments, if
+/// the text is a single call.
+fn call(text: &str) -> Option<(&str, Vec<&str>)> {
+    let start = text.find('(')?;
+    let name = text[..start].trim();
+    if name.is_empty() || name.starts_with('"') {
+        return None;
+    }
+    let (args, end) = arguments(text, start)?;
+    text[end..].trim().is_empty().then_some((name, args))
+}
+
+/// The top level arguments of the call whose `(` is at `start`, and the
+/// offset after its closing `)`.
+fn arguments(text: &str, start: usize) -> Option<(Vec<&str>, usize)> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut arg_start = start + 1;
+    for (i, c) in text.char_indices().skip_while(|(i, _)| *i <= start) {
+        match c {
+            '"' => quoted = !quoted,
+            _ if quoted => {}
+            '(' | '[' => depth += 1,
+            ')' if depth == 0 => {
+                let last = text[arg_start..i].trim();
+                if !(args.is_empty() && last.is_empty()) {
+                    args.push(last);
+                }
+                return Some((args, i + 1));
+            }
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(text[arg_start..i].trim());
+                arg_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The upper case name of a Vensim function, with underscores for spaces.
+fn function_name(name: &str) -> String {
+    canonical(name).to_uppercase()
+}
+
+/// Collects the variables that a rate adds (inflows) or subtracts
+/// (outflows), returning false if it is anything other than a sum and
+/// difference of variables.
+fn flow_terms(expr: &Expr, inflow: bool, terms: &mut Vec<(String, bool)>) -> bool {
+    match expr {
+        Expr::Variable(id) => {
+            terms.push((id.clone(), inflow));
+            true
+        }
+        Expr::Unary(UnaryOp::Negate, operand) => flow_terms(operand, !inflow, terms),
+        Expr::Binary(BinaryOp::Add, lhs, rhs) => {
+            flow_terms(lhs, inflow, terms) && flow_terms(rhs, inflow, terms)
+        }
+        Expr::Binary(BinaryOp::Subtract, lhs, rhs) => {
+            flow_terms(lhs, inflow, terms) && flow_terms(rhs, !inflow, terms)
+        }
+        _ => false,
+    }
+}
+
+/// Translates Vensim equations into oxidyn expressions.
+struct EquationReader {
+    /// IDs of the standalone lookups equations can call
+    lookups: HashSet<String>,
+    /// Values of the control variables other than `TIME STEP`
+    control: HashMap<&'static str, f64>,
+}
+
+impl EquationReader {
+    /// Evaluates the control section. Each control variable may refer to
+    /// the ones before it.
+    fn control(&mut self, equations: &[Equation]) -> Result<Control, VensimError> {
+        let mut state = SystemState::new();
+        state.dt = 1.0;
+        let mut values = HashMap::new();
+        let mut time_units = None;
+        for id in [INITIAL_TIME, FINAL_TIME, TIME_STEP, SAVEPER] {
+            let Some(equation) = equations.iter().find(|equation| equation.id == id) else {
+                continue;
+            };
+            let Definition::Equation(text) = &equation.definition else {
+                return Err(VensimError::Invalid(format!("'{}' is a lookup", id)));
+            };
+            let value = self
+                .equation(text, id)?
+                .eval(&state)
+                .map_err(|error| VensimError::Invalid(format!("'{}': {}", id, error)))?;
+            if !value.is_finite() || (matches!(id, TIME_STEP | SAVEPER) && value <= 0.0) {
+                return Err(VensimError::Invalid(format!(
+                    "'{}' must be positive, found {}",
+                    id, value
+                )));
+            }
+            match id {
+                INITIAL_TIME => state.time = value,
+                TIME_STEP => state.dt = value,
+                _ => {}
+            }
+            if id != TIME_STEP {
+                self.control.insert(id, value);
+            }
+            if time_units.is_none() && !equation.units.is_empty() {
+                time_units = Some(equation.units.clone());
+            }
+            values.insert(id, value);
+        }
+
+        Ok(Control {
+            initial_time: values.get(INITIAL_TIME).copied().unwrap_or(0.0),
+            final_time: values.get(FINAL_TIME).copied(),
+            time_step: values.get(TIME_STEP).copied().unwrap_or(1.0),
+            save_interval: values.get(SAVEPER).copied(),
+            time_units,
+        })
+    }
+
+    fn equation(&self, text: &str, id: &str) -> Result<Expr, VensimError> {
+        let translated = self.translate(text, id)?;
+        Expr::parse(&translated).map_err(|error| VensimError::Equation {
+            element: id.to_string(),
+            error,
+        })
+    }
+
+    /// Rewrites a Vensim equation in oxidyn syntax: names become IDs,
+    /// functions get their oxidyn names and argument order, and
+    /// `{comments}` are removed. Calls to functions oxidyn does not have
+    /// are reported.
+    fn translate(&self, equation: &str, id: &str) -> Result<String, VensimError> {
+        let mut translated = String::with_capacity(equation.len());
+        let mut chars = equation.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            match c {
+                '{' => {
+                    for (_, c) in chars.by_ref() {
+                        if c == '}' {
+                            break;
+                        }
+                    }
+                    translated.push(' ');
+                }
+                ':' => {
+                    let mut end = start + 1;
+                    for (i, c) in chars.by_ref() {
+                        end = i + c.len_utf8();
+                        if c == ':' {
+                            break;
+                        }
+                    }
+                    match equation[start..end].to_ascii_uppercase().as_str() {
+                        ":AND:" => translated.push_str(" AND "),
+                        ":OR:" => translated.push_str(" OR "),
+                        ":NOT:" => translated.push_str(" NOT "),
+                        operator => {
+                            return Err(VensimError::Unsupported(format!(
+                                "operator {} in '{}'",
+                                operator, id
+                            )))
+                        }
+                    }
+                }
+                '[' => return Err(VensimError::Unsupported(format!("subscripts ('{}')", id))),
+                c if c.is_ascii_digit() || c == '.' => {
+                    translated.push(c);
+                    let mut exponent = false;
+                    while let Some(&(_, c)) = chars.peek() {
+                        let sign = exponent && matches!(c, '+' | '-');
+                        exponent = matches!(c, 'e' | 'E');
+                        if !(c.is_ascii_alphanumeric() || c == '.' || sign) {
+                            break;
+                        }
+                        translated.push(c);
+                        chars.next();
+                    }
+                }
+                '"' | '_' | '$' => self.name(equation, start, &mut chars, &mut translated, id)?,
+                c if c.is_alphabetic() => {
+                    self.name(equation, start, &mut chars, &mut translated, id)?
+                }
+                c => translated.push(c),
+            }
+        }
+        Ok(translated)
+    }
+
+    /// Translates the name starting at `start`, and its arguments if it is
+    /// a call. Unquoted names may contain single spaces.
+    fn name(
+        &self,
+        equation: &str,
+        start: usize,
+        chars: &mut std::iter::Peekable<std::str::CharIndices>,
+        translated: &mut String,
+        id: &str,
+    ) -> Result<(), VensimError> {
+        let mut end = start + 1;
+        if equation[start..].starts_with('"') {
+            for (i, c) in chars.by_ref() {
+                end = i + 1;
+                if c == '"' {
+                    break;
+                }
+            }
+        } else {
+            while let Some(&(i, c)) = chars.peek() {
+                let continues = c.is_alphanumeric()
+                    || matches!(c, '_' | '$' | '\'')
+                    || (c == ' '
+                        && equation[i..]
+                            .trim_start()
+                            .starts_with(|c: char| c.is_alphanumeric() || c == '_'));
+                if !continues {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+        }
+        let name = equation[start..end].trim_matches('"');
+
+        let rest = &equation[end..];
+        if !rest.trim_start().starts_with('(') {
+            let variable = canonical(name);
+            match variable.as_str() {
+                "time" => translated.push_str("TIME"),
+                TIME_STEP => translated.push_str("DT"),
+                _ => match self.control.get(variable.as_str()) {
+                    Some(value) => translated.push_str(&format!("({})", value)),
+                    None => translated.push_str(&Expr::Variable(variable).to_string()),
+                },
+            }
+            return Ok(());
+        }
+
+        let open = end + rest.find('(').unwrap_or_default();
+        let (args, close) = arguments(equation, open)
+            .ok_or_else(|| VensimError::Invalid(format!("missing ')' in '{}'", id)))?;
+        while chars.peek().is_some_and(|&(i, _)| i < close) {
+            chars.next();
+        }
+        let args = args
+            .into_iter()
+            .map(|arg| self.translate(arg, id))
+            .collect::<Result<Vec<_>, _>>()?;
+        translated.push_str(&self.call(name, &args, id)?);
+        Ok(())
+    }
+
+    /// An oxidyn expression for a Vensim function call with translated
+    /// arguments.
+    fn call(&self, name: &str, args: &[String], id: &str) -> Result<String, VensimError> {
+        let function = function_name(name);
+        let arity = |count: usize| {
+            if args.len() == count {
+                Ok(())
+            } else {
+                Err(VensimError::Invalid(format!(
+                    "{} takes {} arguments, found {} in '{}'",
+                    name.to_uppercase(),
+                    count,
+                    args.len(),
+                    id
+                )))
+            }
+        };
+        let renamed = match function.as_str() {
+            "IF_THEN_ELSE" => {
+                arity(3)?;
+                return Ok(format!(
+                    "(IF {} THEN {} ELSE {})",
+                    args[0], args[1], args[2]
+                ));
+            }
+            "PULSE" => {
+                arity(2)?;
+                return Ok(format!(
+                    "(STEP(1, {start}) - STEP(1, ({start}) + ({})))",
+                    args[1],
+                    start = args[0]
+                ));
+            }
+            "LOG" => {
+                arity(2)?;
+                return Ok(format!("(LN({}) / LN({}))", args[0], args[1]));
+            }
+            "DELAY_N" => {
+                arity(4)?;
+                return Ok(format!(
+                    "DELAYN({}, {}, {}, {})",
+                    args[0], args[1], args[3], args[2]
+                ));
+            }
+            "ABS" | "EXP" | "LN" | "SQRT" | "SIN" | "COS" | "TAN" | "MIN" | "MAX" | "STEP"
+            | "RAMP" | "DELAY1" | "DELAY3" | "TREND" => function.as_str(),
+            "INTEGER" => "TRUNC",
+            "MODULO" => "MOD",
+            "DELAY1I" => "DELAY1",
+            "DELAY3I" => "DELAY3",
+            "DELAY_FIXED" => "DELAYFIXED",
+            "SMOOTH" | "SMOOTHI" => "SMTH1",
+            "SMOOTH3" | "SMOOTH3I" => "SMTH3",
+            _ if self.lookups.contains(&canonical(name)) => {
+                arity(1)?;
+                let table = Expr::Variable(canonical(name)).to_string();
+                return Ok(format!("LOOKUP({}, {})", table, args[0]));
+            }
+            _ => {
+                return Err(VensimError::Unsupported(format!(
+                    "function {} in '{}'",
+                    name.split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                        .to_uppercase(),
+                    id
+                )))
+            }
+        };
+        Ok(format!("{}({})", renamed, args.join(", ")))
+    }
+}
@@ -0,0 +1,905 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::xml::{Element, XmlError};
+use crate::{
+    delay, AdaptiveSettings, Auxiliary, BinaryOp, EvalError, Expr, Flow, FlowFunction, Function,
+    Integrator, Interpolation, LookupTable, Model, OutOfRange, ParseError, Stock, SystemState,
+    UnaryOp,
+};
+
+/// An XMILE document could not be read into a model.
+#[derive(Debug, Clone, PartialEq)]
+pub enum XmileError {
+    /// The document is not well-formed XML
+    Xml(XmlError),
+    /// A required element is missing or has an invalid value
+    Invalid(String),
+    /// The document uses a construct oxidyn cannot represent, such as
+    /// arrays, modules or conveyors
+    Unsupported(String),
+    /// An equation could not be parsed
+    Equation { element: String, error: ParseError },
+}
+
+impl fmt::Display for XmileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XmileError::Xml(error) => write!(f, "invalid XML: {}", error),
+            XmileError::Invalid(message) => write!(f, "invalid XMILE: {}", message),
+            XmileError::Unsupported(message) => write!(f, "unsupported XMILE: {}", message),
+            XmileError::Equation { element, error } => {
+                write!(f, "invalid equation for '{}': {}", element, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for XmileError {}
+
+impl From<XmlError> for XmileError {
+    fn from(error: XmlError) -> Self {
+        XmileError::Xml(error)
+    }
+}
+
+impl Model {
+    /// Reads a model from an XMILE document, as saved by STELLA, iThink and
+    /// other XMILE tools.
+    ///
+    /// Variable names become IDs by replacing spaces with underscores and
+    /// lowercasing them, as XMILE names are case insensitive. Stock initial
+    /// values are calculated at the start time. The sim specs set the start
+    /// time, stop time, time step, integration method and time units, and
+    /// the save interval when STELLA's `isee:save_interval` is given.
+    pub fn from_xmile(source: &str) -> Result<Model, XmileError> {
+        let root = Element::parse(source)?;
+        if root.name != "xmile" {
+            return Err(XmileError::Invalid(format!(
+                "expected <xmile> root element, found <{}>",
+                root.name
+            )));
+        }
+        if root.child("macro").is_some() {
+            return Err(XmileError::Unsupported("macros".to_string()));
+        }
+        let mut models = root.children("model");
+        let model_element = models
+            .next()
+            .ok_or_else(|| XmileError::Invalid("no <model> element".to_string()))?;
+        if models.next().is_some() {
+            return Err(XmileError::Unsupported("multiple models".to_string()));
+        }
+
+        let name = root
+            .child("header")
+            .and_then(|header| header.child_text("name"))
+            .or_else(|| model_element.attribute("name"))
+            .unwrap_or("model");
+        let mut model = Model::new(name);
+        let specs = SimSpecs::read(root.child("sim_specs"))?;
+
+        let empty = Element::default();
+        let variables = model_element.child("variables").unwrap_or(&empty);
+        for element in &variables.children {
+            match element.name.as_str() {
+                "stock" | "flow" | "aux" | "gf" => check_supported(element)?,
+                "module" => return Err(XmileError::Unsupported("modules".to_string())),
+                // Groups only organize the diagram
+                "group" => {}
+                // Vendor extensions
+                name if name.contains(':') => {}
+                name => return Err(XmileError::Unsupported(format!("<{}> elements", name))),
+            }
+        }
+
+        // Graphical functions that equations can call
+        for element in variables.children("gf") {
+            let id = canonical(variable_name(element)?);
+            let table = graphical_function(element, &id)?;
+            model.add_lookup(&id, table);
+        }
+        let reader = EquationReader {
+            specs: &specs,
+            lookups: model.state.lookups.keys().cloned().collect(),
+        };
+
+        // Stocks, and the flows they connect
+        let mut from_stocks = HashMap::new();
+        let mut to_stocks = HashMap::new();
+        let mut initial_values = Vec::new();
+        for element in variables.children("stock") {
+            let name = variable_name(element)?;
+            let id = canonical(name);
+            let mut stock = Stock::new(&id, &display_name(name), 0.0, units(element));
+            if is_set(element, "non_negative") {
+                stock = stock.with_min(0.0);
+            }
+            initial_values.push((id.clone(), reader.equation(element, &id)?));
+            model.add_stock(stock);
+
+            for (connections, tag) in [(&mut to_stocks, "inflow"), (&mut from_stocks, "outflow")] {
+                for flow in element.children(tag) {
+                    let flow = canonical(flow.text.trim());
+                    if let Some(other) = connections.insert(flow.clone(), id.clone()) {
+                        return Err(XmileError::Unsupported(format!(
+                            "flow '{}' is an {} of both '{}' and '{}'",
+                            flow, tag, other, id
+                        )));
+                    }
+                }
+            }
+        }
+
+        for element in variables.children("flow") {
+            let name = variable_name(element)?;
+            let id = canonical(name);
+            let mut function = reader.function(element, &id)?;
+            if is_set(element, "non_negative") {
+                function = match function {
+                    FlowFunction::Constant(rate) => FlowFunction::Constant(rate.max(0.0)),
+                    FlowFunction::Expression(expr) => FlowFunction::Expression(Expr::Call(
+                        Function::Max,
+                        vec![Expr::Number(0.0), expr],
+                    )),
+                    FlowFunction::Lookup { table, input } => {
+                        // Clamp through a named table, as the table may
+                        // extrapolate below zero
+                        let table_id = format!("{}_table", id);
+                        model.add_lookup(&table_id, table);
+                        FlowFunction::Expression(Expr::Call(
+                            Function::Max,
+                            vec![Expr::Number(0.0), Expr::Lookup(table_id, Box::new(input))],
+                        ))
+                    }
+                    linear => linear,
+                };
+            }
+            model.add_flow(Flow {
+                id: id.clone(),
+                name: display_name(name),
+                from_stock: from_stocks.remove(&id),
+                to_stock: to_stocks.remove(&id),
+                rate_function: function,
+                units: units(element).to_string(),
+            });
+        }
+        if let Some(flow) = from_stocks.keys().chain(to_stocks.keys()).next() {
+            return Err(XmileError::Invalid(format!(
+                "stock refers to unknown flow '{}'",
+                flow
+            )));
+        }
+
+        for element in variables.children("aux") {
+            let name = variable_name(element)?;
+            let id = canonical(name);
+            model.add_auxiliary(Auxiliary {
+                id: id.clone(),
+                name: display_name(name),
+                function: reader.function(element, &id)?,
+                units: units(element).to_string(),
+            });
+        }
+
+        specs.apply(&mut model)?;
+        initialize_stocks(&mut model, initial_values).map_err(|(id, error)| {
+            XmileError::Invalid(format!(
+                "cannot calculate the initial value of '{}': {}",
+                id, error
+            ))
+        })?;
+        Ok(model)
+    }
+
+    /// Writes the model as an XMILE document that STELLA and other XMILE
+    /// tools can open.
+    ///
+    /// Stocks start from their current values at the current time. Names
+    /// are written when they map back to the element's ID, and the ID
+    /// otherwise, so equations can refer to elements by ID. A model without
+    /// a stop time runs for 100 time steps. Integration methods XMILE does
+    /// not have are written as the closest one.
+    pub fn to_xmile(&self) -> String {
+        let start = self.state.time;
+        let stop = self
+            .stop_time
+            .unwrap_or(start + DEFAULT_STEPS * self.time_step);
+        let method = match self.integrator {
+            Integrator::Euler => "Euler",
+            Integrator::Heun | Integrator::Midpoint => "RK2",
+            Integrator::RungeKutta4 => "RK4",
+            Integrator::DormandPrince(_) => "RK45",
+            Integrator::BackwardEuler | Integrator::Bdf { .. } => "Gear",
+        };
+
+        let header = Element::new("header")
+            .with_child(Element::new("name").with_text(&self.name))
+            .with_child(Element::new("vendor").with_text("oxidyn"))
+            .with_child(
+                Element::new("product")
+                    .with_attribute("version", env!("CARGO_PKG_VERSION"))
+                    .with_text("oxidyn"),
+            );
+        let mut sim_specs = Element::new("sim_specs")
+            .with_attribute("method", method)
+            .with_attribute("time_units", &self.time_units);
+        if let Some(interval) = self.save_interval {
+            sim_specs = sim_specs.with_attribute(SAVE_INTERVAL, &interval.to_string());
+        }
+        let sim_specs = sim_specs
+            .with_child(Element::new("start").with_text(&start.to_string()))
+            .with_child(Element::new("stop").with_text(&stop.to_string()))
+            .with_child(Element::new("dt").with_text(&self.time_step.to_string()));
+
+        let mut variables = Element::new("variables");
+        let stocks: Vec<_> = self
+            .state
+            .stocks
+            .values()
+            .filter(|stock| !delay::is_internal(&stock.id))
+            .collect();
+        let flows: Vec<_> = self.flows.values().collect();
+        for stock in stocks {
+            let mut element = variable("stock", &stock.id, &stock.name)
+                .with_child(Element::new("eqn").with_text(&stock.current_value.to_string()));
+            for flow in &flows {
+                if flow.to_stock.as_deref() == Some(stock.id.as_str()) {
+                    element = element.with_child(Element::new("inflow").with_text(&flow.id));
+                }
+                if flow.from_stock.as_deref() == Some(stock.id.as_str()) {
+                    element = element.with_child(Element::new("outflow").with_text(&flow.id));
+                }
+            }
+            if stock.min_value == Some(0.0) {
+                element = element.with_child(Element::new("non_negative"));
+            }
+            if stock.min_value.is_some() || stock.max_value.is_some() {
+                let mut range = Element::new("range");
+                if let Some(min) = stock.min_value {
+                    range = range.with_attribute("min", &min.to_string());
+                }
+                if let Some(max) = stock.max_value {
+                    range = range.with_attribute("max", &max.to_string());
+                }
+                element = element.with_child(range);
+            }
+            variables.children.push(with_units(element, &stock.units));
+        }
+        for flow in flows {
+            let element = function_variable("flow", &flow.id, &flow.name, &flow.rate_function);
+            variables.children.push(with_units(element, &flow.units));
+        }
+        let auxiliaries: Vec<_> = self.auxiliaries.values().collect();
+        for auxiliary in auxiliaries {
+            let element =
+                function_variable("aux", &auxiliary.id, &auxiliary.name, &auxiliary.function);
+            variables
+                .children
+                .push(with_units(element, &auxiliary.units));
+        }
+        let lookups: Vec<_> = self.state.lookups.iter().collect();
+        for (id, table) in lookups {
+            let mut gf = write_graphical_function(table);
+            gf.attributes.insert(0, ("name".to_string(), id.clone()));
+            variables.children.push(gf);
+        }
+
+        Element::new("xmile")
+            .with_attribute("version", "1.0")
+            .with_attribute("xmlns", "http://docs.oasis-open.org/xmile/ns/XMILE/v1.0")
+            .with_attribute("xmlns:isee", "http://iseesystems.com/XMILE")
+            .with_child(header)
+            .with_child(sim_specs)
+            .with_child(Element::new("model").with_child(variables))
+            .to_document()
+    }
+}
+
+/// Simulation settings from `<sim_specs>`.
+struct SimSpecs {
+    start: f64,
+    stop: Option<f64>,
+    dt: f64,
+    save_interval: Option<f64>,
+    method: Integrator,
+    time_units: Option<String>,
+}
+
+impl SimSpecs {
+    fn read(element: Option<&Element>) -> Result<Self, XmileError> {
+        let mut specs = SimSpecs {
+            start: 0.0,
+            stop: None,
+            dt: 1.0,
+            save_interval: None,
+            method: Integrator::Euler,
+            time_units: None,
+        };
+        let Some(element) = element else {
+            return Ok(specs);
+        };
+
+        if let Some(start) = element.child_text("start") {
+            specs.start = number(start, "start")?;
+        }
+        if let Some(stop) = element.child_text("stop") {
+            specs.stop = Some(number(stop, "stop")?);
+        }
+        if let Some(dt) = element.child("dt") {
+            specs.dt = number(dt.text.trim(), "dt")?;
+            if dt.attribute("reciprocal") == Some("true") {
+                specs.dt = 1.0 / specs.dt;
+            }
+        }
+        if let Some(interval) = element.attribute(SAVE_INTERVAL) {
+            specs.save_interval = Some(interval.parse().map_err(|_| {
+                XmileError::Invalid(format!(
+                    "{} must be a number, found '{}'",
+                    SAVE_INTERVAL, interval
+                ))
+            })?);
+        }
+        if let Some(method) = element.attribute("method") {
+            specs.method = match method.to_ascii_lowercase().as_str() {
+                "euler" => Integrator::Euler,
+                "rk2" => Integrator::Heun,
+                "rk4" => Integrator::RungeKutta4,
+                "rk45" => Integrator::DormandPrince(AdaptiveSettings::default()),
+                "gear" => Integrator::Bdf { max_order: 5 },
+                _ => {
+                    return Err(XmileError::Unsupported(format!(
+                        "integration method '{}'",
+                        method
+                    )))
+                }
+            };
+        }
+        specs.time_units = element.attribute("time_units").map(str::to_string);
+        Ok(specs)
+    }
+
+    fn apply(&self, model: &mut Model) -> Result<(), XmileError> {
+        if !(self.dt.is_finite() && self.dt > 0.0) {
+            return Err(XmileError::Invalid(format!(
+                "dt must be positive, found {}",
+                self.dt
+            )));
+        }
+        model.set_start_time(self.start);
+        model.stop_time = self.stop;
+        model.save_interval = self.save_interval;
+        model.set_time_step(self.dt).set_integrator(self.method);
+        if let Some(time_units) = &self.time_units {
+            model.set_time_units(time_units);
+        }
+        Ok(())
+    }
+}
+
+fn number(text: &str, element: &str) -> Result<f64, XmileError> {
+    text.parse().map_err(|_| {
+        XmileError::Invalid(format!("<{}> must be a number, found '{}'", element, text))
+    })
+}
+
+/// Rejects stocks and variables that use constructs oxidyn cannot simulate.
+fn check_supported(element: &Element) -> Result<(), XmileError> {
+    let name = element.attribute("name").unwrap_or_default();
+    for (tag, construct) in [
+        ("dimensions", "arrays"),
+        ("element", "arrays"),
+        ("conveyor", "conveyors"),
+        ("queue", "queues"),
+    ] {
+        if element.child(tag).is_some() {
+            return Err(XmileError::Unsupported(format!(
+                "{} ('{}')",
+                construct, name
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn variable_name(element: &Element) -> Result<&str, XmileError> {
+    element
+        .attribute("name")
+        .ok_or_else(|| XmileError::Invalid(format!("<{}> without a name", element.name)))
+}
+
+/// The ID for an XMILE name: trimmed, lowercased, and with runs of spaces,
+/// underscores and escaped newlines replaced by one underscore.
+pub(crate) fn canonical(name: &str) -> String {
+    let name = name
+        .trim()
+        .trim_matches('"')
+        .replace("\\n", " ")
+        .replace('_', " ");
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join("_")
+        .to_lowercase()
+}
+
+/// The name shown to people, without escaped newlines.
+fn display_name(name: &str) -> String {
+    name.replace("\\n", " ")
+}
+
+fn units(element: &Element) -> &str {
+    element.child_text("units").unwrap_or_default()
+}
+
+/// Whether a flag such as `<non_negative/>` is present and not `false`.
+fn is_set(element: &Element, flag: &str) -> bool {
+    element
+        .child(flag)
+        .is_some_and(|child| child.text.trim() != "false")
+}
+
+/// Reads a `<gf>` element into a lookup table.
+fn graphical_function(element: &Element, id: &str) -> Result<LookupTable, XmileError> {
+    let separator = element
+        .child("ypts")
+        .and_then(|ypts| ypts.attribute("sep"))
+        .unwrap_or(",");
+    let points = |tag: &str| -> Result<Option<Vec<f64>>, XmileError> {
+        let Some(text) = element.child_text(tag) else {
+            return Ok(None);
+        };
+        text.split(separator)
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)
+            .map_err(|_| XmileError::Invalid(format!("invalid <{}> for '{}'", tag, id)))
+    };
+
+    let y_values = points("ypts")?
+        .ok_or_else(|| XmileError::Invalid(format!("graphical function '{}' has no <ypts>", id)))?;
+    let mut table = match (points("xpts")?, element.child("xscale")) {
+        (Some(x_values), _) => {
+            if x_values.len() != y_values.len() {
+                return Err(XmileError::Invalid(format!(
+                    "graphical function '{}' has {} x and {} y points",
+                    id,
+                    x_values.len(),
+                    y_values.len()
+                )));
+            }
+            LookupTable::new(x_values.into_iter().zip(y_values).collect())
+        }
+        (None, Some(xscale)) => {
+            let bound = |name: &str| {
+                xscale
+                    .attribute(name)
+                    .and_then(|value| value.trim().parse::<f64>().ok())
+                    .ok_or_else(|| {
+                        XmileError::Invalid(format!("invalid <xscale> {} for '{}'", name, id))
+                    })
+            };
+            LookupTable::from_range(bound("min")?, bound("max")?, &y_values)
+        }
+        (None, None) => {
+            return Err(XmileError::Invalid(format!(
+                "graphical function '{}' has neither <xpts> nor <xscale>",
+                id
+            )))
+        }
+    };
+
+    match element.attribute("type").unwrap_or("continuous") {
+        "continuous" => {}
+        "extrapolate" => table = table.with_out_of_range(OutOfRange::Extrapolate),
+        "discrete" => table = table.with_interpolation(Interpolation::Step),
+        other => {
+            return Err(XmileError::Unsupported(format!(
+                "graphical function type '{}' ('{}')",
+                other, id
+            )))
+        }
+    }
+    Ok(table)
+}
+
+/// Translates XMILE equations into oxidyn expressions.
+struct EquationReader<'a> {
+    specs: &'a SimSpecs,
+    /// IDs of the graphical functions equations can call
+    lookups: Vec<String>,
+}
+
+impl EquationReader<'_> {
+    /// The function of a flow or auxiliary, from its equation and optional
+    /// graphical function.
+    fn function(&self, element: &Element, id: &str) -> Result<FlowFunction, XmileError> {
+        let expr = self.equation(element, id)?;
+        if let Some(gf) = element.child("gf") {
+            return Ok(FlowFunction::Lookup {
+                table: graphical_function(gf, id)?,
+                input: expr,
+            });
+        }
+        Ok(match expr {
+            Expr::Number(value) => FlowFunction::Constant(value),
+            expr => FlowFunction::Expression(expr),
+        })
+    }
+
+    fn equation(&self, element: &Element, id: &str) -> Result<Expr, XmileError> {
+        let text = element
+            .child_text("eqn")
+            .filter(|text| !text.is_empty())
+            .ok_or_else(|| XmileError::Invalid(format!("'{}' has no equation", id)))?;
+        let mut expr =
+            Expr::parse(&self.translate(text, id)?).map_err(|error| XmileError::Equation {
+                element: id.to_string(),
+                error,
+            })?;
+        rename(&mut expr);
+        if let Some(lookup) = expr
+            .lookups()
+            .into_iter()
+            .find(|name| !self.lookups.iter().any(|lookup| lookup == name))
+        {
+            return Err(XmileError::Invalid(format!(
+                "'{}' calls unknown graphical function '{}'",
+                id, lookup
+            )));
+        }
+        Ok(expr)
+    }
+
+    /// Rewrites XMILE builtins that oxidyn names differently, and removes
+    /// `{comments}`. Calls to functions oxidyn does not have are reported.
+    fn translate(&self, equation: &str, id: &str) -> Result<String, XmileError> {
+        let mut translated = String::with_capacity(equation.len());
+        let mut chars = equation.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            match c {
+                '{' => {
+                    for (_, c) in chars.by_ref() {
+                        if c == '}' {
+                            break;
+                        }
+                    }
+                    translated.push(' ');
+                }
+                '"' => {
+                    translated.push(c);
+                    for (_, c) in chars.by_ref() {
+                        translated.push(c);
+                        if c == '"' {
+                            break;
+                        }
+                    }
+                }
+                c if c.is_alphabetic() || c == '_' => {
+                    let mut end = start + c.len_utf8();
+                    while let Some(&(i, c)) = chars.peek() {
+                        if !(c.is_alphanumeric() || matches!(c, '_' | '.' | '$')) {
+                            break;
+                        }
+                        end = i + c.len_utf8();
+                        chars.next();
+                    }
+                    let word = &equation[start..end];
+                    let is_call = equation[end..].trim_start().starts_with('(');
+                    match word.to_ascii_uppercase().as_str() {
+                        "DELAY" if is_call => translated.push_str("DELAYFIXED"),
+                        "PI" if !is_call => translated.push_str(&std::f64::consts::PI.to_string()),
+                        "STARTTIME" if !is_call => {
+                            translated.push_str(&format!("({})", self.specs.start))
+                        }
+                        "STOPTIME" if !is_call => match self.specs.stop {
+                            Some(stop) => translated.push_str(&format!("({})", stop)),
+                            None => translated.push_str(word),
+                        },
+                        "IF" | "THEN" | "ELSE" | "AND" | "OR" | "NOT" | "LOOKUP" => {
+                            translated.push_str(word)
+                        }
+                        _ if is_call
+                            && Function::from_name(word).is_none()
+                            && !self.lookups.contains(&canonical(word)) =>
+                        {
+                            return Err(XmileError::Unsupported(format!(
+                                "function {} in '{}'",
+                                word.to_uppercase(),
+                                id
+                            )))
+                        }
+                        _ => translated.push_str(word),
+                    }
+                }
+                c => translated.push(c),
+            }
+        }
+        Ok(translated)
+    }
+}
+
+/// Replaces variable and lookup names in an expression by their IDs.
+fn rename(expr: &mut Expr) {
+    match expr {
+        Expr::Variable(name) => *name = canonical(name),
+        Expr::Number(_) | Expr::Time | Expr::Dt => {}
+        Expr::Unary(_, operand) => rename(operand),
+        Expr::Binary(_, lhs, rhs) => {
+            rename(lhs);
+            rename(rhs);
+        }
+        Expr::If {
+            condition,
+            then,
+            otherwise,
+        } => {
+            rename(condition);
+            rename(then);
+            rename(otherwise);
+        }
+        Expr::Call(_, args) => args.iter_mut().for_each(rename),
+        Expr::Lookup(name, input) => {
+            *name = canonical(name);
+            rename(input);
+        }
+    }
+}
+
+/// Calculates the initial stock values, which may depend on auxiliaries
+/// and other stocks. Fails with the first stock whose value cannot be
+/// calculated.
+pub(crate) fn initialize_stocks(
+    model: &mut Model,
+    mut pending: Vec<(String, Expr)>,
+) -> Result<(), (String, EvalError)> {
+    let mut state = SystemState::new();
+    state.time = model.state.time;
+    state.dt = model.time_step;
+    state.lookups = model.state.lookups.clone();
+
+    while !pending.is_empty() {
+        let progress = (pending.len(), state.variables.len());
+        for auxiliary in model.auxiliaries.values() {
+            if let Ok(value) = auxiliary.try_calculate_value(&state) {
+                state.variables.insert(auxiliary.id.clone(), value);
+            }
+        }
+        for flow in model.flows.values() {
+            if let Ok(value) = flow.try_calculate_rate(&state) {
+                state.variables.insert(flow.id.clone(), value);
+            }
+        }
+
+        let mut error = None;
+        let mut unresolved = Vec::new();
+        for (id, expr) in pending {
+            match expr.eval(&state) {
+                Ok(value) => {
+                    state
+                        .stocks
+                        .insert(id.clone(), Stock::new(&id, &id, value, ""));
+                }
+                Err(eval_error) => {
+                    error.get_or_insert((id.clone(), eval_error));
+                    unresolved.push((id, expr));
+                }
+            }
+        }
+        pending = unresolved;
+        if let Some((id, error)) = error {
+            if (pending.len(), state.variables.len()) == progress {
+                return Err((id, error));
+            }
+        }
+    }
+
+    for (id, initialized) in state.stocks {
+        if let Some(stock) = model.state.stocks.get_mut(&id) {
+            stock.initial_value = initialized.current_value;
+            stock.current_value = initialized.current_value;
+        }
+    }
+    Ok(())
+}
+
+/// The run length, in time steps, of exported models without a stop time.
+const DEFAULT_STEPS: f64 = 100.0;
+
+/// The `<sim_specs>` attribute STELLA saves the reporting interval in
+const SAVE_INTERVAL: &str = "isee:save_interval";
+
+/// A variable element named after the element, or after its ID when the
+/// name would be read back as a different ID.
+fn variable(tag: &str, id: &str, name: &str) -> Element {
+    let name = if canonical(name) == id { name } else { id };
+    Element::new(tag).with_attribute("name", name)
+}
+
+fn with_units(element: Element, units: &str) -> Element {
+    if units.is_empty() {
+        element
+    } else {
+        element.with_child(Element::new("units").with_text(units))
+    }
+}
+
+/// A flow or auxiliary with the equation, and graphical function, of its
+/// function.
+fn function_variable(tag: &str, id: &str, name: &str, function: &FlowFunction) -> Element {
+    let element = variable(tag, id, name);
+    match function {
+        FlowFunction::Constant(value) => {
+            element.with_child(Element::new("eqn").with_text(&value.to_string()))
+        }
+        FlowFunction::Linear {
+            slope,
+            intercept,
+            input_stock,
+        } => {
+            let expr = Expr::Binary(
+                BinaryOp::Add,
+                Box::new(Expr::Binary(
+                    BinaryOp::Multiply,
+                    Box::new(Expr::Number(*slope)),
+                    Box::new(Expr::Variable(input_stock.clone())),
+                )),
+                Box::new(Expr::Number(*intercept)),
+            );
+            element.with_child(Element::new("eqn").with_text(&write_equation(&expr)))
+        }
+        FlowFunction::Expression(expr) => {
+            element.with_child(Element::new("eqn").with_text(&write_equation(expr)))
+        }
+        FlowFunction::Lookup { table, input } => element
+            .with_child(Element::new("eqn").with_text(&write_equation(input)))
+            .with_child(write_graphical_function(table)),
+    }
+}
+
+/// Writes an equation with only XMILE builtins, under their XMILE names.
+fn write_equation(expr: &Expr) -> String {
+    let mut expr = expr.clone();
+    standardize(&mut expr);
+    let equation = expr.display_with(xmile_name).to_string();
+    equation
+}
+
+/// The XMILE name of a builtin.
+fn xmile_name(function: &Function) -> &'static str {
+    match function {
+        Function::DelayFixed => "DELAY",
+        function => function.name(),
+    }
+}
+
+/// Replaces calls to functions that XMILE does not define with equivalent
+/// expressions of its builtins. `MIN` and `MAX` take two arguments in
+/// XMILE, so calls with more are nested.
+fn standardize(expr: &mut Expr) {
+    match expr {
+        Expr::Variable(_) | Expr::Number(_) | Expr::Time | Expr::Dt => {}
+        Expr::Unary(_, operand) => standardize(operand),
+        Expr::Binary(_, lhs, rhs) => {
+            standardize(lhs);
+            standardize(rhs);
+        }
+        Expr::If {
+            condition,
+            then,
+            otherwise,
+        } => {
+            standardize(condition);
+            standardize(then);
+            standardize(otherwise);
+        }
+        Expr::Lookup(_, input) => standardize(input),
+        Expr::Call(function, args) => {
+            args.iter_mut().for_each(standardize);
+            let function = *function;
+            let mut args = std::mem::take(args);
+            *expr = match function {
+                // Toward zero, where INT rounds down
+                Function::Trunc => {
+                    let x = args.swap_remove(0);
+                    away_from_zero(&x, int(negate(x.clone())), int(x.clone()))
+                }
+                // Half away from zero
+                Function::Round => {
+                    let x = args.swap_remove(0);
+                    away_from_zero(
+                        &x,
+                        int(binary(BinaryOp::Subtract, Expr::Number(0.5), x.clone())),
+                        int(binary(BinaryOp::Add, x.clone(), Expr::Number(0.5))),
+                    )
+                }
+                // a - |b| * INT(a / |b|), never negative like rem_euclid
+                Function::Mod => {
+                    let divisor = args.pop().unwrap_or(Expr::Number(0.0));
+                    let dividend = args.pop().unwrap_or(Expr::Number(0.0));
+                    let divisor = Expr::Call(Function::Abs, vec![divisor]);
+                    binary(
+                        BinaryOp::Subtract,
+                        dividend.clone(),
+                        binary(
+                            BinaryOp::Multiply,
+                            divisor.clone(),
+                            int(binary(BinaryOp::Divide, dividend, divisor)),
+                        ),
+                    )
+                }
+                // amplitude * SIN(2 * PI * TIME / period)
+                Function::SinWave | Function::CosWave => {
+                    let period = args.pop().unwrap_or(Expr::Number(0.0));
+                    let amplitude = args.pop().unwrap_or(Expr::Number(0.0));
+                    let angle = binary(
+                        BinaryOp::Divide,
+                        binary(
+                            BinaryOp::Multiply,
+                            binary(
+                                BinaryOp::Multiply,
+                                Expr::Number(2.0),
+                                Expr::Variable("PI".to_string()),
+                            ),
+                            Expr::Time,
+                        ),
+                        period,
+                    );
+                    let wave = match function {
+                        Function::SinWave => Function::Sin,
+                        _ => Function::Cos,
+                    };
+                    binary(BinaryOp::Multiply, amplitude, Expr::Call(wave, vec![angle]))
+                }
+                Function::Min | Function::Max if args.len() > 2 => {
+                    let mut args = args.into_iter();
+                    let first = args.next().unwrap_or(Expr::Number(0.0));
+                    args.fold(first, |nested, arg| Expr::Call(function, vec![nested, arg]))
+                }
+                function => Expr::Call(function, args),
+            };
+        }
+    }
+}
+
+/// `IF x < 0 THEN -negative ELSE positive`.
+fn away_from_zero(x: &Expr, negative: Expr, positive: Expr) -> Expr {
+    Expr::If {
+        condition: Box::new(binary(BinaryOp::Less, x.clone(), Expr::Number(0.0))),
+        then: Box::new(negate(negative)),
+        otherwise: Box::new(positive),
+    }
+}
+
+fn int(x: Expr) -> Expr {
+    Expr::Call(Function::Int, vec![x])
+}
+
+fn negate(x: Expr) -> Expr {
+    Expr::Unary(UnaryOp::Negate, Box::new(x))
+}
+
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+}
+
+/// A `<gf>` element for a lookup table. Cubic interpolation is written as
+/// continuous, and tables that report an error out of range as clamped.
+fn write_graphical_function(table: &LookupTable) -> Element {
+    let join = |values: Vec<f64>| {
+        values
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let kind = match (table.interpolation, table.out_of_range) {
+        (Interpolation::Step, _) => "discrete",
+        (_, OutOfRange::Extrapolate) => "extrapolate",
+        _ => "continuous",
+    };
+    let (x_values, y_values) = table.points.iter().copied().unzip();
+    Element::new("gf")
+        .with_attribute("type", kind)
+        .with_child(Element::new("xpts").with_text(&join(x_values)))
+        .with_child(Element::new("ypts").with_text(&join(y_values)))
+}
@@ -0,0 +1,326 @@
+use std::fmt;
+
+/// An XML element with its attributes, child elements and text content.
+///
+/// A small reader for model files: it handles comments, CDATA sections,
+/// processing instructions and the predefined and numeric entities, but
+/// ignores DTDs and namespaces.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Element {
+    pub(crate) name: String,
+    pub(crate) attributes: Vec<(String, String)>,
+    pub(crate) children: Vec<Element>,
+    /// Text directly inside the element, concatenated
+    pub(crate) text: String,
+}
+
+impl Element {
+    /// Parses a document and returns its root element.
+    pub(crate) fn parse(source: &str) -> Result<Element, XmlError> {
+        let mut reader = Reader {
+            source,
+            position: 0,
+        };
+        reader.skip_misc()?;
+        let root = reader.element()?;
+        reader.skip_misc()?;
+        if reader.position < source.len() {
+            return Err(reader.error("content after the root element"));
+        }
+        Ok(root)
+    }
+
+    pub(crate) fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The first child element with the given name.
+    pub(crate) fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// All child elements with the given name.
+    pub(crate) fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// The trimmed text of the first child element with the given name.
+    pub(crate) fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.trim())
+    }
+}
+
+impl Element {
+    pub(crate) fn new(name: &str) -> Element {
+        Element {
+            name: name.to_string(),
+            ..Element::default()
+        }
+    }
+
+    pub(crate) fn with_attribute(mut self, key: &str, value: &str) -> Element {
+        self.attributes.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub(crate) fn with_child(mut self, child: Element) -> Element {
+        self.children.push(child);
+        self
+    }
+
+    pub(crate) fn with_text(mut self, text: &str) -> Element {
+        self.text = text.to_string();
+        self
+    }
+
+    /// Writes a document with this element as its root, indenting child
+    /// elements. Elements with children are written without their text.
+    pub(crate) fn to_document(&self) -> String {
+        let mut document = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        self.write(&mut document, 0);
+        document
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        out.push_str(&"    ".repeat(depth));
+        out.push('<');
+        out.push_str(&self.name);
+        for (key, value) in &self.attributes {
+            out.push_str(&format!(" {}=\"{}\"", key, escape(value)));
+        }
+        if !self.children.is_empty() {
+            out.push_str(">\n");
+            for child in &self.children {
+                child.write(out, depth + 1);
+            }
+            out.push_str(&"    ".repeat(depth));
+        } else if !self.text.is_empty() {
+            out.push('>');
+            out.push_str(&escape(&self.text));
+        } else {
+            out.push_str("/>\n");
+            return;
+        }
+        out.push_str(&format!("</{}>\n", self.name));
+    }
+}
+
+/// A document is not well-formed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XmlError {
+    pub message: String,
+    /// Byte offset into the document where the error was found
+    pub position: usize,
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for XmlError {}
+
+struct Reader<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn error(&self, message: &str) -> XmlError {
+        XmlError {
+            message: message.to_string(),
+            position: self.position,
+        }
+    }
+
+    fn rest(&self) -> &str {
+        &self.source[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skips past `end`, failing if it does not occur.
+    fn skip_past(&mut self, end: &str) -> Result<&str, XmlError> {
+        match self.rest().find(end) {
+            Some(offset) => {
+                let skipped = &self.source[self.position..self.position + offset];
+                self.position += offset + end.len();
+                Ok(skipped)
+            }
+            None => Err(self.error(&format!("missing '{}'", end))),
+        }
+    }
+
+    /// Skips whitespace, comments, processing instructions and doctype
+    /// declarations between elements.
+    fn skip_misc(&mut self) -> Result<(), XmlError> {
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, XmlError> {
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(rest.len());
+        if length == 0 {
+            return Err(self.error("expected a name"));
+        }
+        let name = rest[..length].to_string();
+        self.position += length;
+        Ok(name)
+    }
+
+    fn element(&mut self) -> Result<Element, XmlError> {
+        if !self.rest().starts_with('<') {
+            return Err(self.error("expected an element"));
+        }
+        self.position += 1;
+        let mut element = Element {
+            name: self.name()?,
+            ..Element::default()
+        };
+
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("/>") {
+                self.position += 2;
+                return Ok(element);
+            } else if rest.starts_with('>') {
+                self.position += 1;
+                break;
+            } else if rest.is_empty() {
+                return Err(self.error("unexpected end of document"));
+            }
+
+            let key = self.name()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return Err(self.error("expected '='"));
+            }
+            self.position += 1;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(quote @ ('"' | '\'')) => quote,
+                _ => return Err(self.error("expected a quoted attribute value")),
+            };
+            self.position += 1;
+            let start = self.position;
+            let raw = self.skip_past(&quote.to_string())?;
+            let value = unescape(raw).map_err(|offset| XmlError {
+                message: "invalid entity".to_string(),
+                position: start + offset,
+            })?;
+            element.attributes.push((key, value));
+        }
+
+        // Content
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("</") {
+                self.position += 2;
+                let position = self.position;
+                let name = self.name()?;
+                if name != element.name {
+                    return Err(XmlError {
+                        message: format!("expected '</{}>'", element.name),
+                        position,
+                    });
+                }
+                self.skip_whitespace();
+                if !self.rest().starts_with('>') {
+                    return Err(self.error("expected '>'"));
+                }
+                self.position += 1;
+                return Ok(element);
+            } else if rest.starts_with("<![CDATA[") {
+                self.position += "<![CDATA[".len();
+                let text = self.skip_past("]]>")?;
+                element.text.push_str(text);
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with('<') {
+                element.children.push(self.element()?);
+            } else if rest.is_empty() {
+                return Err(self.error(&format!("missing '</{}>'", element.name)));
+            } else {
+                let length = rest.find('<').unwrap_or(rest.len());
+                let start = self.position;
+                let text = unescape(&rest[..length]).map_err(|offset| XmlError {
+                    message: "invalid entity".to_string(),
+                    position: start + offset,
+                })?;
+                element.text.push_str(&text);
+                self.position += length;
+            }
+        }
+    }
+}
+
+/// Replaces entity references, returning the offset of an invalid one.
+fn unescape(text: &str) -> Result<String, usize> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let offset = text.len() - rest.len() + start;
+        let end = rest[start..].find(';').ok_or(offset)? + start;
+        let entity = &rest[start + 1..end];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => entity.strip_prefix('#').ok_or(offset)?.parse::<u32>(),
+                };
+                code.ok().and_then(char::from_u32).ok_or(offset)?
+            }
+        };
+        unescaped.push(c);
+        rest = &rest[end + 1..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+/// Replaces the characters that cannot appear literally in text or
+/// attribute values.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "arrow")]
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float64Type, UInt64Type};
+use arrow_array::Array;
+use oxidyn::SimulationResult;
+
+fn result(tank: Vec<f64>) -> SimulationResult {
+    let mut res = SimulationResult::new();
+    res.time_series = (0..tank.len()).map(|i| i as f64 * 0.5).collect();
+    res.stock_values
+        .insert("drain".to_string(), tank.iter().map(|v| 10. - v).collect());
+    res.stock_values.insert("tank".to_string(), tank);
+    res
+}
+
+#[test]
+fn test_record_batch() {
+    let batch = result(vec![10., 9.5, 9.025]).to_record_batch().unwrap();
+
+    let names: Vec<_> = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().clone())
+        .collect();
+    assert_eq!(names, ["time", "drain", "tank"]);
+    assert_eq!(batch.num_rows(), 3);
+    assert_eq!(
+        batch.column(0).as_primitive::<Float64Type>().values(),
+        &[0., 0.5, 1.]
+    );
+    assert_eq!(
+        batch.column(2).as_primitive::<Float64Type>().values(),
+        &[10., 9.5, 9.025]
+    );
+    assert!(!batch.schema().field(2).is_nullable());
+}
+
+#[test]
+fn test_runs_record_batch() {
+    let mut second = result(vec![8., 7.6]);
+    second
+        .auxiliary_values
+        .insert("level".to_string(), vec![80., 76.]);
+    let runs = [result(vec![10., 9.5, 9.025]), second];
+    let batch = SimulationResult::runs_to_record_batch(&runs, true).unwrap();
+
+    assert_eq!(batch.num_rows(), 5);
+    assert_eq!(
+        batch.column(0).as_primitive::<UInt64Type>().values(),
+        &[0, 0, 0, 1, 1]
+    );
+    assert_eq!(
+        batch.column(3).as_primitive::<Float64Type>().values(),
+        &[10., 9.5, 9.025, 8., 7.6]
+    );
+
+    let schema = batch.schema();
+    let level = schema.field_with_name("level").unwrap();
+    assert!(level.is_nullable());
+    let level = batch.column(4).as_primitive::<Float64Type>();
+    assert_eq!(level.null_count(), 3);
+    assert_eq!(level.value(3), 80.);
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn test_write_parquet() {
+    let mut out = Vec::new();
+    result(vec![10., 9.5, 9.025])
+        .write_parquet(&mut out)
+        .unwrap();
+    assert!(out.starts_with(b"PAR1"));
+    assert!(out.ends_with(b"PAR1"));
+
+    let mut out = Vec::new();
+    SimulationResult::write_runs_parquet(&[result(vec![10.]), result(vec![8.])], &mut out).unwrap();
+    assert!(out.starts_with(b"PAR1"));
+}
@@ -0,0 +1,182 @@
+use oxidyn::{Auxiliary, Flow, FlowFunction, Model, ModelError, Stock, SystemState};
+
+#[test]
+fn test_auxiliary_creation() {
+    let aux = Auxiliary::constant("capacity", "Capacity", 1000., "people");
+
+    assert_eq!(aux.id, "capacity");
+    assert_eq!(aux.name, "Capacity");
+    assert_eq!(aux.units, "people");
+    match aux.function {
+        FlowFunction::Constant(value) => assert_eq!(value, 1000.),
+        _ => panic!("Expected constant function"),
+    }
+
+    assert!(Auxiliary::expression("bad", "Bad", "1 +", "units").is_err());
+}
+
+#[test]
+fn test_auxiliary_calculate_value() {
+    let mut state = SystemState::new();
+    state.stocks.insert(
+        "pop".to_string(),
+        Stock::new("pop", "Population", 100.0, "people"),
+    );
+    state.variables.insert("capacity".to_string(), 400.0);
+
+    let aux = Auxiliary::expression("crowding", "Crowding", "pop / capacity", "dmnl").unwrap();
+    assert_eq!(aux.calculate_value(&state), 0.25);
+}
+
+#[test]
+fn test_auxiliaries_in_model() {
+    let mut model = Model::new("logistic");
+
+    // auxiliaries are added in reverse dependency order on purpose
+    model
+        .add_stock(Stock::new("population", "Population", 10., "people"))
+        .add_auxiliary(
+            Auxiliary::expression(
+                "births_per_person",
+                "Births per Person",
+                "fertility * (1 - crowding)",
+                "1/time",
+            )
+            .unwrap(),
+        )
+        .add_auxiliary(
+            Auxiliary::expression("crowding", "Crowding", "population / capacity", "dmnl").unwrap(),
+        )
+        .add_auxiliary(Auxiliary::constant("capacity", "Capacity", 100., "people"))
+        .add_auxiliary(Auxiliary::constant("fertility", "Fertility", 0.5, "1/time"))
+        .add_flow(
+            Flow::expression(
+                "births",
+                "Births",
+                "births_per_person * population",
+                "people/time",
+            )
+            .unwrap()
+            .to_stock("population"),
+        )
+        .set_time_step(1.);
+
+    let res = model.simulate(2.0);
+
+    // population: 10 -> 10 + 0.5 * 0.9 * 10 = 14.5 -> 14.5 + 0.5 * 0.855 * 14.5
+    let population = &res.stock_values["population"];
+    assert_eq!(population[1], 14.5);
+
+    let crowding = &res.auxiliary_values["crowding"];
+    assert_eq!(crowding.len(), res.time_series.len());
+    assert_eq!(crowding[0], 0.1);
+    assert_eq!(crowding[1], 0.145);
+    assert_eq!(res.auxiliary_values["capacity"], vec![100.; 3]);
+
+    // The model state keeps the auxiliary values for the final stock values
+    let final_crowding = population[2] / 100.;
+    assert_eq!(model.state.get_value("crowding"), Some(final_crowding));
+}
+
+#[test]
+fn test_auxiliary_referencing_flow() {
+    let mut model = Model::new("model");
+
+    model
+        .add_stock(Stock::new("tank", "Tank", 0., "liters"))
+        .add_flow(Flow::constant("inflow", "Inflow", 3., "liters/time").to_stock("tank"))
+        .add_auxiliary(
+            Auxiliary::expression("doubled", "Doubled", "inflow * 2", "liters/time").unwrap(),
+        )
+        .set_time_step(1.);
+
+    let res = model.simulate(2.0);
+    assert_eq!(res.auxiliary_values["doubled"], vec![6.; 3]);
+}
+
+#[test]
+fn test_auxiliary_chain_and_cycle() {
+    let mut model = Model::new("model");
+
+    model
+        .add_stock(Stock::new("tank", "Tank", 10., "liters"))
+        .add_flow(
+            Flow::expression("drain", "Drain", "quarter", "liters/time")
+                .unwrap()
+                .from_stock("tank"),
+        )
+        .add_auxiliary(Auxiliary::expression("quarter", "Quarter", "half / 2", "liters").unwrap())
+        .add_auxiliary(Auxiliary::expression("half", "Half", "tank / 2", "liters").unwrap())
+        .set_time_step(1.);
+
+    let res = model.simulate(2.0);
+    assert_eq!(res.stock_values["tank"], vec![10., 7.5, 5.625]);
+    assert_eq!(res.auxiliary_values["quarter"], vec![2.5, 1.875, 1.40625]);
+
+    model
+        .add_auxiliary(Auxiliary::expression("a", "A", "b + 1", "dmnl").unwrap())
+        .add_auxiliary(Auxiliary::expression("b", "B", "a + 1", "dmnl").unwrap());
+    let errors = model.try_simulate(3.0).unwrap_err();
+    assert_eq!(
+        errors,
+        vec![ModelError::CircularDefinition {
+            ids: vec!["a".to_string(), "b".to_string()],
+        }]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "circular definition 'a' -> 'b' -> 'a'"
+    );
+}
+
+#[test]
+fn test_circular_definitions() {
+    let mut model = Model::new("model");
+
+    model
+        .add_stock(Stock::new("tank", "Tank", 10., "liters"))
+        .add_auxiliary(Auxiliary::expression("echo", "Echo", "echo * 2", "").unwrap())
+        .add_auxiliary(Auxiliary::expression("gap", "Gap", "tank - smoothed", "").unwrap())
+        .add_flow(
+            Flow::expression("drain", "Drain", "gap / 4", "liters/time")
+                .unwrap()
+                .from_stock("tank"),
+        )
+        .add_auxiliary(Auxiliary::expression("smoothed", "Smoothed", "drain * 2", "").unwrap());
+
+    let ids = |ids: &[&str]| ModelError::CircularDefinition {
+        ids: ids.iter().map(|id| id.to_string()).collect(),
+    };
+    assert_eq!(
+        model.validate(),
+        vec![ids(&["echo"]), ids(&["gap", "smoothed", "drain"])]
+    );
+
+    // A stateful function breaks the loop with its stock
+    model.auxiliaries["echo"].function = FlowFunction::Constant(1.);
+    model.auxiliaries["smoothed"].function =
+        Auxiliary::expression("smoothed", "", "SMTH1(drain * 2, 3)", "")
+            .unwrap()
+            .function;
+    assert_eq!(model.validate(), vec![]);
+    model.try_simulate(1.).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "circular definition 'a' -> 'b' -> 'a'")]
+fn test_circular_definition_after_a_run() {
+    let mut model = Model::new("model");
+
+    model
+        .add_stock(Stock::new("tank", "Tank", 10., "liters"))
+        .add_auxiliary(Auxiliary::expression("a", "A", "tank + 1", "").unwrap())
+        .add_auxiliary(Auxiliary::expression("b", "B", "a + 1", "").unwrap());
+    model.simulate(1.);
+    assert_eq!(model.state.variables["b"], 12.);
+
+    // The values of the last run must not stand in for the loop
+    model.auxiliaries["a"].function = Auxiliary::expression("a", "A", "b + 1", "")
+        .unwrap()
+        .function;
+    model.simulate(1.);
+}
@@ -0,0 +1,314 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const TANK: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<xmile version="1.0" xmlns="http://docs.oasis-open.org/xmile/ns/XMILE/v1.0">
+    <header><name>tank</name></header>
+    <sim_specs>
+        <start>0</start>
+        <stop>2</stop>
+        <dt>1</dt>
+    </sim_specs>
+    <model>
+        <variables>
+            <stock name="tank">
+                <eqn>8</eqn>
+                <outflow>drain</outflow>
+            </stock>
+            <flow name="drain">
+                <eqn>tank * rate</eqn>
+            </flow>
+            <aux name="rate">
+                <eqn>0.5</eqn>
+            </aux>
+        </variables>
+    </model>
+</xmile>
+"#;
+
+/// Writes `contents` to a file in the test directory.
+fn model_file(name: &str, contents: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn oxidyn(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_oxidyn"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn test_run_csv() {
+    let path = model_file("run_csv.xmile", TANK);
+    let output = oxidyn(&["run", path.to_str().unwrap(), "--format", "csv"]);
+    assert_eq!(
+        stdout(&output),
+        "time,tank,rate,drain\n\
+         0,8,0.5,4\n\
+         1,4,0.5,2\n\
+         2,2,0.5,1\n"
+    );
+}
+
+#[test]
+fn test_run_net_flows() {
+    let path = model_file("run_net_flows.xmile", TANK);
+    let output = oxidyn(&["run", path.to_str().unwrap(), "-f", "csv", "--net-flows"]);
+    assert_eq!(
+        stdout(&output),
+        "time,tank,rate,drain,net(tank)\n\
+         0,8,0.5,4,-4\n\
+         1,4,0.5,2,-2\n\
+         2,2,0.5,1,-1\n"
+    );
+}
+
+#[test]
+fn test_overrides() {
+    let path = model_file("overrides.xmile", TANK);
+    let output = oxidyn(&[
+        "run",
+        path.to_str().unwrap(),
+        "--set",
+        "tank=16",
+        "--set=rate=0.25",
+        "--stop",
+        "3",
+        "--dt=0.5",
+        "--method",
+        "rk4",
+        "--columns",
+        "tank",
+        "-f",
+        "tsv",
+        "--precision",
+        "2",
+    ]);
+    let output = stdout(&output);
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 8);
+    assert_eq!(lines[0], "time\ttank");
+    assert_eq!(lines[1], "0.00\t16.00");
+    assert_eq!(lines[7], format!("3.00\t{:.2}", 16. * (-0.75f64).exp()));
+}
+
+#[test]
+fn test_save_interval() {
+    let path = model_file("save_interval.xmile", TANK);
+    let output = oxidyn(&[
+        "run",
+        path.to_str().unwrap(),
+        "--dt",
+        "0.5",
+        "--save-interval",
+        "1",
+        "--format",
+        "csv",
+    ]);
+    assert_eq!(
+        stdout(&output),
+        "time,tank,rate,drain\n\
+         0,8,0.5,4\n\
+         1,4.5,0.5,2.25\n\
+         2,2.53125,0.5,1.265625\n"
+    );
+
+    let output = oxidyn(&["run", path.to_str().unwrap(), "--save-interval", "0.5"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "oxidyn: save interval 0.5 is not a whole multiple of the time step 1\n"
+    );
+}
+
+#[test]
+fn test_json_output_file() {
+    let path = model_file("json_output.xmile", TANK);
+    let output_path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("results.json");
+    let output = oxidyn(&[
+        "run",
+        path.to_str().unwrap(),
+        "-o",
+        output_path.to_str().unwrap(),
+    ]);
+    assert_eq!(stdout(&output), "");
+    assert_eq!(
+        fs::read_to_string(output_path).unwrap(),
+        "{\n  \"time\": [0, 1, 2],\n  \"tank\": [8, 4, 2],\n  \"rate\": [0.5, 0.5, 0.5],\n  \"drain\": [4, 2, 1]\n}\n"
+    );
+}
+
+#[test]
+fn test_table() {
+    let path = model_file("table.xmile", TANK);
+    let output = stdout(&oxidyn(&["run", path.to_str().unwrap()]));
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(
+        lines[0].split_whitespace().collect::<Vec<_>>(),
+        ["time", "tank", "rate", "drain"]
+    );
+    assert_eq!(
+        lines[3].split_whitespace().collect::<Vec<_>>(),
+        ["1.000", "4.000", "0.500", "2.000"]
+    );
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_run_model_file() {
+    let path = model_file(
+        "model_file.toml",
+        r#"
+version = 1
+name = "tank"
+
+[simulation]
+dt = 1
+stop = 2
+
+[[stocks]]
+id = "tank"
+initial = 8
+
+[[flows]]
+id = "drain"
+from = "tank"
+equation = "tank / 2"
+"#,
+    );
+    let output = oxidyn(&["run", path.to_str().unwrap(), "--format=csv"]);
+    assert_eq!(stdout(&output), "time,tank,drain\n0,8,4\n1,4,2\n2,2,1\n");
+}
+
+#[test]
+fn test_errors() {
+    let path = model_file("errors.xmile", TANK);
+    let path = path.to_str().unwrap();
+
+    let output = oxidyn(&["run", path, "--dt"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("oxidyn: --dt needs a value\n"));
+
+    let output = oxidyn(&["run", path, "--set", "level=3"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "oxidyn: --set: 'level' is not a stock, flow or auxiliary of the model\n"
+    );
+
+    let output = oxidyn(&["run", path, "--columns", "tank,level"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "oxidyn: unknown column 'level'\n"
+    );
+
+    let output = oxidyn(&["run", "missing.xmile"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).starts_with("oxidyn: cannot read missing.xmile")
+    );
+
+    assert_eq!(oxidyn(&["simulate"]).status.code(), Some(2));
+    assert!(stdout(&oxidyn(&["--help"])).starts_with("Usage: oxidyn <COMMAND>"));
+    assert_eq!(oxidyn(&["graph", path, "--strict"]).status.code(), Some(2));
+}
+
+#[test]
+fn test_validate() {
+    let path = model_file("validate.xmile", TANK);
+    let output = oxidyn(&["validate", path.to_str().unwrap()]);
+    assert!(stdout(&output).ends_with("validate.xmile: no problems found\n"));
+
+    let invalid = TANK
+        .replace("tank * rate", "tank * rtae")
+        .replace("<eqn>8</eqn>", "<eqn>8</eqn><units>liters</units>")
+        .replace(
+            "<eqn>tank * rtae</eqn>",
+            "<eqn>tank * rtae</eqn><units>meters</units>",
+        );
+    let path = model_file("invalid.xmile", &invalid);
+    let output = oxidyn(&["validate", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let lines: Vec<_> = std::str::from_utf8(&output.stdout)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    assert_eq!(lines[0], "error: 'drain' refers to unknown variable 'rtae'");
+    assert!(lines[1].starts_with("warning: "));
+    assert!(lines
+        .last()
+        .unwrap()
+        .ends_with("invalid.xmile: 1 error, 1 warning"));
+
+    let units = TANK
+        .replace("<eqn>8</eqn>", "<eqn>8</eqn><units>liters</units>")
+        .replace(
+            "<eqn>tank * rate</eqn>",
+            "<eqn>tank * rate</eqn><units>meters</units>",
+        );
+    let path = model_file("units.xmile", &units);
+    assert!(oxidyn(&["validate", path.to_str().unwrap()])
+        .status
+        .success());
+    let output = oxidyn(&["validate", "--strict", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_inspect() {
+    let path = model_file("inspect.xmile", TANK);
+    let output = stdout(&oxidyn(&["inspect", path.to_str().unwrap()]));
+    assert_eq!(
+        output,
+        "Model: tank\n\
+         Time: 0 to 2 time, dt 1, method euler\n\
+         \n\
+         Stocks (1):\n  \
+           tank\n    \
+             initial: 8\n    \
+             outflows: drain\n\
+         \n\
+         Flows (1):\n  \
+           drain\n    \
+             tank -> (sink)\n    \
+             = tank * rate\n    \
+             uses: tank, rate\n\
+         \n\
+         Auxiliaries (1):\n  \
+           rate\n    \
+             = 0.5\n\
+         \n\
+         Lookups (0):\n"
+    );
+}
+
+#[test]
+fn test_graph_output_file() {
+    let path = model_file("graph.xmile", TANK);
+    let output_path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("graph.dot");
+    let output = oxidyn(&[
+        "graph",
+        path.to_str().unwrap(),
+        "--output",
+        output_path.to_str().unwrap(),
+    ]);
+    assert_eq!(stdout(&output), "");
+    let dot = fs::read_to_string(output_path).unwrap();
+    assert!(dot.starts_with("digraph \"tank\" {\n"));
+    assert!(dot.contains("\"tank\" -> \"drain\" [penwidth=2, arrowhead=none];"));
+}
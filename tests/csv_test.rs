@@ -0,0 +1,105 @@
+use oxidyn::{CsvOptions, Flow, Layout, Model, SimulationResult, Stock};
+
+fn result() -> SimulationResult {
+    let mut res = SimulationResult::new();
+    res.time_series = vec![0., 0.5, 1.];
+    res.stock_values
+        .insert("tank".to_string(), vec![10., 9.5, 9.025]);
+    res.stock_values
+        .insert("drain".to_string(), vec![0., 0.5, 0.975]);
+    res.auxiliary_values
+        .insert("level, %".to_string(), vec![100., 95., 90.25]);
+    res
+}
+
+fn write(options: &CsvOptions) -> String {
+    let mut out = Vec::new();
+    result().write_csv(&mut out, options).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_wide_csv() {
+    assert_eq!(
+        write(&CsvOptions::csv()),
+        "time,tank,drain,\"level, %\"\n\
+         0,10,0,100\n\
+         0.5,9.5,0.5,95\n\
+         1,9.025,0.975,90.25\n"
+    );
+}
+
+#[test]
+fn test_column_order_precision_and_header() {
+    let options = CsvOptions::tsv()
+        .with_columns(&["tank", "level, %"])
+        .with_precision(1)
+        .with_header(false);
+    assert_eq!(
+        write(&options),
+        "0.0\t10.0\t100.0\n\
+         0.5\t9.5\t95.0\n\
+         1.0\t9.0\t90.2\n"
+    );
+}
+
+#[test]
+fn test_long_layout() {
+    let options = CsvOptions::csv()
+        .with_layout(Layout::Long)
+        .with_columns(&["tank", "drain"]);
+    assert_eq!(
+        write(&options),
+        "time,variable,value\n\
+         0,tank,10\n\
+         0,drain,0\n\
+         0.5,tank,9.5\n\
+         0.5,drain,0.5\n\
+         1,tank,9.025\n\
+         1,drain,0.975\n"
+    );
+}
+
+#[test]
+fn test_unknown_column() {
+    let mut out = Vec::new();
+    let error = result()
+        .write_csv(&mut out, &CsvOptions::csv().with_columns(&["missing"]))
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(error.to_string(), "unknown column 'missing'");
+}
+
+/// Writes the results of a draining tank, with or without its net flow.
+fn tank_csv(net_flows: bool) -> String {
+    let mut model = Model::new("tank");
+    model
+        .add_stock(Stock::new("tank", "Tank", 8., "liters"))
+        .add_flow(Flow::constant("fill", "Fill", 1., "liters/time").to_stock("tank"))
+        .add_flow(Flow::linear("drain", "Drain", 0.5, 0., "tank", "liters/time").from_stock("tank"))
+        .set_time_step(1.)
+        .set_record_net_flows(net_flows);
+
+    let mut out = Vec::new();
+    let res = model.simulate(2.);
+    res.write_csv(&mut out, &CsvOptions::csv()).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_net_flows() {
+    assert_eq!(
+        tank_csv(false),
+        "time,tank,fill,drain\n\
+         0,8,1,4\n\
+         1,5,1,2.5\n\
+         2,3.5,1,1.75\n"
+    );
+    assert_eq!(
+        tank_csv(true),
+        "time,tank,fill,drain,net(tank)\n\
+         0,8,1,4,-3\n\
+         1,5,1,2.5,-1.5\n\
+         2,3.5,1,1.75,-0.75\n"
+    );
+}
@@ -0,0 +1,293 @@
+use oxidyn::{Auxiliary, EvalError, Expr, Flow, Function, Integrator, Model, Stock, SystemState};
+
+/// Orders of 10 per time unit during the first time unit, received through
+/// `delay` with no material in transit at the start.
+fn shipping_model(delay: &str) -> Model {
+    let mut model = Model::new("shipping");
+
+    model
+        .add_stock(Stock::new("received", "Received", 0., "widgets"))
+        .add_auxiliary(
+            Auxiliary::expression(
+                "orders",
+                "Orders",
+                "IF TIME < 1 THEN 10 ELSE 0",
+                "widgets/time",
+            )
+            .unwrap(),
+        )
+        .add_flow(
+            Flow::expression("receiving", "Receiving", delay, "widgets/time")
+                .unwrap()
+                .to_stock("received"),
+        )
+        .set_time_step(0.25);
+    model
+}
+
+#[test]
+fn test_delay_conserves_material() {
+    let first = shipping_model("DELAY1(orders, 4, 0)").simulate(80.0);
+    let third = shipping_model("DELAY3(orders, 4, 0)").simulate(80.0);
+
+    let total = |res: &oxidyn::SimulationResult| *res.stock_values["received"].last().unwrap();
+    assert!((total(&first) - 10.).abs() < 1e-6, "{}", total(&first));
+    assert!((total(&third) - 10.).abs() < 1e-6, "{}", total(&third));
+
+    // A third-order delay releases less early on than a first-order one
+    let at_two = 8;
+    assert_eq!(first.time_series[at_two], 2.);
+    assert!(third.stock_values["received"][at_two] < first.stock_values["received"][at_two]);
+    assert!(third.stock_values["received"][at_two] > 0.);
+}
+
+#[test]
+fn test_delay_starts_in_equilibrium() {
+    let mut model = Model::new("model");
+
+    model
+        .add_stock(Stock::new("received", "Received", 0., "widgets"))
+        .add_flow(
+            Flow::expression("receiving", "Receiving", "DELAY3(5, 2)", "widgets/time")
+                .unwrap()
+                .to_stock("received"),
+        )
+        .set_time_step(0.5);
+
+    let res = model.simulate(4.0);
+    for (i, value) in res.stock_values["received"].iter().enumerate() {
+        assert!((value - 2.5 * i as f64).abs() < 1e-9, "{}", value);
+    }
+}
+
+#[test]
+fn test_smooth_converges_to_input() {
+    let mut model = Model::new("model");
+
+    model
+        .add_auxiliary(Auxiliary::expression("input", "Input", "STEP(1, 0)", "dmnl").unwrap())
+        .add_auxiliary(
+            Auxiliary::expression("smoothed", "Smoothed", "SMTH1(input, 2, 0)", "dmnl").unwrap(),
+        )
+        .set_integrator(Integrator::RungeKutta4)
+        .set_time_step(0.1);
+
+    let res = model.simulate(30.0);
+    let smoothed = &res.auxiliary_values["smoothed"];
+    assert_eq!(smoothed[0], 0.);
+    assert!(
+        (smoothed[20] - (1. - (-1f64).exp())).abs() < 1e-6,
+        "{}",
+        smoothed[20]
+    );
+    assert!((smoothed.last().unwrap() - 1.).abs() < 1e-6);
+}
+
+#[test]
+fn test_trend_of_exponential_growth() {
+    let mut model = Model::new("model");
+
+    model
+        .add_auxiliary(Auxiliary::expression("input", "Input", "EXP(0.1 * TIME)", "dmnl").unwrap())
+        .add_auxiliary(
+            Auxiliary::expression("growth", "Growth", "TREND(input, 1, 0.1)", "1/time").unwrap(),
+        )
+        .set_integrator(Integrator::RungeKutta4)
+        .set_time_step(0.1);
+
+    let res = model.simulate(10.0);
+    for value in &res.auxiliary_values["growth"] {
+        assert!((value - 0.1).abs() < 1e-6, "{}", value);
+    }
+}
+
+#[test]
+fn test_delayn_order_from_constant() {
+    let mut model = Model::new("model");
+
+    model
+        .add_stock(Stock::new("received", "Received", 0., "widgets"))
+        .add_auxiliary(Auxiliary::constant("stages", "Stages", 5., "dmnl"))
+        .add_flow(
+            Flow::expression(
+                "receiving",
+                "Receiving",
+                "DELAYN(2, 10, stages)",
+                "widgets/time",
+            )
+            .unwrap()
+            .to_stock("received"),
+        )
+        .set_record_internal(true)
+        .set_time_step(1.);
+
+    let res = model.simulate(3.0);
+    for i in 0..5 {
+        let id = format!("#receiving.delayn.0[{}]", i);
+        assert_eq!(res.stock_values[&id], vec![4.; 4], "{}", id);
+    }
+    assert_eq!(res.stock_values["received"], vec![0., 2., 4., 6.]);
+}
+
+#[test]
+#[should_panic(expected = "order must be a constant")]
+fn test_delayn_rejects_variable_order() {
+    let mut model = Model::new("model");
+
+    model
+        .add_stock(Stock::new("received", "Received", 0., "widgets"))
+        .add_flow(
+            Flow::expression(
+                "receiving",
+                "Receiving",
+                "DELAYN(2, 10, received)",
+                "widgets/time",
+            )
+            .unwrap()
+            .to_stock("received"),
+        );
+
+    model.simulate(1.0);
+}
+
+#[test]
+fn test_delay_arguments_checked_when_expanded() {
+    let error = |equation: &str| {
+        let mut model = shipping_model(equation);
+        model.add_auxiliary(Auxiliary::constant("stages", "Stages", 2.5, "dmnl"));
+        let errors = model.try_simulate(1.0).unwrap_err();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        errors[0].to_string()
+    };
+
+    for order in ["0", "2.4", "1001", "stages"] {
+        assert_eq!(
+            error(&format!("DELAYN(orders, 4, {})", order)),
+            format!(
+                "invalid argument to DELAYN: order must be a constant whole number from 1 to 1000, found '{}'",
+                order
+            )
+        );
+    }
+    assert_eq!(
+        error("DELAYN(orders, 0, 3)"),
+        "invalid argument to DELAYN: delay time must be positive, found 0"
+    );
+    assert_eq!(
+        error("DELAY1(orders, -2)"),
+        "invalid argument to DELAY1: delay time must be positive, found -2"
+    );
+    assert_eq!(
+        error("SMTH3(orders, 0)"),
+        "invalid argument to SMTH3: averaging time must be positive, found 0"
+    );
+}
+
+#[test]
+fn test_internal_stocks_hidden_by_default() {
+    let mut model = shipping_model("DELAY3(orders, 4)");
+    let res = model.simulate(2.0);
+    assert_eq!(
+        res.stock_values.keys().collect::<Vec<_>>(),
+        vec!["received"]
+    );
+
+    model.set_record_internal(true);
+    let res = model.simulate(2.0);
+    assert_eq!(res.stock_values.len(), 4);
+    assert_eq!(
+        res.stock_values["#receiving.delay3.0[2]"].len(),
+        res.time_series.len()
+    );
+}
+
+#[test]
+fn test_delay_continues_across_simulations() {
+    let mut whole = shipping_model("DELAY3(orders, 4, 0)");
+    let expected = *whole.simulate(10.0).stock_values["received"]
+        .last()
+        .unwrap();
+
+    let mut split = shipping_model("DELAY3(orders, 4, 0)");
+    split.simulate(5.0);
+    let actual = *split.simulate(5.0).stock_values["received"].last().unwrap();
+
+    assert!(
+        (actual - expected).abs() < 1e-9,
+        "{} != {}",
+        actual,
+        expected
+    );
+}
+
+#[test]
+fn test_stateful_function_outside_model() {
+    let expr = Expr::parse("DELAY1(1, 2)").unwrap();
+    assert_eq!(
+        expr.eval(&SystemState::new()),
+        Err(EvalError::StatefulFunction(Function::Delay1))
+    );
+}
+
+fn pipeline_model(equation: &str, time_step: f64) -> Model {
+    let mut model = Model::new("model");
+
+    model
+        .add_auxiliary(
+            Auxiliary::expression("input", "Input", "STEP(5, 2) + TIME", "dmnl").unwrap(),
+        )
+        .add_auxiliary(Auxiliary::expression("output", "Output", equation, "dmnl").unwrap())
+        .set_time_step(time_step);
+    model
+}
+
+#[test]
+fn test_fixed_delay_transports_input() {
+    let res = pipeline_model("DELAYFIXED(input, 3, -1)", 0.5).simulate(6.0);
+
+    let input = &res.auxiliary_values["input"];
+    let output = &res.auxiliary_values["output"];
+    for (i, time) in res.time_series.iter().enumerate() {
+        if *time < 3. {
+            assert_eq!(output[i], -1., "{}", time);
+        } else {
+            assert_eq!(output[i], input[i - 6], "{}", time);
+        }
+    }
+    // The step arrives whole, without smearing
+    assert_eq!(output[10], 7.);
+    assert_eq!(output[9], 1.5);
+}
+
+#[test]
+fn test_fixed_delay_with_uneven_time_step() {
+    let res = pipeline_model("PIPELINE(TIME, 1)", 0.4).simulate(4.0);
+
+    let output = &res.auxiliary_values["output"];
+    assert_eq!(output[0], 0.);
+    for (time, value) in res.time_series.iter().zip(output).skip(3) {
+        // The latest input at least one time unit old
+        assert!(*value <= time - 1. + 1e-9, "{} at {}", value, time);
+        assert!(*value > time - 1.4 + 1e-9, "{} at {}", value, time);
+    }
+}
+
+#[test]
+fn test_fixed_delay_continues_across_simulations() {
+    let mut whole = pipeline_model("DELAYFIXED(input, 3)", 0.5);
+    let expected = whole.simulate(8.0).auxiliary_values["output"].clone();
+
+    let mut split = pipeline_model("DELAYFIXED(input, 3)", 0.5);
+    let mut actual = split.simulate(4.0).auxiliary_values["output"].clone();
+    actual.extend(&split.simulate(4.0).auxiliary_values["output"][1..]);
+
+    // The initial value defaults to the input at the start
+    assert_eq!(expected[0], 0.);
+    assert_eq!(actual, expected);
+}
+
+#[test]
+#[should_panic(expected = "delay time must not be negative")]
+fn test_fixed_delay_rejects_negative_time() {
+    pipeline_model("DELAYFIXED(input, -1)", 1.).simulate(1.0);
+}
@@ -0,0 +1,189 @@
+use oxidyn::{BinaryOp, EvalError, Expr, Flow, Function, Model, Stock, SystemState};
+
+fn eval(equation: &str) -> f64 {
+    let mut state = SystemState::new();
+    state.stocks.insert(
+        "pop".to_string(),
+        Stock::new("pop", "Population", 50.0, "people"),
+    );
+    state.time = 3.0;
+    state.dt = 0.25;
+    Expr::parse(equation).unwrap().eval(&state).unwrap()
+}
+
+#[test]
+fn test_arithmetic_precedence() {
+    assert_eq!(eval("1 + 2 * 3"), 7.0);
+    assert_eq!(eval("(1 + 2) * 3"), 9.0);
+    assert_eq!(eval("10 - 4 - 3"), 3.0);
+    assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+    assert_eq!(eval("-2 ^ 2"), -4.0);
+    assert_eq!(eval("1.5e1 / .5"), 30.0);
+}
+
+#[test]
+fn test_variables_time_and_dt() {
+    assert_eq!(eval("pop * 2"), 100.0);
+    assert_eq!(eval("time + DT"), 3.25);
+    assert_eq!(eval("\"pop\" / 5"), 10.0);
+}
+
+#[test]
+fn test_comparisons_and_conditionals() {
+    assert_eq!(eval("pop > 10"), 1.0);
+    assert_eq!(eval("pop <= 10"), 0.0);
+    assert_eq!(eval("pop = 50 and not time < 3"), 1.0);
+    assert_eq!(eval("IF TIME >= 3 THEN 5 ELSE 0"), 5.0);
+    assert_eq!(eval("if pop < 10 or pop > 100 then 1 else 2"), 2.0);
+}
+
+#[test]
+fn test_functions() {
+    assert_eq!(eval("MIN(3, pop, 7)"), 3.0);
+    assert_eq!(eval("max(3, pop)"), 50.0);
+    assert_eq!(eval("ABS(-2)"), 2.0);
+    assert_eq!(eval("LN(EXP(2))"), 2.0);
+    assert_eq!(eval("SQRT(16)"), 4.0);
+    assert_eq!(eval("INT(-1.5)"), -2.0);
+    assert_eq!(eval("TRUNC(-1.5)"), -1.0);
+    assert_eq!(eval("TRUNC(1.5)"), 1.0);
+    assert_eq!(eval("MOD(7, 3)"), 1.0);
+}
+
+#[test]
+fn test_parse_structure() {
+    let expr = Expr::parse("a + MAX(b, 1)").unwrap();
+    assert_eq!(
+        expr,
+        Expr::Binary(
+            BinaryOp::Add,
+            Box::new(Expr::Variable("a".to_string())),
+            Box::new(Expr::Call(
+                Function::Max,
+                vec![Expr::Variable("b".to_string()), Expr::Number(1.0)]
+            )),
+        )
+    );
+    assert_eq!(expr.variables(), vec!["a", "b"]);
+
+    let expr = Expr::parse("strength[3] * 2").unwrap();
+    assert_eq!(expr.variables(), vec!["strength[3]"]);
+}
+
+#[test]
+fn test_parse_errors() {
+    assert!(Expr::parse("1 +").is_err());
+    assert!(Expr::parse("(1 + 2").is_err());
+    assert!(Expr::parse("IF a THEN b").is_err());
+    assert!(Expr::parse("table(1, 2)").is_err());
+    assert!(Expr::parse("LOOKUP(1, 2)").is_err());
+    assert!(Expr::parse("SQRT(1, 2)").is_err());
+    assert!(Expr::parse("a $ b").is_err());
+
+    let error = Expr::parse("1 + * 2").unwrap_err();
+    assert_eq!(error.position, 4);
+
+    for (equation, message) in [
+        ("MOD(1)", "MOD takes 2 arguments, found 1"),
+        ("MIN(1)", "MIN takes at least 2 arguments, found 1"),
+        ("PULSE(1, 2, 3, 4)", "PULSE takes 2 to 3 arguments, found 4"),
+        ("DELAYN(1, 2)", "DELAYN takes 3 to 4 arguments, found 2"),
+    ] {
+        assert_eq!(Expr::parse(equation).unwrap_err().message, message);
+    }
+}
+
+#[test]
+fn test_display_round_trip() {
+    for equation in [
+        "a - (b - c)",
+        "(a + b) * c",
+        "-(a + b) ^ 2",
+        "IF a > 0 AND NOT b THEN MIN(a, b) ELSE \"stock 1\"",
+        "2 ^ (3 ^ 2)",
+        "(2 ^ 3) ^ 2",
+    ] {
+        let expr = Expr::parse(equation).unwrap();
+        let printed = expr.to_string();
+        assert_eq!(Expr::parse(&printed).unwrap(), expr, "{}", printed);
+    }
+    assert_eq!(Expr::parse("(a * b) + c").unwrap().to_string(), "a * b + c");
+}
+
+#[test]
+fn test_unknown_variable() {
+    let expr = Expr::parse("missing + 1").unwrap();
+    assert_eq!(
+        expr.eval(&SystemState::new()),
+        Err(EvalError::UnknownVariable("missing".to_string()))
+    );
+
+    let flow = Flow::expression("f", "F", "missing + 1", "units").unwrap();
+    assert!(flow.calculate_rate(&SystemState::new()).is_nan());
+}
+
+#[test]
+fn test_logistic_growth_model() {
+    let mut model = Model::new("logistic");
+
+    model
+        .add_stock(Stock::new("population", "Population", 10., "people"))
+        .add_flow(
+            Flow::expression(
+                "births",
+                "Births",
+                "0.5 * population * (1 - population / 1000)",
+                "people/time",
+            )
+            .unwrap()
+            .to_stock("population"),
+        )
+        .set_time_step(0.1);
+
+    let res = model.simulate(40.0);
+    let final_value = *res.stock_values["population"].last().unwrap();
+    assert!((final_value - 1000.).abs() < 1., "{}", final_value);
+}
+
+#[test]
+fn test_flow_referencing_other_flows() {
+    let mut model = Model::new("model");
+
+    model
+        .add_stock(Stock::new("tank", "Tank", 0., "liters"))
+        .add_flow(
+            Flow::expression("overflow", "Overflow", "inflow / 2", "liters/time")
+                .unwrap()
+                .from_stock("tank"),
+        )
+        .add_flow(
+            Flow::expression(
+                "inflow",
+                "Inflow",
+                "IF TIME < 2 THEN 4 ELSE 0",
+                "liters/time",
+            )
+            .unwrap()
+            .to_stock("tank"),
+        )
+        .set_time_step(1.);
+
+    let res = model.simulate(4.0);
+    assert_eq!(res.stock_values["tank"], vec![0., 2., 4., 4., 4.]);
+}
+
+#[test]
+#[should_panic(expected = "unknown variable 'capacity'")]
+fn test_simulate_panics_on_unknown_variable() {
+    let mut model = Model::new("model");
+
+    model
+        .add_stock(Stock::new("population", "Population", 10., "people"))
+        .add_flow(
+            Flow::expression("births", "Births", "population / capacity", "people/time")
+                .unwrap()
+                .to_stock("population"),
+        );
+
+    model.simulate(1.0);
+}
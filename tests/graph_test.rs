@@ -0,0 +1,50 @@
+use oxidyn::{Auxiliary, Flow, Model, Stock};
+
+#[test]
+fn test_stock_and_flow_diagram() {
+    let mut model = Model::new("water \"tank\"");
+    model
+        .add_stock(Stock::new("tank", "Water\nTank", 10., "liters"))
+        .add_flow(Flow::constant("fill", "Fill", 1., "liters/time").to_stock("tank"))
+        .add_flow(
+            Flow::expression("drain", "", "tank * rate", "liters/time")
+                .unwrap()
+                .from_stock("tank"),
+        )
+        .add_auxiliary(Auxiliary::constant("rate", "Rate", 0.1, "1/time"));
+
+    assert_eq!(
+        model.to_dot(),
+        r##"digraph "water \"tank\"" {
+    rankdir=LR;
+    "tank" [shape=box, label="Water\nTank"];
+    "fill" [shape=circle, width=0.2, fixedsize=true, xlabel="Fill", label=""];
+    "drain" [shape=circle, width=0.2, fixedsize=true, xlabel="drain", label=""];
+    "rate" [shape=ellipse, label="Rate"];
+    "#source fill" [shape=egg, style=dashed, label="", width=0.3, height=0.2];
+    "#source fill" -> "fill" [penwidth=2, arrowhead=none];
+    "fill" -> "tank" [penwidth=2];
+    "#sink drain" [shape=egg, style=dashed, label="", width=0.3, height=0.2];
+    "tank" -> "drain" [penwidth=2, arrowhead=none];
+    "drain" -> "#sink drain" [penwidth=2];
+    "tank" -> "drain" [style=dashed, color=gray40];
+    "rate" -> "drain" [style=dashed, color=gray40];
+}
+"##
+    );
+}
+
+#[test]
+fn test_internal_stocks_are_not_drawn() {
+    let mut model = Model::new("smooth");
+    model
+        .add_stock(Stock::new("level", "Level", 1., ""))
+        .add_auxiliary(
+            Auxiliary::expression("smoothed", "Smoothed", "SMTH1(level, 2)", "").unwrap(),
+        );
+    model.simulate(1.);
+
+    let dot = model.to_dot();
+    assert!(!dot.contains("\"#"));
+    assert!(dot.contains("\"level\" -> \"smoothed\" [style=dashed, color=gray40];"));
+}
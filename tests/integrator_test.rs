@@ -0,0 +1,249 @@
+use oxidyn::{AdaptiveSettings, Flow, Integrator, Model, ModelError, Stock};
+
+/// Exponential decay of 100 units at rate 0.5, simulated for 4 time units.
+fn decay_model(integrator: Integrator, dt: f64) -> f64 {
+    let mut model = Model::new("decay");
+
+    model
+        .add_stock(Stock::new("amount", "Amount", 100., "units"))
+        .add_flow(
+            Flow::linear("decay", "Decay", 0.5, 0., "amount", "units/time").from_stock("amount"),
+        )
+        .set_time_step(dt)
+        .set_integrator(integrator);
+
+    let res = model.simulate(4.0);
+    *res.stock_values["amount"].last().unwrap()
+}
+
+#[test]
+fn test_default_integrator_is_euler() {
+    let model = Model::new("model");
+    assert_eq!(model.integrator, Integrator::Euler);
+}
+
+#[test]
+fn test_euler_matches_hand_computation() {
+    // y_{n+1} = y_n * (1 - 0.5 * 1.0)
+    let final_value = decay_model(Integrator::Euler, 1.0);
+    assert_eq!(final_value, 100. * 0.5_f64.powi(4));
+}
+
+#[test]
+fn test_higher_order_methods_are_more_accurate() {
+    let exact = 100. * (-0.5_f64 * 4.0).exp();
+
+    let euler = (decay_model(Integrator::Euler, 0.5) - exact).abs();
+    let heun = (decay_model(Integrator::Heun, 0.5) - exact).abs();
+    let midpoint = (decay_model(Integrator::Midpoint, 0.5) - exact).abs();
+    let rk4 = (decay_model(Integrator::RungeKutta4, 0.5) - exact).abs();
+
+    assert!(heun < euler, "heun {} vs euler {}", heun, euler);
+    assert!(midpoint < euler, "midpoint {} vs euler {}", midpoint, euler);
+    assert!(rk4 < heun && rk4 < midpoint);
+    assert!(rk4 < 1e-2, "rk4 error too large: {}", rk4);
+}
+
+#[test]
+fn test_rk4_constant_flows_are_exact() {
+    let mut model = Model::new("constant");
+
+    model
+        .add_stock(Stock::new("amount", "Amount", 0., "units"))
+        .add_flow(Flow::constant("input", "Input", 2., "units").to_stock("amount"))
+        .set_time_step(1.)
+        .set_integrator(Integrator::RungeKutta4);
+
+    let res = model.simulate(5.0);
+    for (value, expected) in res.stock_values["amount"]
+        .iter()
+        .zip([0., 2., 4., 6., 8., 10.])
+    {
+        assert!((value - expected).abs() < 1e-9, "{} != {}", value, expected);
+    }
+}
+
+#[test]
+fn test_dormand_prince_reports_on_output_grid() {
+    let mut model = Model::new("decay");
+
+    model
+        .add_stock(Stock::new("amount", "Amount", 100., "units"))
+        .add_flow(
+            Flow::linear("decay", "Decay", 0.5, 0., "amount", "units/time").from_stock("amount"),
+        )
+        .set_time_step(0.5)
+        .set_integrator(Integrator::DormandPrince(
+            AdaptiveSettings::new().with_tolerances(1e-9, 1e-9),
+        ));
+
+    let res = model.simulate(4.0);
+
+    assert_eq!(res.time_series.len(), 9);
+    for (time, value) in res.time_series.iter().zip(&res.stock_values["amount"]) {
+        let exact = 100. * (-0.5 * time).exp();
+        assert!(
+            (value - exact).abs() < 1e-5,
+            "t={}: {} != {}",
+            time,
+            value,
+            exact
+        );
+    }
+    assert_eq!(model.state.time, 4.0);
+    assert!(res.solver_stats.accepted_steps > 0);
+}
+
+#[test]
+fn test_dormand_prince_adapts_step_size() {
+    let run = |tolerance: f64| {
+        let mut model = Model::new("decay");
+        model
+            .add_stock(Stock::new("amount", "Amount", 100., "units"))
+            .add_flow(
+                Flow::linear("decay", "Decay", 5.0, 0., "amount", "units/time")
+                    .from_stock("amount"),
+            )
+            .set_time_step(1.0)
+            .set_integrator(Integrator::DormandPrince(
+                AdaptiveSettings::new()
+                    .with_tolerances(tolerance, tolerance)
+                    .with_max_step(1.0),
+            ));
+        model.simulate(10.0).solver_stats
+    };
+
+    let loose = run(1e-3);
+    let tight = run(1e-10);
+
+    assert!(tight.accepted_steps > loose.accepted_steps);
+    assert!(tight.evaluations > loose.evaluations);
+    // The initial step of 1.0 is far too large for a decay rate of 5.0
+    assert!(loose.rejected_steps > 0);
+}
+
+#[test]
+fn test_dormand_prince_respects_constraints() {
+    let mut model = Model::new("drain");
+
+    model
+        .add_stock(Stock::new("tank", "Tank", 10., "liters").with_min(0.0))
+        .add_flow(Flow::constant("drain", "Drain", 5., "liters/time").from_stock("tank"))
+        .set_time_step(1.)
+        .set_integrator(Integrator::DormandPrince(AdaptiveSettings::default()));
+
+    let res = model.simulate(4.0);
+
+    assert!(res.stock_values["tank"].iter().all(|v| *v >= 0.0));
+    assert_eq!(*res.stock_values["tank"].last().unwrap(), 0.0);
+}
+
+/// Two tanks draining into each other with a fast and a slow time constant.
+fn stiff_model(integrator: Integrator) -> Model {
+    let mut model = Model::new("stiff");
+
+    model
+        .add_stock(Stock::new("fast", "Fast", 1., "units"))
+        .add_stock(Stock::new("slow", "Slow", 0., "units"))
+        .add_flow(
+            Flow::linear("transfer", "Transfer", 50., 0., "fast", "units/time")
+                .from_stock("fast")
+                .to_stock("slow"),
+        )
+        .add_flow(Flow::linear("drain", "Drain", 0.1, 0., "slow", "units/time").from_stock("slow"))
+        .set_time_step(0.1)
+        .set_integrator(integrator);
+    model
+}
+
+#[test]
+fn test_euler_is_unstable_on_stiff_model() {
+    let res = stiff_model(Integrator::Euler).simulate(2.0);
+    assert!(res.stock_values["fast"].last().unwrap().abs() > 1.0);
+}
+
+#[test]
+fn test_backward_euler_is_stable_on_stiff_model() {
+    let res = stiff_model(Integrator::BackwardEuler).simulate(2.0);
+
+    let fast = &res.stock_values["fast"];
+    assert!(fast.windows(2).all(|w| w[1] <= w[0] && w[1] >= 0.0));
+
+    // Backward Euler on a linear system: fast_{n+1} = fast_n / (1 + 50 * dt)
+    assert!((fast[1] - 1. / 6.).abs() < 1e-9);
+
+    let slow = *res.stock_values["slow"].last().unwrap();
+    let exact = (-0.1_f64 * 2.0).exp();
+    assert!((slow - exact).abs() < 0.05, "{} vs {}", slow, exact);
+}
+
+#[test]
+fn test_bdf_is_more_accurate_than_backward_euler() {
+    let exact = 100. * (-0.5_f64 * 4.0).exp();
+
+    let backward_euler = (decay_model(Integrator::BackwardEuler, 0.1) - exact).abs();
+    let bdf = (decay_model(Integrator::Bdf { max_order: 4 }, 0.1) - exact).abs();
+
+    assert!(bdf < backward_euler / 10., "{} vs {}", bdf, backward_euler);
+
+    let res = stiff_model(Integrator::Bdf { max_order: 5 }).simulate(2.0);
+    assert!(res.stock_values["fast"].iter().all(|v| v.abs() <= 1.0));
+}
+
+#[test]
+fn test_dormand_prince_rejects_steps_that_produce_nan() {
+    let mut model = Model::new("drain");
+
+    // The outflow is not a number once a step overshoots below empty
+    model
+        .add_stock(Stock::new("tank", "Tank", 1., "liters").with_min(0.0))
+        .add_flow(
+            Flow::expression("drain", "Drain", "SQRT(tank)", "liters/time")
+                .unwrap()
+                .from_stock("tank"),
+        )
+        .set_time_step(1.)
+        .set_integrator(Integrator::DormandPrince(AdaptiveSettings::default()));
+
+    let res = model.try_simulate(4.0).unwrap();
+
+    let tank = &res.stock_values["tank"];
+    assert!(tank.iter().all(|v| v.is_finite()), "{:?}", tank);
+    assert!((tank[1] - 0.25).abs() < 1e-3);
+    assert!(tank[3].abs() < 1e-3);
+    assert!(res.solver_stats.rejected_steps > 0);
+}
+
+#[test]
+fn test_implicit_convergence_failure() {
+    // The outflow reverses at 0.5, so no value at the end of a step lands
+    // there once the tank gets close
+    let mut model = Model::new("switch");
+    model
+        .add_stock(Stock::new("tank", "Tank", 1., "units"))
+        .add_flow(
+            Flow::expression(
+                "drain",
+                "Drain",
+                "IF tank > 0.5 THEN 1 ELSE -1",
+                "units/time",
+            )
+            .unwrap()
+            .from_stock("tank"),
+        )
+        .set_time_step(0.1)
+        .set_integrator(Integrator::BackwardEuler);
+
+    let mut simulator = model.simulator().unwrap();
+    simulator.step_until(0.4).unwrap();
+    assert_eq!(simulator.into_result().solver_stats.convergence_failures, 0);
+
+    let mut simulator = model.simulator().unwrap();
+    assert_eq!(
+        simulator.step_until(1.),
+        Err(ModelError::ConvergenceFailure { time: 0.5 })
+    );
+    let stats = simulator.into_result().solver_stats;
+    assert!(stats.convergence_failures > 0);
+    assert_eq!(model.state.time, 0.5);
+}
@@ -0,0 +1,175 @@
+use oxidyn::{
+    Auxiliary, EvalError, Expr, Flow, Interpolation, LookupTable, Model, OutOfRange, Stock,
+    SystemState,
+};
+
+fn table() -> LookupTable {
+    LookupTable::new(vec![(2., 4.), (0., 0.), (1., 1.)])
+}
+
+#[test]
+fn test_points_are_sorted() {
+    assert_eq!(table().points, vec![(0., 0.), (1., 1.), (2., 4.)]);
+
+    let table = LookupTable::from_range(0., 1., &[1., 0.5, 0.]);
+    assert_eq!(table.points, vec![(0., 1.), (0.5, 0.5), (1., 0.)]);
+}
+
+#[test]
+fn test_linear_interpolation() {
+    let table = table();
+    assert_eq!(table.evaluate(0.5), Ok(0.5));
+    assert_eq!(table.evaluate(1.5), Ok(2.5));
+    assert_eq!(table.evaluate(2.), Ok(4.));
+}
+
+#[test]
+fn test_step_interpolation() {
+    let table = table().with_interpolation(Interpolation::Step);
+    assert_eq!(table.evaluate(0.99), Ok(0.));
+    assert_eq!(table.evaluate(1.), Ok(1.));
+    assert_eq!(table.evaluate(1.5), Ok(1.));
+    assert_eq!(table.evaluate(2.), Ok(4.));
+}
+
+#[test]
+fn test_cubic_interpolation() {
+    let table = table().with_interpolation(Interpolation::Cubic);
+
+    // Passes through the points
+    assert_eq!(table.evaluate(0.), Ok(0.));
+    assert_eq!(table.evaluate(1.), Ok(1.));
+    assert_eq!(table.evaluate(2.), Ok(4.));
+
+    // Natural spline through (0,0), (1,1), (2,4): curvature at x = 1 is 3
+    let value = table.evaluate(0.5).unwrap();
+    assert!((value - 0.3125).abs() < 1e-12, "{}", value);
+}
+
+#[test]
+fn test_cubic_interpolation_in_simulation() {
+    let table = LookupTable::from_range(0., 4., &[0., 3., 1., 4., 2.])
+        .with_interpolation(Interpolation::Cubic);
+    let mut model = Model::new("model");
+    model
+        .add_auxiliary(Auxiliary::time_series(
+            "curve",
+            "Curve",
+            table.clone(),
+            "dmnl",
+        ))
+        .add_auxiliary(Auxiliary::expression("named", "Named", "shape(TIME)", "dmnl").unwrap())
+        .add_lookup("shape", table.clone())
+        .set_time_step(0.25);
+
+    let res = model.simulate(4.0);
+    for (i, time) in res.time_series.iter().enumerate() {
+        let expected = table.evaluate(*time).unwrap();
+        assert_eq!(res.auxiliary_values["curve"][i], expected);
+        assert_eq!(res.auxiliary_values["named"][i], expected);
+    }
+}
+
+#[test]
+fn test_out_of_range() {
+    let clamp = table();
+    assert_eq!(clamp.evaluate(-1.), Ok(0.));
+    assert_eq!(clamp.evaluate(3.), Ok(4.));
+
+    let extrapolate = table().with_out_of_range(OutOfRange::Extrapolate);
+    assert_eq!(extrapolate.evaluate(-1.), Ok(-1.));
+    assert_eq!(extrapolate.evaluate(3.), Ok(7.));
+
+    let error = table().with_out_of_range(OutOfRange::Error);
+    assert_eq!(
+        error.evaluate(3.),
+        Err(EvalError::OutOfRange {
+            value: 3.,
+            min: 0.,
+            max: 2.
+        })
+    );
+}
+
+#[test]
+fn test_lookup_in_expression() {
+    let mut state = SystemState::new();
+    state.lookups.insert("effect".to_string(), table());
+    state.variables.insert("crowding".to_string(), 1.5);
+
+    let expr = Expr::parse("10 * effect(crowding)").unwrap();
+    assert_eq!(expr.eval(&state), Ok(25.));
+    assert_eq!(expr.variables(), vec!["crowding"]);
+
+    let expr = Expr::parse("LOOKUP(effect, crowding / 3)").unwrap();
+    assert_eq!(expr.eval(&state), Ok(0.5));
+    assert_eq!(Expr::parse(&expr.to_string()).unwrap(), expr);
+
+    let expr = Expr::parse("missing(1)").unwrap();
+    assert_eq!(
+        expr.eval(&state),
+        Err(EvalError::UnknownLookup("missing".to_string()))
+    );
+}
+
+#[test]
+fn test_crowding_effect_model() {
+    let mut model = Model::new("crowding");
+
+    // births per person fall from 0.5 to 0 as the population fills capacity
+    let effect = LookupTable::from_range(0., 1., &[1., 0.8, 0.5, 0.2, 0.]);
+
+    model
+        .add_stock(Stock::new("population", "Population", 10., "people"))
+        .add_auxiliary(
+            Auxiliary::expression("crowding", "Crowding", "population / 100", "dmnl").unwrap(),
+        )
+        .add_auxiliary(Auxiliary::lookup(
+            "birth_effect",
+            "Effect of Crowding on Births",
+            effect.clone(),
+            "crowding",
+            "dmnl",
+        ))
+        .add_lookup("effect", effect)
+        .add_flow(
+            Flow::expression(
+                "births",
+                "Births",
+                "0.5 * population * effect(crowding)",
+                "people/time",
+            )
+            .unwrap()
+            .to_stock("population"),
+        )
+        .set_time_step(0.25);
+
+    let res = model.simulate(50.0);
+
+    let final_value = *res.stock_values["population"].last().unwrap();
+    assert!((final_value - 100.).abs() < 1., "{}", final_value);
+    assert_eq!(res.auxiliary_values["birth_effect"][0], 0.92);
+}
+
+#[test]
+#[should_panic(expected = "outside the table range")]
+fn test_out_of_range_error_fails_simulation() {
+    let mut model = Model::new("model");
+
+    model
+        .add_stock(Stock::new("tank", "Tank", 0., "liters"))
+        .add_flow(
+            Flow::lookup(
+                "inflow",
+                "Inflow",
+                table().with_out_of_range(OutOfRange::Error),
+                "tank",
+                "liters/time",
+            )
+            .to_stock("tank"),
+        )
+        .add_flow(Flow::constant("feed", "Feed", 1., "liters/time").to_stock("tank"))
+        .set_time_step(1.);
+
+    model.simulate(5.0);
+}
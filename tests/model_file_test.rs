@@ -0,0 +1,303 @@
+#![cfg(any(feature = "json", feature = "toml", feature = "yaml"))]
+
+use oxidyn::{
+    AdaptiveSettings, Auxiliary, Expr, Flow, FlowFunction, Integrator, Interpolation, LookupTable,
+    Model, ModelFileError, OutOfRange, Stock,
+};
+
+fn population_model() -> Model {
+    let mut model = Model::new("population");
+    model
+        .add_stock(Stock::new("population", "Population", 100., "people").with_min(0.))
+        .add_stock(Stock::new("retirees", "Retirees", 10., "people"))
+        .add_flow(
+            Flow::expression(
+                "births",
+                "Births",
+                "population * birth_rate * crowding(population)",
+                "people/year",
+            )
+            .unwrap()
+            .to_stock("population"),
+        )
+        .add_flow(
+            Flow::linear(
+                "retiring",
+                "Retiring",
+                0.02,
+                0.,
+                "population",
+                "people/year",
+            )
+            .from_stock("population")
+            .to_stock("retirees"),
+        )
+        .add_auxiliary(Auxiliary::constant(
+            "birth_rate",
+            "Birth Rate",
+            0.1,
+            "1/year",
+        ))
+        .add_auxiliary(Auxiliary {
+            id: "pressure".to_string(),
+            name: "Pressure".to_string(),
+            function: FlowFunction::Lookup {
+                table: LookupTable::new(vec![(0., 0.), (100., 1.)])
+                    .with_out_of_range(OutOfRange::Extrapolate),
+                input: Expr::parse("population").unwrap(),
+            },
+            units: String::new(),
+        })
+        .add_lookup(
+            "crowding",
+            LookupTable::new(vec![(0., 1.), (500., 0.5), (1000., 0.)])
+                .with_interpolation(Interpolation::Step),
+        )
+        .set_time_units("year")
+        .set_time_step(0.25)
+        .set_integrator(Integrator::DormandPrince(
+            AdaptiveSettings::new().with_tolerances(1e-8, 1e-6),
+        ));
+    model.set_stop_time(20.).set_save_interval(0.5);
+    model
+}
+
+fn assert_same_model(loaded: &Model, model: &Model) {
+    assert_eq!(loaded.name, model.name);
+    assert_eq!(loaded.time_step, model.time_step);
+    assert_eq!(loaded.stop_time, model.stop_time);
+    assert_eq!(loaded.save_interval, model.save_interval);
+    assert_eq!(loaded.time_units, model.time_units);
+    assert_eq!(loaded.integrator, model.integrator);
+    assert_eq!(loaded.state.lookups, model.state.lookups);
+    assert_eq!(loaded.state.stocks["population"].min_value, Some(0.));
+    assert_eq!(loaded.flows["births"].units, "people/year");
+    assert!(matches!(
+        &loaded.flows["retiring"].rate_function,
+        FlowFunction::Linear { input_stock, .. } if input_stock == "population"
+    ));
+    assert!(matches!(
+        loaded.auxiliaries["birth_rate"].function,
+        FlowFunction::Constant(rate) if rate == 0.1
+    ));
+    assert!(matches!(
+        &loaded.auxiliaries["pressure"].function,
+        FlowFunction::Lookup { table, .. } if table.out_of_range == OutOfRange::Extrapolate
+    ));
+
+    let expected = model.clone().simulate(5.);
+    let actual = loaded.clone().simulate(5.);
+    assert_eq!(actual.stock_values, expected.stock_values);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json_round_trip() {
+    let model = population_model();
+    let json = model.to_json();
+    assert!(json.contains("\"version\": 1"));
+    assert_same_model(&Model::from_json(&json).unwrap(), &model);
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_toml_round_trip() {
+    let model = population_model();
+    assert_same_model(&Model::from_toml(&model.to_toml()).unwrap(), &model);
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_yaml_round_trip() {
+    let model = population_model();
+    assert_same_model(&Model::from_yaml(&model.to_yaml()).unwrap(), &model);
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_hand_written_toml() {
+    let model = Model::from_toml(
+        r#"
+version = 1
+name = "tank"
+
+[simulation]
+dt = 0.5
+method = "rk4"
+
+[[stocks]]
+id = "tank"
+initial = 10
+
+[[flows]]
+id = "drain"
+from = "tank"
+equation = "tank / 2"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(model.time_step, 0.5);
+    assert_eq!(model.time_units, "time");
+    assert_eq!(model.integrator, Integrator::RungeKutta4);
+    assert_eq!(model.state.stocks["tank"].name, "tank");
+    assert_eq!(model.flows["drain"].from_stock.as_deref(), Some("tank"));
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_unknown_stock_location() {
+    let error = Model::from_toml(
+        r#"
+version = 1
+name = "tank"
+
+[[stocks]]
+id = "tank"
+initial = 10
+
+[[flows]]
+id = "fill"
+to = "tank"
+equation = "1"
+
+[[flows]]
+id = "drain"
+from = "tnak"
+equation = "tank / 2"
+"#,
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        error,
+        ModelFileError::UnknownStock {
+            location: "flows[1].from".to_string(),
+            stock: "tnak".to_string(),
+            line: Some(16),
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "flows[1].from: unknown stock 'tnak' at line 16"
+    );
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_unknown_linear_input_stock() {
+    let error = Model::from_yaml(
+        "version: 1\n\
+         name: tank\n\
+         auxiliaries:\n\
+         - id: half\n  \
+           linear:\n    \
+             slope: 0.5\n    \
+             input_stock: tank\n",
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "auxiliaries[0].linear.input_stock: unknown stock 'tank' at line 7"
+    );
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_repeated_unknown_stock_location() {
+    let error = Model::from_yaml(
+        "version: 1\n\
+         name: tank\n\
+         auxiliaries:\n\
+         - id: half\n  \
+           linear:\n    \
+             slope: 0.5\n    \
+             input_stock: tank\n\
+         flows:\n\
+         - id: drain\n  \
+           linear:\n    \
+             slope: 0.1\n    \
+             input_stock: tank\n",
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "flows[0].linear.input_stock: unknown stock 'tank' at line 12"
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_unknown_variable_location() {
+    let error = Model::from_json(
+        r#"{
+  "version": 1,
+  "name": "tank",
+  "stocks": [{"id": "tank", "initial": 10}],
+  "flows": [
+    {"id": "fill", "to": "tank", "equation": "1"},
+    {
+      "id": "drain",
+      "from": "tank",
+      "equation": "tank / drain_time"
+    }
+  ]
+}"#,
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        error,
+        ModelFileError::UnknownVariable {
+            location: "flows[1].equation".to_string(),
+            variable: "drain_time".to_string(),
+            line: Some(10),
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "flows[1].equation: unknown variable 'drain_time' at line 10"
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_invalid_files() {
+    let error =
+        Model::from_json("{\"version\": 1,\n \"name\": \"m\",\n \"stocks\": 3}").unwrap_err();
+    assert!(matches!(
+        error,
+        ModelFileError::Syntax { line: Some(3), .. }
+    ));
+
+    assert_eq!(
+        Model::from_json(r#"{"version": 2, "name": "m"}"#).unwrap_err(),
+        ModelFileError::UnsupportedVersion(2)
+    );
+    assert!(matches!(
+        Model::from_json(r#"{"name": "m"}"#),
+        Err(ModelFileError::Syntax { .. })
+    ));
+
+    let error = Model::from_json(
+        r#"{"version": 1, "name": "m", "auxiliaries": [{"id": "a", "equation": "1 +"}]}"#,
+    )
+    .unwrap_err();
+    assert!(
+        matches!(&error, ModelFileError::Invalid { location, .. } if location == "auxiliaries[0].equation")
+    );
+
+    let error = Model::from_json(
+        r#"{"version": 1, "name": "m", "auxiliaries": [{"id": "a", "equation": "1"}, {"id": "a", "equation": "2"}]}"#,
+    )
+    .unwrap_err();
+    assert_eq!(error.to_string(), "auxiliaries[1]: duplicate ID 'a'");
+
+    let error = Model::from_json(
+        r#"{"version": 1, "name": "m", "lookups": [{"id": "t", "points": [[0, 1]]}, {"id": "t", "points": [[0, 2]]}]}"#,
+    )
+    .unwrap_err();
+    assert_eq!(error.to_string(), "lookups[1]: duplicate ID 't'");
+}
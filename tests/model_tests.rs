@@ -1,6 +1,4 @@
-use std::collections::HashMap;
-
-use oxidyn::{Flow, Model, Stock};
+use oxidyn::{AdaptiveSettings, Auxiliary, Flow, IndexMap, Integrator, Model, Stock};
 
 #[test]
 fn test_simple_constant() {
@@ -17,7 +15,7 @@ fn test_simple_constant() {
     let expected_res_vec = vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
     assert_eq!(res.time_series, expected_res_vec);
 
-    let mut expected_stock_values = HashMap::new();
+    let mut expected_stock_values = IndexMap::new();
     expected_stock_values.insert("amount".to_string(), expected_res_vec);
     assert_eq!(res.stock_values, expected_stock_values);
 }
@@ -113,3 +111,137 @@ fn test_stock_constraints() {
     let final_val = tank_values.last().unwrap();
     assert_eq!(*final_val, 15.0, "Final stock should be 15.0")
 }
+
+#[test]
+fn test_insertion_order() {
+    let mut model = Model::new("ordered_model");
+
+    let ids = ["zeta", "alpha", "mu", "beta", "omega", "gamma"];
+    for id in ids {
+        model.add_stock(Stock::new(id, id, 1., "units"));
+        model.add_flow(Flow::constant(&format!("{}_in", id), "", 1., "units").to_stock(id));
+        model.add_auxiliary(Auxiliary::constant(&format!("{}_aux", id), "", 2., "units"));
+    }
+    model
+        .add_auxiliary(Auxiliary::expression("total", "", "zeta + alpha + mu", "units").unwrap())
+        .set_time_step(1.);
+
+    assert_eq!(model.state.get_stock_names(), ids);
+    assert_eq!(model.stock_ids().collect::<Vec<_>>(), ids);
+    assert_eq!(model.flow_ids().next(), Some("zeta_in"));
+    assert_eq!(model.auxiliary_ids().last(), Some("total"));
+
+    let res = model.simulate(1.0);
+    let variables: Vec<_> = res.variable_ids().into_iter().cloned().collect();
+    let expected: Vec<_> = ids
+        .iter()
+        .map(|id| id.to_string())
+        .chain(ids.iter().map(|id| format!("{}_aux", id)))
+        .chain(["total".to_string()])
+        .chain(ids.iter().map(|id| format!("{}_in", id)))
+        .collect();
+    assert_eq!(variables, expected);
+    assert_eq!(
+        res.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>(),
+        expected
+    );
+    assert_eq!(res.auxiliary_values["total"].last(), Some(&6.));
+}
+
+#[test]
+fn test_exact_time_grid() {
+    let mut model = Model::new("grid_model");
+
+    model
+        .add_stock(Stock::new("amount", "Amount", 0., "units"))
+        .add_flow(Flow::constant("input", "Input", 1., "units").to_stock("amount"))
+        .set_time_step(0.1);
+
+    let res = model.simulate(1.0);
+    let expected: Vec<f64> = (0..=10).map(|k| k as f64 / 10.).collect();
+    assert_eq!(res.time_series, expected);
+    assert_eq!(res.solver_stats.accepted_steps, 10);
+
+    let res = model.simulate(0.3);
+    let expected: Vec<f64> = (10..=13).map(|k| k as f64 / 10.).collect();
+    assert_eq!(res.time_series, expected);
+    assert_eq!(model.state.time, 1.3);
+
+    let mut model = Model::new("grid_model");
+    model
+        .add_stock(Stock::new("amount", "Amount", 0., "units"))
+        .add_flow(Flow::constant("input", "Input", 1., "units").to_stock("amount"))
+        .set_start_time(1900.)
+        .set_stop_time(1901.)
+        .set_time_step(0.2);
+    let res = model.try_simulate_to_stop().unwrap();
+    assert_eq!(
+        res.time_series,
+        [1900., 1900.2, 1900.4, 1900.6, 1900.8, 1901.]
+    );
+}
+
+#[test]
+fn test_save_interval() {
+    let mut model = Model::new("saved_model");
+
+    model
+        .add_stock(Stock::new("amount", "Amount", 0., "units"))
+        .add_flow(Flow::constant("input", "Input", 2., "units").to_stock("amount"))
+        .set_time_step(0.25)
+        .set_save_interval(1.);
+
+    let res = model.simulate(2.5);
+    assert_eq!(res.time_series, [0., 1., 2., 2.5]);
+    assert_eq!(res.stock_values["amount"], [0., 2., 4., 5.]);
+    assert_eq!(res.solver_stats.accepted_steps, 10);
+
+    let res = model.simulate(1.5);
+    assert_eq!(res.time_series, [2.5, 3., 4.]);
+
+    model.set_integrator(Integrator::DormandPrince(AdaptiveSettings::new()));
+    let res = model.simulate(2.);
+    assert_eq!(res.time_series, [4., 5., 6.]);
+    assert!((res.stock_values["amount"][2] - 12.).abs() < 1e-9);
+}
+
+#[test]
+fn test_flow_values() {
+    let mut model = Model::new("population");
+
+    model
+        .add_stock(Stock::new("population", "Population", 100., "people"))
+        .add_flow(
+            Flow::linear("births", "Births", 0.1, 0., "population", "people/time")
+                .to_stock("population"),
+        )
+        .add_flow(
+            Flow::expression("deaths", "Deaths", "DELAY1(births, 2)", "people/time")
+                .unwrap()
+                .from_stock("population"),
+        )
+        .set_time_step(1.)
+        .set_record_net_flows(true);
+
+    let res = model.simulate(3.);
+    assert_eq!(
+        res.flow_values.keys().collect::<Vec<_>>(),
+        ["births", "deaths"]
+    );
+    assert_eq!(res.flow_values["births"][0], 10.);
+    assert_eq!(res.flow_values["deaths"][0], 10.);
+    assert_eq!(res.get("deaths"), Some(&res.flow_values["deaths"]));
+
+    let population = &res.stock_values["population"];
+    let net = &res.net_flow_values["net(population)"];
+    assert_eq!(res.get("net(population)"), Some(net));
+    for i in 0..res.time_series.len() {
+        let births = res.flow_values["births"][i];
+        let deaths = res.flow_values["deaths"][i];
+        assert_eq!(births, 0.1 * population[i]);
+        assert_eq!(net[i], births - deaths);
+        if i + 1 < population.len() {
+            assert_eq!(population[i + 1] - population[i], net[i]);
+        }
+    }
+}
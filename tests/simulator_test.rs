@@ -0,0 +1,226 @@
+use oxidyn::{
+    AdaptiveSettings, Auxiliary, Flow, Integrator, LookupTable, Model, ModelError, OutOfRange,
+    SimulationResult, Stock,
+};
+
+/// Inventory restocked through a third-order delay, with orders that
+/// depend on a delayed reading of the stock.
+fn inventory_model(integrator: Integrator) -> Model {
+    let mut model = Model::new("inventory");
+
+    model
+        .add_stock(Stock::new("inventory", "Inventory", 50., "widgets").with_min(0.))
+        .add_auxiliary(Auxiliary::constant("target", "Target", 100., "widgets"))
+        .add_auxiliary(
+            Auxiliary::expression(
+                "orders",
+                "Orders",
+                "MAX(0, target - DELAYFIXED(inventory, 1, 50)) / 2",
+                "widgets/week",
+            )
+            .unwrap(),
+        )
+        .add_flow(
+            Flow::expression(
+                "arrivals",
+                "Arrivals",
+                "DELAY3(orders, 3, 0)",
+                "widgets/week",
+            )
+            .unwrap()
+            .to_stock("inventory"),
+        )
+        .add_flow(
+            Flow::expression("sales", "Sales", "10 + STEP(5, 4)", "widgets/week")
+                .unwrap()
+                .from_stock("inventory"),
+        )
+        .set_time_step(0.25)
+        .set_integrator(integrator);
+    model
+}
+
+#[test]
+fn test_steps_match_simulate() {
+    for integrator in [
+        Integrator::Euler,
+        Integrator::RungeKutta4,
+        Integrator::Bdf { max_order: 3 },
+    ] {
+        let expected = inventory_model(integrator).simulate(10.);
+
+        let mut model = inventory_model(integrator);
+        let mut simulator = model.simulator().unwrap();
+        while simulator.time() < 6. {
+            simulator.step().unwrap();
+        }
+        simulator.step_until(10.).unwrap();
+        let result = simulator.into_result();
+
+        assert_eq!(result.time_series, expected.time_series);
+        assert_eq!(result.stock_values, expected.stock_values);
+        assert_eq!(result.auxiliary_values, expected.auxiliary_values);
+        assert_eq!(result.solver_stats, expected.solver_stats);
+        assert_eq!(model.state.time, 10.);
+    }
+}
+
+#[test]
+fn test_adaptive_steps_stay_within_tolerance() {
+    let integrator = Integrator::DormandPrince(AdaptiveSettings::new().with_tolerances(1e-9, 1e-9));
+    let expected = inventory_model(integrator).simulate(5.);
+
+    let mut model = inventory_model(integrator);
+    let mut simulator = model.simulator().unwrap();
+    for _ in 0..20 {
+        simulator.step().unwrap();
+    }
+    let result = simulator.into_result();
+
+    assert_eq!(result.time_series, expected.time_series);
+    for (actual, expected) in result.stock_values["inventory"]
+        .iter()
+        .zip(&expected.stock_values["inventory"])
+    {
+        assert!(
+            (actual - expected).abs() < 1e-6 * expected.abs().max(1.),
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_failed_adaptive_run_keeps_no_results() {
+    let mut model = Model::new("tank");
+    model
+        .add_stock(Stock::new("tank", "Tank", 0., "liters"))
+        .add_flow(Flow::constant("feed", "Feed", 1., "liters/time").to_stock("tank"))
+        .add_auxiliary(Auxiliary::lookup(
+            "level",
+            "Level",
+            LookupTable::new(vec![(0., 0.), (3., 1.)]).with_out_of_range(OutOfRange::Error),
+            "tank",
+            "dmnl",
+        ))
+        .set_time_step(0.25)
+        .set_integrator(Integrator::DormandPrince(AdaptiveSettings::new()));
+    let mut simulator = model.simulator().unwrap();
+
+    // The tank passes the end of the table after 3 time units
+    assert!(simulator.step_until(5.).is_err());
+    assert_eq!(simulator.time(), 0.);
+    assert_eq!(simulator.take_result().time_series, [0.]);
+
+    simulator.step_until(1.).unwrap();
+    let result = simulator.into_result();
+    assert_eq!(result.time_series, [0.25, 0.5, 0.75, 1.]);
+    assert_eq!(result.stock_values["tank"].len(), 4);
+}
+
+#[test]
+fn test_failed_bdf_step_taken_again() {
+    let mut model = Model::new("tank");
+    model
+        .add_stock(Stock::new("tank", "Tank", 0., "liters"))
+        .add_flow(Flow::constant("feed", "Feed", 1., "liters/time").to_stock("tank"))
+        .add_auxiliary(Auxiliary::lookup(
+            "level",
+            "Level",
+            LookupTable::new(vec![(0., 0.), (2.5, 1.)]).with_out_of_range(OutOfRange::Error),
+            "tank",
+            "dmnl",
+        ))
+        .set_time_step(1.)
+        .set_integrator(Integrator::Bdf { max_order: 3 });
+    let mut simulator = model.simulator().unwrap();
+
+    simulator.step().unwrap();
+    simulator.step().unwrap();
+    // The tank passes the end of the table on the third step, however
+    // often it is taken
+    for _ in 0..2 {
+        assert!(simulator.step().is_err());
+        assert_eq!(simulator.time(), 2.);
+        assert_eq!(simulator.current_state().get_stock_value("tank"), Some(2.));
+    }
+}
+
+#[test]
+fn test_changes_between_steps() {
+    let mut model = inventory_model(Integrator::Euler);
+    model.set_save_interval(1.);
+    let mut simulator = model.simulator().unwrap();
+
+    simulator.step_until(2.).unwrap();
+    let mut results = simulator.take_result();
+    assert_eq!(results.time_series, [0., 1., 2.]);
+    assert_eq!(
+        simulator.current_state().get_stock_value("inventory"),
+        results.stock_values["inventory"].last().copied()
+    );
+
+    // A decision for the next period
+    simulator.set_stock_value("inventory", 80.).unwrap();
+    simulator.set_parameter("target", 120.).unwrap();
+    assert_eq!(
+        simulator.current_state().get_stock_value("inventory"),
+        Some(80.)
+    );
+    assert_eq!(
+        simulator.set_parameter("traget", 1.),
+        Err(ModelError::UnknownId("traget".to_string()))
+    );
+    assert!(simulator.set_stock_value("orders", 1.).is_err());
+
+    for _ in 0..4 {
+        simulator.step().unwrap();
+    }
+    results.append(simulator.take_result());
+    assert_eq!(results.time_series, [0., 1., 2., 3.]);
+    assert_eq!(results.auxiliary_values["target"], [100., 100., 100., 120.]);
+    assert_eq!(results.solver_stats.accepted_steps, 12);
+
+    let errors = simulator
+        .update(|model| {
+            model.add_auxiliary(
+                Auxiliary::expression("bonus", "Bonus", "sales * margn", "widgets").unwrap(),
+            );
+        })
+        .unwrap_err();
+    assert_eq!(errors.len(), 1);
+    simulator
+        .update(|model| {
+            model.add_auxiliary(
+                Auxiliary::expression("margin", "Margin", "sales / 4", "widgets/week").unwrap(),
+            );
+        })
+        .unwrap();
+    simulator.step_until(4.).unwrap();
+    results.append(simulator.into_result());
+
+    assert_eq!(results.time_series, [0., 1., 2., 3., 4.]);
+    let margin = &results.auxiliary_values["margin"];
+    assert!(margin[..4].iter().all(|value| value.is_nan()));
+    assert_eq!(margin[4], 3.75);
+    assert!(!model.auxiliaries.contains_key("bonus"));
+    assert_eq!(model.state.time, 4.);
+}
+
+#[test]
+fn test_append_consecutive_runs() {
+    let mut model = inventory_model(Integrator::Euler);
+    let expected = model.clone().simulate(4.);
+
+    let mut result = SimulationResult::new();
+    result.append(model.simulate(1.5));
+    result.append(model.simulate(2.5));
+
+    assert_eq!(result.time_series, expected.time_series);
+    assert_eq!(result.stock_values, expected.stock_values);
+    assert_eq!(
+        result.solver_stats.accepted_steps,
+        expected.solver_stats.accepted_steps
+    );
+}
@@ -0,0 +1,103 @@
+use oxidyn::{Expr, Flow, Integrator, LookupTable, Model, Stock, SystemState};
+
+fn eval_at(equation: &str, time: f64, dt: f64) -> f64 {
+    let mut state = SystemState::new();
+    state.time = time;
+    state.dt = dt;
+    Expr::parse(equation).unwrap().eval(&state).unwrap()
+}
+
+#[test]
+fn test_step() {
+    assert_eq!(eval_at("STEP(5, 3)", 2.9, 0.1), 0.);
+    assert_eq!(eval_at("STEP(5, 3)", 3., 0.1), 5.);
+    // accumulated floating point time just short of the step time
+    assert_eq!(eval_at("STEP(5, 0.3)", 0.1 + 0.1 + 0.1 - 1e-12, 0.1), 5.);
+}
+
+#[test]
+fn test_ramp() {
+    assert_eq!(eval_at("RAMP(2, 1)", 0.5, 0.1), 0.);
+    assert_eq!(eval_at("RAMP(2, 1)", 3., 0.1), 4.);
+    assert_eq!(eval_at("RAMP(2, 1, 2)", 3., 0.1), 2.);
+}
+
+#[test]
+fn test_pulse() {
+    assert_eq!(eval_at("PULSE(10, 2)", 1.5, 0.5), 0.);
+    assert_eq!(eval_at("PULSE(10, 2)", 2., 0.5), 20.);
+    assert_eq!(eval_at("PULSE(10, 2)", 2.5, 0.5), 0.);
+    assert_eq!(eval_at("PULSE(10, 2)", 4., 0.5), 0.);
+
+    assert_eq!(eval_at("PULSE(10, 2, 3)", 5., 0.5), 20.);
+    assert_eq!(eval_at("PULSE(10, 2, 3)", 6., 0.5), 0.);
+    assert_eq!(eval_at("PULSE(10, 2, 3)", 8., 0.5), 20.);
+}
+
+#[test]
+fn test_waves() {
+    assert!((eval_at("SINWAVE(2, 4)", 1., 0.1) - 2.).abs() < 1e-12);
+    assert!((eval_at("COSWAVE(2, 4)", 2., 0.1) + 2.).abs() < 1e-12);
+}
+
+#[test]
+fn test_repeated_pulse_adds_volume() {
+    for integrator in [Integrator::Euler, Integrator::RungeKutta4] {
+        let mut model = Model::new("pulses");
+
+        model
+            .add_stock(Stock::new("inbox", "Inbox", 0., "items"))
+            .add_flow(
+                Flow::expression("arrivals", "Arrivals", "PULSE(3, 1, 2)", "items/time")
+                    .unwrap()
+                    .to_stock("inbox"),
+            )
+            .set_time_step(0.1)
+            .set_integrator(integrator);
+
+        // pulses at 1, 3, 5 and 7
+        let res = model.simulate(8.0);
+        let final_value = *res.stock_values["inbox"].last().unwrap();
+        assert!((final_value - 12.).abs() < 1e-9, "{}", final_value);
+    }
+}
+
+#[test]
+fn test_rehearsal_starts_at_time() {
+    let mut model = Model::new("working_memory");
+
+    model
+        .add_stock(Stock::new("strength", "Memory Strength", 0.5, "strength").with_max(1.0))
+        .add_flow(
+            Flow::linear("decay", "Decay", 0.1, 0., "strength", "strength/sec")
+                .from_stock("strength"),
+        )
+        .add_flow(
+            Flow::expression("rehearsal", "Rehearsal", "STEP(0.2, 3)", "strength/sec")
+                .unwrap()
+                .to_stock("strength"),
+        )
+        .set_time_step(0.1);
+
+    let res = model.simulate(6.0);
+    let strength = &res.stock_values["strength"];
+
+    // decays until rehearsal starts, then recovers
+    assert!(strength[30] < strength[0]);
+    assert!(strength[60] > strength[30]);
+}
+
+#[test]
+fn test_time_series_input() {
+    let mut model = Model::new("demand");
+
+    let orders = LookupTable::new(vec![(0., 0.), (2., 4.), (4., 4.)]);
+
+    model
+        .add_stock(Stock::new("backlog", "Backlog", 0., "orders"))
+        .add_flow(Flow::time_series("orders", "Orders", orders, "orders/day").to_stock("backlog"))
+        .set_time_step(1.);
+
+    let res = model.simulate(4.0);
+    assert_eq!(res.stock_values["backlog"], vec![0., 0., 2., 6., 10.]);
+}
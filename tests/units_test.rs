@@ -0,0 +1,247 @@
+use oxidyn::{Auxiliary, Flow, FlowFunction, Function, Model, ModelError, Stock, Unit, UnitError};
+
+fn unit(units: &str) -> Unit {
+    Unit::parse(units).unwrap()
+}
+
+#[test]
+fn test_parse_and_display() {
+    assert_eq!(unit("people/time").to_string(), "people/time");
+    assert_eq!(unit("m^2 / s^2").to_string(), "m^2/s^2");
+    assert_eq!(unit("1/(person*year)").to_string(), "1/(person*year)");
+    assert_eq!(unit("kg * m / s / s").to_string(), "kg*m/s^2");
+    assert_eq!(unit("s^-1").to_string(), "1/s");
+    assert_eq!(unit("widgets/widgets"), Unit::dimensionless());
+    assert!(unit("dmnl").is_dimensionless());
+    assert!(unit("Dmnl").is_dimensionless());
+    assert!(unit("Dimensionless").is_dimensionless());
+    assert_eq!(unit("people/Dmnl"), unit("people"));
+    assert!(unit("1").is_dimensionless());
+
+    assert!(Unit::parse("people/").is_err());
+    assert!(Unit::parse("m^x").is_err());
+    assert!(Unit::parse("(m").is_err());
+    assert_eq!(Unit::parse("3 people").unwrap_err().position, 0);
+    assert_eq!(Unit::parse("people years").unwrap_err().position, 7);
+}
+
+#[test]
+fn test_unit_algebra() {
+    assert_eq!(&unit("people/time") * &unit("time"), unit("people"));
+    assert_eq!(&unit("m") / &unit("s^2"), unit("m/s^2"));
+    assert_eq!(unit("m/s").powi(2), unit("m^2/s^2"));
+    assert_eq!(unit("m/s").powi(0), Unit::dimensionless());
+    assert_eq!(unit("m^2/s^2").sqrt(), Some(unit("m/s")));
+    assert_eq!(unit("m^3").sqrt(), None);
+    assert_eq!(unit("m^65536").checked_powi(65536), None);
+    assert_eq!(unit("m^2147483647").checked_mul(&unit("m")), None);
+    assert_eq!(unit("m").checked_div(&unit("1/m")), Some(unit("m^2")));
+}
+
+#[test]
+fn test_exponent_overflow() {
+    let error = Unit::parse("(m^99999)^99999").unwrap_err();
+    assert_eq!(error.message, "exponent too large");
+    assert!(Unit::parse("m^2147483647*m").is_err());
+
+    let mut model = Model::new("model");
+    model
+        .add_stock(Stock::new("tank", "Tank", 0., "(m^99999)^99999"))
+        .add_stock(Stock::new("level", "Level", 0., "m^2000000000"))
+        .add_auxiliary(Auxiliary::expression("squared", "Squared", "level * level", "").unwrap());
+
+    let errors = model.check_units();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(&errors[0], UnitError::Invalid { element, .. } if element == "tank"));
+}
+
+fn logistic_model() -> Model {
+    let mut model = Model::new("logistic");
+
+    model
+        .add_stock(Stock::new("population", "Population", 10., "people"))
+        .add_auxiliary(Auxiliary::constant("capacity", "Capacity", 100., "people"))
+        .add_auxiliary(Auxiliary::constant("fertility", "Fertility", 0.5, "1/year"))
+        .add_flow(
+            Flow::expression(
+                "births",
+                "Births",
+                "fertility * population * (1 - population / capacity)",
+                "people/year",
+            )
+            .unwrap()
+            .to_stock("population"),
+        )
+        .set_time_units("year");
+    model
+}
+
+#[test]
+fn test_consistent_model() {
+    assert_eq!(logistic_model().check_units(), vec![]);
+}
+
+#[test]
+fn test_flow_units_mismatch() {
+    let mut model = logistic_model();
+    model.add_stock(Stock::new("savings", "Savings", 0., "dollars"));
+    model
+        .flows
+        .get_mut("births")
+        .unwrap()
+        .to_stock
+        .replace("savings".to_string());
+
+    let errors = model.check_units();
+    assert_eq!(
+        errors,
+        vec![UnitError::FlowMismatch {
+            flow: "births".to_string(),
+            stock: "savings".to_string(),
+            expected: unit("dollars/year"),
+            found: unit("people/year"),
+        }]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "flow 'births' has units people/year but stock 'savings' needs dollars/year"
+    );
+
+    // Flows are checked against the model time unit
+    let mut model = logistic_model();
+    model.set_time_units("time");
+    assert!(matches!(
+        model.check_units().as_slice(),
+        [UnitError::FlowMismatch { .. }]
+    ));
+
+    // Rates per year convert to months, but not to weeks
+    model.set_time_units("month");
+    assert_eq!(model.check_units(), vec![]);
+    model.set_time_units("week");
+    assert!(matches!(
+        model.check_units().as_slice(),
+        [UnitError::AmbiguousConversion { .. }]
+    ));
+}
+
+#[test]
+fn test_expression_units() {
+    let mut model = logistic_model();
+    model
+        .add_auxiliary(
+            Auxiliary::expression("crowded", "Crowded", "population > capacity * 2", "dmnl")
+                .unwrap(),
+        )
+        .add_auxiliary(
+            Auxiliary::expression("mixed", "Mixed", "population + fertility", "people").unwrap(),
+        )
+        .add_auxiliary(Auxiliary::expression("growth", "Growth", "EXP(fertility)", "dmnl").unwrap())
+        .add_auxiliary(
+            Auxiliary::expression("per_year", "Per Year", "population / TIME", "people").unwrap(),
+        )
+        .add_auxiliary(
+            Auxiliary::expression("lagged", "Lagged", "SMTH1(population, 2)", "people").unwrap(),
+        );
+
+    assert_eq!(
+        model.check_units(),
+        vec![
+            UnitError::NotDimensionless {
+                element: "growth".to_string(),
+                function: Function::Exp,
+                found: unit("1/year"),
+            },
+            UnitError::Incompatible {
+                element: "mixed".to_string(),
+                left: unit("people"),
+                right: unit("1/year"),
+            },
+            UnitError::EquationMismatch {
+                element: "per_year".to_string(),
+                declared: unit("people"),
+                found: unit("people/year"),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_invalid_and_missing_units() {
+    let mut model = Model::new("model");
+    model
+        .add_stock(Stock::new("tank", "Tank", 0., "liters/"))
+        .add_flow(Flow::constant("fill", "Fill", 1., "").to_stock("tank"));
+
+    let errors = model.check_units();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(&errors[0], UnitError::Invalid { element, .. } if element == "tank"));
+}
+
+fn tank_model(time_units: &str) -> Model {
+    let mut model = Model::new("tank");
+
+    model
+        .add_stock(Stock::new("tank", "Tank", 0., "liters"))
+        .add_flow(Flow::constant("fill", "Fill", 60., "liters/hour").to_stock("tank"))
+        .add_flow(Flow::constant("leak", "Leak", 1., "liters/minute").from_stock("tank"))
+        .add_auxiliary(Auxiliary::expression("net", "Net", "fill - leak", "").unwrap())
+        .set_time_units(time_units)
+        .set_time_step(1.);
+    model
+}
+
+#[test]
+fn test_rate_conversion() {
+    let model = tank_model("minute");
+    assert_eq!(
+        model.rate_conversion(&model.flows["fill"]).unwrap(),
+        1. / 60.
+    );
+    assert_eq!(model.rate_conversion(&model.flows["leak"]).unwrap(), 1.);
+
+    let model = tank_model("hours");
+    assert_eq!(model.rate_conversion(&model.flows["leak"]).unwrap(), 60.);
+
+    // No conversion without a known model time unit
+    let model = tank_model("time");
+    assert_eq!(model.rate_conversion(&model.flows["fill"]).unwrap(), 1.);
+
+    let mut model = Model::new("model");
+    model.set_time_units("month");
+    let flow = Flow::constant("income", "Income", 1200., "dollars/year");
+    assert_eq!(model.rate_conversion(&flow).unwrap(), 1. / 12.);
+}
+
+#[test]
+fn test_simulation_converts_rates() {
+    let mut model = tank_model("minute");
+    let res = model.simulate(3.);
+
+    // 60 liters/hour in, 1 liter/minute out
+    assert_eq!(res.stock_values["tank"], vec![0.; 4]);
+    // Equations see rates in their declared units
+    assert_eq!(res.auxiliary_values["net"], vec![59.; 4]);
+
+    let mut model = tank_model("hour");
+    model.flows.get_mut("leak").unwrap().rate_function = FlowFunction::Constant(0.5);
+    let res = model.simulate(2.);
+    assert_eq!(res.stock_values["tank"], vec![0., 30., 60.]);
+}
+
+#[test]
+fn test_ambiguous_conversion() {
+    let mut model = tank_model("month");
+
+    let errors = model.validate();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+        errors[0],
+        ModelError::AmbiguousTimeUnits {
+            flow: "fill".to_string(),
+            units: "liters/hour".to_string(),
+            time_units: "month".to_string(),
+        }
+    );
+    assert!(model.try_simulate(1.).is_err());
+}
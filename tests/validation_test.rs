@@ -0,0 +1,255 @@
+use oxidyn::{
+    AdaptiveSettings, Auxiliary, EvalError, Flow, Integrator, LookupTable, Model, ModelError,
+    OutOfRange, Stock,
+};
+
+fn valid_model() -> Model {
+    let mut model = Model::new("model");
+
+    model
+        .add_stock(Stock::new("tank", "Tank", 10., "liters").with_min(0.))
+        .add_auxiliary(Auxiliary::constant("fraction", "Fraction", 0.1, "1/time"))
+        .add_flow(
+            Flow::expression("drain", "Drain", "tank * fraction", "liters/time")
+                .unwrap()
+                .from_stock("tank"),
+        )
+        .set_time_step(0.5);
+    model
+}
+
+#[test]
+fn test_valid_model() {
+    let mut model = valid_model();
+    assert_eq!(model.validate(), vec![]);
+
+    let res = model.try_simulate(1.0).unwrap();
+    assert_eq!(res.stock_values["tank"], vec![10., 9.5, 9.025]);
+}
+
+#[test]
+fn test_dangling_references() {
+    let mut model = valid_model();
+    model
+        .add_flow(Flow::constant("spill", "Spill", 1., "liters/time").from_stock("tnak"))
+        .add_flow(Flow::linear(
+            "leak",
+            "Leak",
+            0.1,
+            0.,
+            "bucket",
+            "liters/time",
+        ))
+        .add_auxiliary(
+            Auxiliary::expression("level", "Level", "tank / capacity + curve(tank)", "dmnl")
+                .unwrap(),
+        );
+
+    assert_eq!(
+        model.validate(),
+        vec![
+            ModelError::UnknownStock {
+                element: "leak".to_string(),
+                stock: "bucket".to_string(),
+            },
+            ModelError::UnknownVariable {
+                element: "level".to_string(),
+                variable: "capacity".to_string(),
+            },
+            ModelError::UnknownLookup {
+                element: "level".to_string(),
+                lookup: "curve".to_string(),
+            },
+            ModelError::UnknownStock {
+                element: "spill".to_string(),
+                stock: "tnak".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_duplicate_ids() {
+    let mut model = valid_model();
+    model
+        .add_stock(Stock::new("tank", "Tank", 5., "liters"))
+        .add_auxiliary(Auxiliary::constant("drain", "Drain", 1., "liters/time"))
+        .add_lookup("curve", LookupTable::new(vec![(0., 0.)]))
+        .add_lookup("curve", LookupTable::new(vec![(0., 1.)]))
+        .add_lookup("fraction", LookupTable::new(vec![(0., 1.)]));
+
+    assert_eq!(
+        model.validate(),
+        vec![
+            ModelError::DuplicateId("curve".to_string()),
+            ModelError::DuplicateId("drain".to_string()),
+            ModelError::DuplicateId("fraction".to_string()),
+            ModelError::DuplicateId("tank".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_invalid_settings() {
+    let mut model = valid_model();
+    model
+        .add_stock(Stock::new("low", "Low", -1., "liters").with_min(0.))
+        .add_stock(
+            Stock::new("empty", "Empty", 0., "liters")
+                .with_min(1.)
+                .with_max(0.),
+        )
+        .set_time_step(0.);
+
+    let errors = model.validate();
+    assert_eq!(
+        errors,
+        vec![
+            ModelError::InvalidTimeStep(0.),
+            ModelError::InvalidBounds {
+                stock: "empty".to_string(),
+                min: 1.,
+                max: 0.,
+            },
+            ModelError::InitialValueOutOfBounds {
+                stock: "low".to_string(),
+                value: -1.,
+            },
+        ]
+    );
+    assert_eq!(errors[0].to_string(), "time step must be positive, found 0");
+
+    model.set_time_step(f64::NAN);
+    assert!(matches!(
+        model.validate()[0],
+        ModelError::InvalidTimeStep(_)
+    ));
+}
+
+#[test]
+fn test_invalid_time_settings() {
+    let mut model = valid_model();
+    assert_eq!(
+        model.try_simulate_to_stop().unwrap_err(),
+        vec![ModelError::MissingStopTime]
+    );
+
+    model
+        .set_start_time(10.)
+        .set_stop_time(5.)
+        .set_time_step(0.25)
+        .set_save_interval(0.3);
+    let errors = model.validate();
+    assert_eq!(
+        errors,
+        vec![
+            ModelError::InvalidSaveInterval {
+                interval: 0.3,
+                time_step: 0.25,
+            },
+            ModelError::InvalidStopTime {
+                start: 10.,
+                stop: 5.,
+            },
+        ]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "save interval 0.3 is not a whole multiple of the time step 0.25"
+    );
+
+    model.set_stop_time(12.).set_save_interval(0.75);
+    assert_eq!(model.validate(), vec![]);
+    let res = model.try_simulate_to_stop().unwrap();
+    assert_eq!(res.time_series, [10., 10.75, 11.5, 12.]);
+}
+
+#[test]
+fn test_invalid_integrator_settings() {
+    let mut model = valid_model();
+    model.set_integrator(Integrator::DormandPrince(
+        AdaptiveSettings::new()
+            .with_tolerances(0., f64::NAN)
+            .with_min_step(1.)
+            .with_max_step(0.1),
+    ));
+
+    let errors = model.validate();
+    assert_eq!(errors.len(), 2);
+    assert!(matches!(
+        errors[0],
+        ModelError::InvalidTolerances { absolute, .. } if absolute == 0.
+    ));
+    assert_eq!(
+        errors[1],
+        ModelError::InvalidStepBounds { min: 1., max: 0.1 }
+    );
+    assert_eq!(
+        errors[1].to_string(),
+        "step bounds must be positive with minimum at most maximum, found 1 and 0.1"
+    );
+    assert_eq!(model.try_simulate(1.).unwrap_err().len(), 2);
+
+    model.set_integrator(Integrator::DormandPrince(
+        AdaptiveSettings::new().with_min_step(0.),
+    ));
+    assert!(matches!(
+        model.validate().as_slice(),
+        [ModelError::InvalidStepBounds { .. }]
+    ));
+
+    for max_order in [0, 6] {
+        model.set_integrator(Integrator::Bdf { max_order });
+        assert_eq!(
+            model.validate(),
+            vec![ModelError::InvalidBdfOrder(max_order)]
+        );
+    }
+    assert_eq!(
+        ModelError::InvalidBdfOrder(0).to_string(),
+        "BDF order must be from 1 to 5, found 0"
+    );
+}
+
+#[test]
+fn test_try_simulate_refuses_invalid_model() {
+    let mut model = valid_model();
+    model.add_flow(Flow::constant("fill", "Fill", 1., "liters/time").to_stock("tnak"));
+
+    let errors = model.try_simulate(1.0).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].to_string(),
+        "'fill' refers to unknown stock 'tnak'"
+    );
+    assert_eq!(model.state.time, 0.);
+}
+
+#[test]
+fn test_try_simulate_reports_evaluation_errors() {
+    let mut model = valid_model();
+    model
+        .add_lookup(
+            "curve",
+            LookupTable::new(vec![(9., 0.), (10., 1.)]).with_out_of_range(OutOfRange::Error),
+        )
+        .add_auxiliary(Auxiliary::expression("effect", "Effect", "curve(tank)", "dmnl").unwrap());
+
+    assert_eq!(model.validate(), vec![]);
+    let errors = model.try_simulate(5.0).unwrap_err();
+    assert!(matches!(
+        errors.as_slice(),
+        [ModelError::Evaluation(EvalError::OutOfRange { .. })]
+    ));
+    assert_eq!(model.state.time, 0.);
+
+    model.add_flow(
+        Flow::expression("bad", "Bad", "DELAYN(1, 2, tank)", "liters/time")
+            .unwrap()
+            .to_stock("tank"),
+    );
+    assert!(matches!(
+        model.validate().as_slice(),
+        [ModelError::Evaluation(EvalError::InvalidArgument { .. })]
+    ));
+}
@@ -0,0 +1,237 @@
+use oxidyn::{FlowFunction, Model, VensimError};
+
+const POPULATION: &str = r#"{UTF-8}
+Population= INTEG (
+	births-deaths,
+		capacity / 10)
+	~	people
+	~	The number of people.
+	|
+
+births=
+	Population*birth rate*effect of crowding(Population / capacity)
+	~	people/Year
+	~		|
+
+deaths=
+	Population/average lifetime
+	~	people/Year
+	~		|
+
+birth rate=
+	0.1
+	~	1/Year [0,1,0.01]
+	~		|
+
+average lifetime=
+	IF THEN ELSE(Time >= 5 :AND: capacity > 0, 40, 50)
+	~	Year
+	~		|
+
+capacity=
+	1000
+	~	people
+	~		|
+
+effect of crowding(
+	[(0,0)-(1,1)],(0,1),(0.5,0.5),(1,0))
+	~	Dmnl
+	~		|
+
+smoothed births=
+	SMOOTHI(births, 2, births)
+	~	people/Year
+	~		|
+
+crowding=
+	WITH LOOKUP(Population / capacity, ([(0,0)-(2,1)],(0,0),(2,1)))
+	~	Dmnl
+	~		|
+
+Savings=INTEG(income * 0.5, 0)
+	~	dollars
+	~		|
+
+income=
+	12
+	~	dollars/Year
+	~		|
+
+********************************************************
+	.Control
+********************************************************~
+		Simulation Control Parameters
+	|
+
+FINAL TIME  = 10
+	~	Year
+	~	The final time for the simulation.
+	|
+
+INITIAL TIME  = 1
+	~	Year
+	~	The initial time for the simulation.
+	|
+
+SAVEPER  =
+        TIME STEP
+	~	Year [0,?]
+	~	The frequency with which output is stored.
+	|
+
+TIME STEP  = 0.25
+	~	Year [0,?]
+	~	The time step for the simulation.
+	|
+
+\\\---/// Sketch information - do not modify anything except names
+V300  Do not put anything below this section - it will be ignored
+*View 1
+$192-192-192,0,Times New Roman|12||0-0-0|0-0-0|0-0-255|-1--1--1|-1--1--1|96,96,100,0
+"#;
+
+fn expression(function: &FlowFunction) -> String {
+    match function {
+        FlowFunction::Expression(expr) => expr.to_string(),
+        _ => panic!("Expected expression, found {:?}", function),
+    }
+}
+
+#[test]
+fn test_import_population_model() {
+    let model = Model::from_vensim(POPULATION).unwrap();
+
+    assert_eq!(model.start_time, 1.);
+    assert_eq!(model.state.time, 1.);
+    assert_eq!(model.stop_time, Some(10.));
+    assert_eq!(model.time_step, 0.25);
+    assert_eq!(model.save_interval, Some(0.25));
+    assert_eq!(model.time_units, "Year");
+
+    let population = &model.state.stocks["population"];
+    assert_eq!(population.name, "Population");
+    assert_eq!(population.units, "people");
+    assert_eq!(population.initial_value, 100.);
+
+    let births = &model.flows["births"];
+    assert_eq!(births.to_stock.as_deref(), Some("population"));
+    assert_eq!(births.units, "people/Year");
+    assert_eq!(
+        expression(&births.rate_function),
+        "population * birth_rate * LOOKUP(effect_of_crowding, population / capacity)"
+    );
+    assert_eq!(
+        model.flows["deaths"].from_stock.as_deref(),
+        Some("population")
+    );
+
+    // Units without the range
+    assert_eq!(model.auxiliaries["birth_rate"].units, "1/Year");
+    assert_eq!(
+        expression(&model.auxiliaries["average_lifetime"].function),
+        "IF TIME >= 5 AND capacity > 0 THEN 40 ELSE 50"
+    );
+    assert_eq!(
+        expression(&model.auxiliaries["smoothed_births"].function),
+        "SMTH1(births, 2, births)"
+    );
+    match &model.auxiliaries["crowding"].function {
+        FlowFunction::Lookup { table, input } => {
+            assert_eq!(table.points, vec![(0., 0.), (2., 1.)]);
+            assert_eq!(input.to_string(), "population / capacity");
+        }
+        other => panic!("Expected lookup, found {:?}", other),
+    }
+    assert_eq!(
+        model.state.lookups["effect_of_crowding"].points,
+        vec![(0., 1.), (0.5, 0.5), (1., 0.)]
+    );
+
+    // A rate that is not a sum of variables becomes its own flow
+    let net = &model.flows["savings_net_flow"];
+    assert_eq!(net.to_stock.as_deref(), Some("savings"));
+    assert_eq!(expression(&net.rate_function), "income * 0.5");
+    assert!(model.auxiliaries.contains_key("income"));
+}
+
+#[test]
+fn test_imported_model_simulates() {
+    let mut model = Model::from_vensim(POPULATION).unwrap();
+    assert_eq!(model.validate(), vec![]);
+    assert_eq!(model.check_units(), vec![]);
+
+    let res = model.try_simulate(9.).unwrap();
+    assert_eq!(res.time_series.len(), 37);
+    assert_eq!(res.stock_values["savings"][4], 6.);
+    let births = 100. * 0.1 * 0.9;
+    let deaths = 100. / 50.;
+    assert_eq!(
+        res.stock_values["population"][1],
+        100. + (births - deaths) * 0.25
+    );
+}
+
+fn equations(text: &str) -> Result<Model, VensimError> {
+    Model::from_vensim(&format!("{{UTF-8}}\n{}\n~ ~ |\n", text))
+}
+
+#[test]
+fn test_function_translation() {
+    let model = equations(
+        "a = DELAY N(b, 3, 0, 4) + DELAY FIXED(b, 2, 1) + PULSE(2, 1)\n~~|\n\
+         b = MODULO(INTEGER(Time), 2) + LOG(8, 2)",
+    )
+    .unwrap();
+
+    assert_eq!(
+        expression(&model.auxiliaries["a"].function),
+        "DELAYN(b, 3, 4, 0) + DELAYFIXED(b, 2, 1) + (STEP(1, 2) - STEP(1, 2 + 1))"
+    );
+    assert_eq!(
+        expression(&model.auxiliaries["b"].function),
+        "MOD(TRUNC(TIME), 2) + LN(8) / LN(2)"
+    );
+
+    // Vensim rounds toward zero, where INT rounds down
+    let mut model = equations("c = INTEGER(-2.5)").unwrap();
+    assert_eq!(model.simulate(1.).auxiliary_values["c"], [-2., -2.]);
+}
+
+#[test]
+fn test_unsupported_constructs() {
+    let unsupported = |text: &str| match equations(text) {
+        Err(VensimError::Unsupported(message)) => message,
+        other => panic!("Expected unsupported error, found {:?}", other),
+    };
+
+    assert_eq!(
+        unsupported("a = RANDOM NORMAL(0, 1, 0, 1, 0)"),
+        "function RANDOM NORMAL in 'a'"
+    );
+    assert_eq!(
+        unsupported("a = MAX(0, GET XLS DATA('f.xlsx', 'a', '1', 'B2'))"),
+        "function GET XLS DATA in 'a'"
+    );
+    assert_eq!(unsupported("pop[region] = 1"), "subscripts ('pop')");
+    assert_eq!(unsupported("a := 1"), "data equations ('a')");
+}
+
+#[test]
+fn test_invalid_models() {
+    assert!(matches!(
+        equations("a = 1 +"),
+        Err(VensimError::Equation { element, .. }) if element == "a"
+    ));
+    assert!(matches!(
+        equations("a = IF THEN ELSE(1, 2)"),
+        Err(VensimError::Invalid(_))
+    ));
+    assert_eq!(
+        equations("s = INTEG(0, missing)").unwrap_err().to_string(),
+        "invalid Vensim model: cannot calculate the initial value of 's': unknown variable 'missing'"
+    );
+    assert!(matches!(
+        equations("TIME STEP = 0"),
+        Err(VensimError::Invalid(_))
+    ));
+}
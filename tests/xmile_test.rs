@@ -0,0 +1,311 @@
+use oxidyn::{
+    Auxiliary, Flow, FlowFunction, Integrator, Interpolation, LookupTable, Model, Stock, XmileError,
+};
+
+const POPULATION: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<xmile version="1.0" xmlns="http://docs.oasis-open.org/xmile/ns/XMILE/v1.0">
+    <header>
+        <name>Population &amp; Crowding</name>
+        <vendor>Test</vendor>
+    </header>
+    <sim_specs method="RK4" time_units="Years">
+        <start>1</start>
+        <stop>11</stop>
+        <dt reciprocal="true">4</dt>
+    </sim_specs>
+    <model>
+        <variables>
+            <stock name="Population">
+                <eqn>capacity / 10</eqn>
+                <inflow>births</inflow>
+                <outflow>deaths</outflow>
+                <non_negative/>
+                <units>people</units>
+            </stock>
+            <flow name="births">
+                <eqn>Population * birth_rate * "effect of\ncrowding"</eqn>
+                <non_negative/>
+                <units>people/year</units>
+            </flow>
+            <flow name="deaths">
+                <eqn>Population / lifetime(TIME) {average lifetime}</eqn>
+                <units>people/year</units>
+            </flow>
+            <aux name="Birth Rate">
+                <eqn>0.1</eqn>
+                <units>1/year</units>
+            </aux>
+            <aux name="capacity">
+                <eqn>1000</eqn>
+            </aux>
+            <aux name="effect of\ncrowding">
+                <eqn>Population / capacity</eqn>
+                <gf type="extrapolate">
+                    <xscale min="0" max="1"/>
+                    <ypts>1, 0.5, 0</ypts>
+                </gf>
+            </aux>
+            <gf name="lifetime" type="discrete">
+                <xpts>0;10</xpts>
+                <ypts sep=";">50;80</ypts>
+            </gf>
+        </variables>
+        <views>
+            <view><stock name="Population" x="10" y="10"/></view>
+        </views>
+    </model>
+</xmile>
+"#;
+
+#[test]
+fn test_import_population_model() {
+    let model = Model::from_xmile(POPULATION).unwrap();
+
+    assert_eq!(model.name, "Population & Crowding");
+    assert_eq!(model.start_time, 1.);
+    assert_eq!(model.state.time, 1.);
+    assert_eq!(model.stop_time, Some(11.));
+    assert_eq!(model.time_step, 0.25);
+    assert_eq!(model.save_interval, None);
+    assert_eq!(model.time_units, "Years");
+    assert_eq!(model.integrator, Integrator::RungeKutta4);
+
+    let population = &model.state.stocks["population"];
+    assert_eq!(population.name, "Population");
+    assert_eq!(population.initial_value, 100.);
+    assert_eq!(population.current_value, 100.);
+    assert_eq!(population.min_value, Some(0.));
+    assert_eq!(population.units, "people");
+
+    let births = &model.flows["births"];
+    assert_eq!(births.to_stock.as_deref(), Some("population"));
+    assert_eq!(births.from_stock, None);
+    assert_eq!(births.units, "people/year");
+    match &births.rate_function {
+        FlowFunction::Expression(expr) => assert_eq!(
+            expr.to_string(),
+            "MAX(0, population * birth_rate * effect_of_crowding)"
+        ),
+        _ => panic!("Expected expression"),
+    }
+    assert_eq!(
+        model.flows["deaths"].from_stock.as_deref(),
+        Some("population")
+    );
+
+    assert!(matches!(
+        model.auxiliaries["birth_rate"].function,
+        FlowFunction::Constant(rate) if rate == 0.1
+    ));
+    let effect = &model.auxiliaries["effect_of_crowding"];
+    assert_eq!(effect.name, "effect of crowding");
+    match &effect.function {
+        FlowFunction::Lookup { table, .. } => {
+            assert_eq!(table.points, vec![(0., 1.), (0.5, 0.5), (1., 0.)]);
+        }
+        _ => panic!("Expected lookup"),
+    }
+
+    let lifetime = &model.state.lookups["lifetime"];
+    assert_eq!(lifetime.points, vec![(0., 50.), (10., 80.)]);
+    assert_eq!(lifetime.interpolation, Interpolation::Step);
+}
+
+#[test]
+fn test_imported_model_simulates() {
+    let mut model = Model::from_xmile(POPULATION).unwrap();
+    assert_eq!(model.validate(), vec![]);
+    assert_eq!(model.check_units(), vec![]);
+
+    let res = model.try_simulate(10.).unwrap();
+    assert_eq!(res.time_series.len(), 41);
+    // About one Euler step of births minus deaths
+    let births = 100. * 0.1 * 0.9;
+    let deaths = 100. / 50.;
+    let expected = 100. + (births - deaths) * 0.25;
+    let actual = res.stock_values["population"][1];
+    assert!((actual - expected).abs() < 0.05, "{}", actual);
+}
+
+fn document(variables: &str) -> String {
+    format!(
+        "<xmile><model><variables>{}</variables></model></xmile>",
+        variables
+    )
+}
+
+#[test]
+fn test_builtins_are_translated() {
+    let model = Model::from_xmile(&document(
+        r#"<aux name="start"><eqn>STARTTIME + PI * 0</eqn></aux>
+           <aux name="late"><eqn>DELAY(start, 2, 0)</eqn></aux>"#,
+    ))
+    .unwrap();
+
+    match &model.auxiliaries["late"].function {
+        FlowFunction::Expression(expr) => {
+            assert_eq!(expr.to_string(), "DELAYFIXED(start, 2, 0)")
+        }
+        _ => panic!("Expected expression"),
+    }
+}
+
+#[test]
+fn test_unsupported_constructs() {
+    let unsupported = |variables: &str| match Model::from_xmile(&document(variables)) {
+        Err(XmileError::Unsupported(message)) => message,
+        other => panic!("Expected unsupported error, found {:?}", other),
+    };
+
+    assert_eq!(
+        unsupported(r#"<aux name="a"><eqn>SMTHN(1, 2, 3)</eqn></aux>"#),
+        "function SMTHN in 'a'"
+    );
+    assert_eq!(
+        unsupported(r#"<aux name="a"><dimensions><dim name="d"/></dimensions><eqn>1</eqn></aux>"#),
+        "arrays ('a')"
+    );
+    assert_eq!(
+        unsupported(r#"<stock name="s"><eqn>0</eqn><conveyor/></stock>"#),
+        "conveyors ('s')"
+    );
+    assert_eq!(unsupported(r#"<module name="m"/>"#), "modules");
+}
+
+#[test]
+fn test_invalid_documents() {
+    let error = Model::from_xmile("<xmile><model></xmile>").unwrap_err();
+    assert!(matches!(&error, XmileError::Xml(error) if error.position == 16));
+    assert_eq!(
+        error.to_string(),
+        "invalid XML: expected '</model>' at position 16"
+    );
+
+    assert!(matches!(
+        Model::from_xmile("<model/>"),
+        Err(XmileError::Invalid(_))
+    ));
+    assert!(matches!(
+        Model::from_xmile(&document(r#"<aux name="a"><eqn>1 +</eqn></aux>"#)),
+        Err(XmileError::Equation { element, .. }) if element == "a"
+    ));
+    assert_eq!(
+        Model::from_xmile(&document(r#"<stock name="s"><eqn>missing</eqn></stock>"#))
+            .unwrap_err()
+            .to_string(),
+        "invalid XMILE: cannot calculate the initial value of 's': unknown variable 'missing'"
+    );
+    assert!(matches!(
+        Model::from_xmile(&document(
+            r#"<stock name="s"><eqn>0</eqn><inflow>nowhere</inflow></stock>"#
+        )),
+        Err(XmileError::Invalid(_))
+    ));
+}
+
+#[test]
+fn test_export_round_trip() {
+    let mut model = Model::new("Tanks <1 & 2>");
+    model
+        .add_stock(
+            Stock::new("tank", "Tank", 50., "liters")
+                .with_min(0.)
+                .with_max(100.),
+        )
+        .add_stock(Stock::new("drain", "Storm Drain", 0., "liters"))
+        .add_flow(
+            Flow::linear("leak", "Leak", 0.1, 0., "tank", "liters/minute")
+                .from_stock("tank")
+                .to_stock("drain"),
+        )
+        .add_flow(
+            Flow::expression(
+                "fill",
+                "Fill",
+                "IF TIME < 5 THEN rain(TIME) ELSE 0",
+                "liters/minute",
+            )
+            .unwrap()
+            .to_stock("tank"),
+        )
+        .add_auxiliary(
+            Auxiliary::expression("late", "Late Leak", "DELAYFIXED(leak, 2, 0)", "").unwrap(),
+        )
+        .add_lookup("rain", LookupTable::new(vec![(0., 1.), (10., 3.)]))
+        .set_time_units("minute")
+        .set_time_step(0.5)
+        .set_save_interval(1.)
+        .set_integrator(Integrator::RungeKutta4);
+
+    let document = model.to_xmile();
+    assert!(document.contains("<name>Tanks &lt;1 &amp; 2&gt;</name>"));
+    assert!(document.contains(r#"<range min="0" max="100"/>"#));
+    assert!(document.contains(r#"<stock name="drain">"#));
+    assert!(document.contains("<eqn>DELAY(leak, 2, 0)</eqn>"));
+
+    let imported = Model::from_xmile(&document).unwrap();
+    assert_eq!(imported.name, model.name);
+    assert_eq!(imported.time_step, 0.5);
+    assert_eq!(imported.stop_time, Some(50.));
+    assert_eq!(imported.save_interval, Some(1.));
+    assert_eq!(imported.integrator, Integrator::RungeKutta4);
+    assert_eq!(imported.time_units, "minute");
+    assert_eq!(imported.state.stocks["tank"].name, "Tank");
+    assert_eq!(imported.state.stocks["tank"].min_value, Some(0.));
+    assert_eq!(imported.flows["leak"].to_stock.as_deref(), Some("drain"));
+    assert_eq!(
+        imported.state.lookups["rain"].points,
+        vec![(0., 1.), (10., 3.)]
+    );
+
+    let expected = model.simulate(10.);
+    let actual = imported.clone().simulate(10.);
+    for id in ["tank", "drain"] {
+        assert_eq!(actual.stock_values[id], expected.stock_values[id]);
+    }
+    assert_eq!(
+        actual.auxiliary_values["late"],
+        expected.auxiliary_values["late"]
+    );
+}
+
+#[test]
+fn test_export_writes_only_xmile_builtins() {
+    let mut model = Model::new("builtins");
+    model
+        .add_stock(Stock::new("level", "Level", 0., ""))
+        .add_flow(
+            Flow::expression(
+                "change",
+                "Change",
+                "TRUNC(wave) + ROUND(-wave) + MOD(TIME, -3) + MAX(wave, 0, TIME - 4)",
+                "",
+            )
+            .unwrap()
+            .to_stock("level"),
+        )
+        .add_auxiliary(
+            Auxiliary::expression("wave", "Wave", "SINWAVE(2.5, 4) - COSWAVE(1, 3)", "").unwrap(),
+        )
+        .set_time_step(0.25);
+
+    let document = model.to_xmile();
+    for function in ["TRUNC", "ROUND", "MOD", "SINWAVE", "COSWAVE"] {
+        assert!(!document.contains(function), "{}", document);
+    }
+    assert!(document.contains("MAX(MAX(wave, 0), TIME - 4)"));
+
+    let expected = model.simulate(10.);
+    let actual = Model::from_xmile(&document).unwrap().simulate(10.);
+    for (actual, expected) in actual.stock_values["level"]
+        .iter()
+        .zip(&expected.stock_values["level"])
+    {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+}